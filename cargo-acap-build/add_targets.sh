@@ -47,3 +47,22 @@ sed -i -e 's/supported_targets! {/supported_targets! { ("armv7-axis-linux-gnueab
   $target_specs/mod.rs
 sed -i -e 's/supported_targets! {/supported_targets! { ("armv7-axis-linux-gnueabihf", armv7_axis_linux_gnueabihf),/' \
   $target_specs/mod.rs
+
+# Set the vendor on every Axis target, so that `#[cfg(target_vendor = "axis")]` works as documented
+for spec in aarch64_axis_linux_gnu armv5te_axis_linux_gnueabi arm_axis_linux_gnueabi \
+  armv7_axis_linux_gnueabi armv7_axis_linux_gnueabihf mipsel_axis_linux_gnu
+do
+  # Specs either build their options inline or assign a `base` which was customized earlier
+  sed -i \
+    -e 's/options: TargetOptions {/options: TargetOptions { vendor: "axis".into(),/' \
+    -e 's/options: base,/options: TargetOptions { vendor: "axis".into(), ..base },/' \
+    $target_specs/$spec.rs
+
+  # Make sure the target actually contains the vendor
+  grep -c 'vendor: "axis"' $target_specs/$spec.rs >/dev/null || (
+    echo "couldn't define vendor at $target_specs/$spec.rs" >&2
+    echo
+    cat $target_specs/$spec.rs
+    exit 1
+  )
+done
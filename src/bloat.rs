@@ -0,0 +1,416 @@
+use crate::cargo_config::ByteSize;
+use crate::error::{Error, IoResultExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::path::Path;
+
+/// What takes up space in an executable: its largest functions, and the crates they come from.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Bloat {
+    /// What measured it
+    pub analyzer: Analyzer,
+    pub file_size: u64,
+    /// The size of the executable's code, which `functions` and `crates` divide up
+    pub text_section_size: u64,
+    /// The largest functions, largest first
+    pub functions: Vec<Function>,
+    /// The crates with the most code, largest first
+    pub crates: Vec<Crate>,
+}
+
+/// How a [`Bloat`] was measured.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Analyzer {
+    /// By `cargo bloat`, where it's installed
+    CargoBloat,
+    /// From the symbol table of the executable as built
+    Symbols,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Function {
+    /// The crate the function comes from, if known
+    #[serde(rename = "crate")]
+    pub crate_name: Option<String>,
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Crate {
+    pub name: String,
+    pub size: u64,
+}
+
+/// What `cargo bloat --message-format json` prints, listing either functions or, with `--crates`,
+/// crates.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct CargoBloatOutput {
+    file_size: u64,
+    text_section_size: u64,
+    #[serde(default)]
+    functions: Vec<Function>,
+    #[serde(default)]
+    crates: Vec<Crate>,
+}
+
+/// A symbol's `st_info` type for functions.
+const STT_FUNC: u8 = 2;
+/// A section's `sh_type` for symbol tables.
+const SHT_SYMTAB: u32 = 2;
+
+impl Bloat {
+    /// Combine the output of `cargo bloat` listing functions and of `cargo bloat --crates`.
+    pub fn from_cargo_bloat(functions: CargoBloatOutput, crates: CargoBloatOutput) -> Self {
+        Bloat {
+            analyzer: Analyzer::CargoBloat,
+            file_size: functions.file_size,
+            text_section_size: functions.text_section_size,
+            functions: functions.functions,
+            crates: crates.crates,
+        }
+    }
+
+    /// Measure the executable at `path` from its symbol table, keeping the `top` largest functions
+    /// and crates.
+    ///
+    /// Functions are attributed to crates by their mangled names, so those which aren't Rust's are
+    /// counted together as `[Unknown]`, as `cargo bloat` does.
+    pub fn from_symbols(path: &Path, top: usize) -> Result<Self, Error> {
+        let data = std::fs::read(path).with_path(path)?;
+        let elf = Elf::parse(&data).ok_or_else(|| {
+            Error::Package(format!("{} is not a readable ELF file", path.display()))
+        })?;
+        let symbols = elf.functions().ok_or_else(|| {
+            Error::Package(format!(
+                "{} has no symbol table: the profile may set `strip`",
+                path.display()
+            ))
+        })?;
+
+        let mut functions: Vec<Function> = symbols
+            .into_iter()
+            .map(|(name, size)| {
+                let (crate_name, name) = match demangle(name) {
+                    Some((crate_name, name)) => (Some(crate_name), name),
+                    None => (None, String::from_utf8_lossy(name).into_owned()),
+                };
+                Function {
+                    crate_name,
+                    name,
+                    size,
+                }
+            })
+            .collect();
+
+        let mut crate_sizes: HashMap<&str, u64> = HashMap::new();
+        for function in &functions {
+            let name = function.crate_name.as_deref().unwrap_or("[Unknown]");
+            *crate_sizes.entry(name).or_default() += function.size;
+        }
+        let mut crates: Vec<Crate> = crate_sizes
+            .into_iter()
+            .map(|(name, size)| Crate {
+                name: name.to_string(),
+                size,
+            })
+            .collect();
+        crates.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+        crates.truncate(top);
+
+        functions.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+        functions.truncate(top);
+
+        Ok(Bloat {
+            analyzer: Analyzer::Symbols,
+            file_size: data.len() as u64,
+            text_section_size: elf.text_section_size(),
+            functions,
+            crates,
+        })
+    }
+
+    /// Print the largest crates and functions to stderr, describing them as `what`.
+    pub fn print(&self, what: &str) {
+        let percent = |size: u64| match self.text_section_size {
+            0 => 0.0,
+            total => size as f64 * 100.0 / total as f64,
+        };
+        eprintln!(
+            "cargo-acap: {}'s code is {} of {}, by {}; its largest crates:",
+            what,
+            ByteSize(self.text_section_size),
+            ByteSize(self.file_size),
+            match self.analyzer {
+                Analyzer::CargoBloat => "`cargo bloat`",
+                Analyzer::Symbols => "its symbol table",
+            }
+        );
+        for krate in &self.crates {
+            eprintln!(
+                "  {:>10} {:>5.1}%  {}",
+                ByteSize(krate.size).to_string(),
+                percent(krate.size),
+                krate.name
+            );
+        }
+        eprintln!("cargo-acap: and its largest functions:");
+        for function in &self.functions {
+            eprintln!(
+                "  {:>10} {:>5.1}%  {}",
+                ByteSize(function.size).to_string(),
+                percent(function.size),
+                function.name
+            );
+        }
+    }
+}
+
+/// Just enough of an ELF file to find its functions and code.
+struct Elf<'a> {
+    data: &'a [u8],
+    is_64_bit: bool,
+    big_endian: bool,
+    /// Each section's header
+    sections: Vec<Section>,
+    /// The index of the section naming the others
+    names_index: usize,
+}
+
+struct Section {
+    name: u32,
+    kind: u32,
+    offset: u64,
+    size: u64,
+    /// The section this refers to, e.g. a symbol table's string table
+    link: u32,
+}
+
+impl<'a> Elf<'a> {
+    /// Parse the section headers of the ELF file `data`.
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < 52 || &data[..4] != b"\x7fELF" {
+            return None;
+        }
+        let mut elf = Elf {
+            data,
+            is_64_bit: data[4] == 2,
+            big_endian: data[5] == 2,
+            sections: vec![],
+            names_index: 0,
+        };
+
+        let (offset, entry_size, count, names_index) = if elf.is_64_bit {
+            (
+                elf.u64(0x28)?,
+                elf.u16(0x3a)?,
+                elf.u16(0x3c)?,
+                elf.u16(0x3e)?,
+            )
+        } else {
+            (
+                elf.u32(0x20)? as u64,
+                elf.u16(0x2e)?,
+                elf.u16(0x30)?,
+                elf.u16(0x32)?,
+            )
+        };
+        elf.names_index = names_index as usize;
+        for index in 0..count as u64 {
+            let at = (offset + index * entry_size as u64) as usize;
+            let section = if elf.is_64_bit {
+                Section {
+                    name: elf.u32(at)?,
+                    kind: elf.u32(at + 4)?,
+                    offset: elf.u64(at + 24)?,
+                    size: elf.u64(at + 32)?,
+                    link: elf.u32(at + 40)?,
+                }
+            } else {
+                Section {
+                    name: elf.u32(at)?,
+                    kind: elf.u32(at + 4)?,
+                    offset: elf.u32(at + 16)? as u64,
+                    size: elf.u32(at + 20)? as u64,
+                    link: elf.u32(at + 24)?,
+                }
+            };
+            elf.sections.push(section);
+        }
+        Some(elf)
+    }
+
+    /// The size of the `.text` section, or 0 if there's none.
+    fn text_section_size(&self) -> u64 {
+        let names = match self.sections.get(self.names_index) {
+            Some(names) => names,
+            None => return 0,
+        };
+        self.sections
+            .iter()
+            .find(|section| self.string(names, section.name) == Some(b".text"))
+            .map_or(0, |section| section.size)
+    }
+
+    /// The name and size of each function in the symbol table, counting aliases once, or `None`
+    /// if there's no symbol table.
+    fn functions(&self) -> Option<Vec<(&'a [u8], u64)>> {
+        let symbols = self.sections.iter().find(|s| s.kind == SHT_SYMTAB)?;
+        let strings = self.sections.get(symbols.link as usize)?;
+        let entry_size = if self.is_64_bit { 24 } else { 16 };
+
+        let mut seen = std::collections::HashSet::new();
+        let mut functions = vec![];
+        for index in 0..symbols.size / entry_size {
+            let at = (symbols.offset + index * entry_size) as usize;
+            let (name, info, value, size) = if self.is_64_bit {
+                (
+                    self.u32(at)?,
+                    *self.data.get(at + 4)?,
+                    self.u64(at + 8)?,
+                    self.u64(at + 16)?,
+                )
+            } else {
+                (
+                    self.u32(at)?,
+                    *self.data.get(at + 12)?,
+                    self.u32(at + 4)? as u64,
+                    self.u32(at + 8)? as u64,
+                )
+            };
+            if info & 0xf != STT_FUNC || size == 0 || !seen.insert((value, size)) {
+                continue;
+            }
+            functions.push((self.string(strings, name)?, size));
+        }
+        Some(functions)
+    }
+
+    /// The NUL-terminated string at `offset` in the string table `strings`.
+    fn string(&self, strings: &Section, offset: u32) -> Option<&'a [u8]> {
+        let start = (strings.offset + offset as u64) as usize;
+        let end = (strings.offset + strings.size) as usize;
+        let bytes = self.data.get(start..end.min(self.data.len()))?;
+        let length = bytes.iter().position(|&b| b == 0)?;
+        Some(&bytes[..length])
+    }
+
+    fn bytes<const N: usize>(&self, at: usize) -> Option<[u8; N]> {
+        self.data.get(at..at + N)?.try_into().ok()
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes = self.bytes(at)?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let bytes = self.bytes(at)?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn u64(&self, at: usize) -> Option<u64> {
+        let bytes = self.bytes(at)?;
+        Some(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+}
+
+/// The crate and readable path of the Rust function with the legacy mangled name `symbol`, e.g.
+/// `("std", "std::rt::lang_start")`, or `None` if it isn't one.
+///
+/// The trailing hash is dropped. Functions in `impl`s, whose paths start with a type, e.g.
+/// `<alloc::vec::Vec<T> as core::ops::Drop>::drop`, are attributed to the crate of that type.
+fn demangle(symbol: &[u8]) -> Option<(String, String)> {
+    let symbol = std::str::from_utf8(symbol).ok()?;
+    // Some platforms add a leading underscore
+    let mut rest = symbol
+        .strip_prefix("_ZN")
+        .or_else(|| symbol.strip_prefix("__ZN"))?;
+
+    let mut components = vec![];
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let length: usize = rest[..digits].parse().ok()?;
+        let component = rest.get(digits..digits + length)?;
+        rest = &rest[digits + length..];
+        components.push(component);
+    }
+    if let Some(last) = components.last() {
+        let is_hash = last.len() == 17
+            && last.starts_with('h')
+            && last[1..].bytes().all(|b| b.is_ascii_hexdigit());
+        if is_hash {
+            components.pop();
+        }
+    }
+
+    let path = components
+        .iter()
+        .map(|component| unescape(component))
+        .collect::<Option<Vec<String>>>()?
+        .join("::");
+    let crate_name = path
+        .trim_start_matches('<')
+        .trim_start_matches('&')
+        .trim_start_matches("mut ")
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .next()
+        .filter(|name| !name.is_empty())?
+        .to_string();
+    Some((crate_name, path))
+}
+
+/// Undo the escaping of punctuation in a component of a legacy mangled name.
+fn unescape(component: &str) -> Option<String> {
+    let mut component = component;
+    // Components can't start with `$`, so it's preceded by `_`
+    if component.starts_with("_$") {
+        component = &component[1..];
+    }
+
+    let mut unescaped = String::new();
+    let mut rest = component;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("..") {
+            unescaped.push_str("::");
+            rest = &rest[2..];
+        } else if c == '$' {
+            let end = rest[1..].find('$')? + 1;
+            let escape = &rest[1..end];
+            unescaped.push(match escape {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                _ => {
+                    let code = u32::from_str_radix(escape.strip_prefix('u')?, 16).ok()?;
+                    char::from_u32(code)?
+                }
+            });
+            rest = &rest[end + 1..];
+        } else {
+            unescaped.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Some(unescaped)
+}
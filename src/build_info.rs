@@ -0,0 +1,103 @@
+use serde::Serialize;
+use std::path::Path;
+use std::process::Command;
+use std::time::SystemTime;
+
+/// Where and how a package was built, to be embedded in it by `embed_build_info = true`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct BuildInfo {
+    /// `git describe` of the package's repository, suffixed `-dirty` if it had changes, or `None`
+    /// outside a repository
+    pub commit: Option<String>,
+    /// When the package was built, in UTC, or at `SOURCE_DATE_EPOCH` if set
+    pub built_at: String,
+    /// The `rustc --version` which built the executable
+    pub rustc: String,
+    /// The version of `cargo-acap` which built the package
+    pub cargo_acap: &'static str,
+}
+
+impl BuildInfo {
+    /// Describe a build of the package at `package_root` with `rustc`.
+    pub fn new(package_root: &Path, rustc: &str) -> Self {
+        let commit = git_describe(package_root);
+        if commit.is_none() {
+            eprintln!(
+                "cargo-acap: warning: {} is not in a git repository with commits, so the \
+                 package's build info has no commit",
+                package_root.display()
+            );
+        }
+
+        let built_at = std::env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|epoch| epoch.parse().ok())
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs())
+            });
+
+        BuildInfo {
+            commit,
+            built_at: utc_timestamp(built_at),
+            rustc: rustc.to_string(),
+            cargo_acap: env!("CARGO_PKG_VERSION"),
+        }
+    }
+
+    /// Comment lines for the end of `package.conf`, which the device ignores.
+    pub fn package_conf_comments(&self) -> String {
+        format!(
+            "# Commit: {}\n# Built at: {}\n# Built with: {}, cargo-acap {}\n",
+            self.commit.as_deref().unwrap_or("unknown"),
+            self.built_at,
+            self.rustc,
+            self.cargo_acap
+        )
+    }
+
+    /// The contents of `build-info.json`.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serialize build info") + "\n"
+    }
+}
+
+/// `git describe --always --dirty` in `dir`, if it's in a repository and `git` is installed.
+fn git_describe(dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["describe", "--always", "--tags", "--dirty"])
+        .current_dir(dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let describe = String::from_utf8(output.stdout).ok()?;
+    Some(describe.trim().to_string()).filter(|d| !d.is_empty() && !d.contains('\n'))
+}
+
+/// Format `secs` since the UNIX epoch like `2021-03-04T05:06:07Z`.
+pub(crate) fn utc_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+
+    // Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
@@ -1,8 +1,96 @@
-use crate::package_dot_conf::StartMode;
+use crate::eap::Compression;
+use crate::error::Error;
+use crate::package_dot_conf::{MicroVersionStyle, StartMode, LINT_IDS};
+use crate::param_dot_conf::Parameter;
 use crate::target::Target;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+/// The keys of `[package.metadata.acap]`, which must match the fields of `CargoAcapMetadata`.
+const KNOWN_KEYS: &[&str] = &[
+    "app_name",
+    "executable_name",
+    "display_name",
+    "menu_name",
+    "vendor",
+    "vendor_homepage_url",
+    "launch_arguments",
+    "license_check_arguments",
+    "axis_application_id",
+    "settings_page_file",
+    "settings_page_text",
+    "post_install_script",
+    "pre_uninstall_script",
+    "other_files",
+    "parameters",
+    "start_mode",
+    "targets",
+    "required_embedded_development_version",
+    "manifest_version",
+    "forward_env",
+    "rustflags",
+    "strip_args",
+    "target",
+    "libraries",
+    "unix_user",
+    "unix_group",
+    "compression",
+    "license_file",
+    "embed_build_info",
+    "artifact_name",
+    "artifact_per_soc",
+    "version",
+    "micro_version_style",
+    "suppress_profile_warnings",
+    "max_eap_size",
+    "max_binary_size",
+    "license_key_file",
+    "cgi_paths",
+    "http_cgi_paths_file",
+    "allow",
+    "dbus",
+    "web_server",
+    "files",
+];
+
+/// The placeholders which `artifact_name` may contain.
+const ARTIFACT_NAME_PLACEHOLDERS: &[&str] = &["app_name", "version", "target", "triple", "profile"];
+
+/// The values of `unix_user` and `unix_group` which Axis products accept.
+const KNOWN_UNIX_NAMES: &[&str] = &["sdk", "root"];
+
+/// The keys of each `[[package.metadata.acap.parameters]]` table.
+const KNOWN_PARAMETER_KEYS: &[&str] = &["name", "default", "type"];
+
+/// The keys of each `[[package.metadata.acap.libraries]]` table.
+const KNOWN_LIBRARY_KEYS: &[&str] = &["name", "path"];
+
+/// The keys of each `[[package.metadata.acap.cgi_paths]]` table.
+const KNOWN_CGI_PATH_KEYS: &[&str] = &["name", "access", "type"];
+
+/// The keys of each entry of `files` in `[package.metadata.acap]`.
+const KNOWN_FILE_MAPPING_KEYS: &[&str] = &["src", "dst", "mode"];
+
+/// The keys of the `[package.metadata.acap.dbus]` table.
+const KNOWN_DBUS_KEYS: &[&str] = &["service", "conf_file"];
+
+/// The keys of the `[package.metadata.acap.web_server]` table.
+const KNOWN_WEB_SERVER_KEYS: &[&str] = &["port", "path", "access"];
+
+/// The keys of each `[package.metadata.acap.target.<name>]` table.
+const KNOWN_TARGET_KEYS: &[&str] = &[
+    "rustflags",
+    "docker_image",
+    "strip_args",
+    "required_embedded_development_version",
+    "launch_arguments",
+    "max_eap_size",
+    "max_binary_size",
+];
 
 #[derive(Debug, Clone, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct CargoAcapMetadata {
     /// The machine-friendly name of the package. Used for:
     ///
@@ -12,6 +100,10 @@ pub struct CargoAcapMetadata {
     /// * Myriad related files
     pub app_name: Option<String>,
 
+    /// The executable's name within the package, if not `app_name`, e.g. because scripts run it by
+    /// another name. `<app_name>` is then a symlink to it, which the device launches.
+    pub executable_name: Option<String>,
+
     /// A user-friendly package name. The name will be displayed in the Axis product's web pages.
     pub display_name: Option<String>,
 
@@ -34,6 +126,33 @@ pub struct CargoAcapMetadata {
     /// The Axis-assigned application ID, if using Axis licensing.
     pub axis_application_id: Option<String>,
 
+    /// The file within `html/` to use as a custom settings page, e.g. `"settings.html"`.
+    pub settings_page_file: Option<String>,
+
+    /// The text displayed on the link to the custom settings page.
+    pub settings_page_text: Option<String>,
+
+    /// A shell script, relative to the package root, to run on the device after installation.
+    ///
+    /// Defaults to `postinstall.sh` if the package root contains one.
+    pub post_install_script: Option<String>,
+
+    /// A shell script, relative to the package root, to run on the device before uninstallation.
+    ///
+    /// Defaults to `preuninstall.sh` if the package root contains one.
+    pub pre_uninstall_script: Option<String>,
+
+    /// Files or directories, relative to the package root, to install alongside the executable,
+    /// e.g. shared libraries or default configuration. Directories are included recursively.
+    pub other_files: Option<Vec<String>>,
+
+    /// Files to install at paths of their own within the application's directory, which are
+    /// listed in `OTHERFILES` too.
+    pub files: Option<Vec<FileMapping>>,
+
+    /// The application's parameters, from which to generate `param.conf`.
+    pub parameters: Option<Vec<Parameter>>,
+
     /// The start mode to use for this application.
     pub start_mode: Option<StartMode>,
 
@@ -43,4 +162,1116 @@ pub struct CargoAcapMetadata {
     /// Specifies the minimum required SDK version that the product running the
     /// application must support.
     pub required_embedded_development_version: Option<String>,
+
+    /// The `manifest.json` schema version, e.g. `"1.3"`. If set, the package includes a
+    /// `manifest.json` in addition to `package.conf`.
+    pub manifest_version: Option<String>,
+
+    /// Environment variables to forward from the host into the build container if they're set,
+    /// e.g. `["GIT_SHA", "CI"]`.
+    pub forward_env: Option<Vec<String>>,
+
+    /// Flags to pass to `rustc` when building for any target.
+    pub rustflags: Option<Vec<String>>,
+
+    /// The arguments with which `objcopy` strips the executable, instead of `--strip-all`.
+    pub strip_args: Option<Vec<String>>,
+
+    /// Settings which apply only when building for a particular target.
+    pub target: Option<HashMap<Target, TargetMetadata>>,
+
+    /// Shared libraries built by the package, e.g. a `cdylib` plugin, to install alongside the
+    /// executable.
+    pub libraries: Option<Vec<Library>>,
+
+    /// The UNIX user as which to run the application (defaults to `sdk`).
+    pub unix_user: Option<String>,
+
+    /// The UNIX group in which to run the application (defaults to `sdk`).
+    pub unix_group: Option<String>,
+
+    /// How hard to gzip the `.eap`: a level from 0 to 9, or `"fast"` or `"best"` (defaults to 6).
+    /// Level 0 suits packages of data which is already compressed.
+    pub compression: Option<Compression>,
+
+    /// A text file, relative to the package root, containing the application's license, e.g. an
+    /// EULA (defaults to the package's `license-file`).
+    pub license_file: Option<String>,
+
+    /// Whether to embed the git commit, build time, and toolchain versions in the package, as
+    /// `package.conf` comments and `build-info.json`.
+    pub embed_build_info: Option<bool>,
+
+    /// How to name built artifacts, before their extension, e.g. `"{app_name}-{version}-{target}"`.
+    pub artifact_name: Option<String>,
+
+    /// Whether to also name each target's `.eap` after every SoC using that target, e.g.
+    /// `app_1.0.0_artpec7.eap`.
+    pub artifact_per_soc: Option<bool>,
+
+    /// The version of the application, instead of the package's.
+    pub version: Option<semver::Version>,
+
+    /// What of the version to put in `APPMICROVERSION` (defaults to `full`).
+    pub micro_version_style: Option<MicroVersionStyle>,
+
+    /// Whether to build without being warned about a `cargo` profile which makes packages larger
+    /// or slower than they need to be.
+    pub suppress_profile_warnings: Option<bool>,
+
+    /// The largest the `.eap` may be, e.g. because that's all the space devices have free.
+    pub max_eap_size: Option<ByteSize>,
+
+    /// The largest the packaged, stripped executable may be.
+    pub max_binary_size: Option<ByteSize>,
+
+    /// A license key file, relative to the package root, to upload after installing the
+    /// application on a device, e.g. to test an application which uses Axis licensing.
+    pub license_key_file: Option<String>,
+
+    /// The CGIs which the application serves through the device's web server, from which to
+    /// generate `cgi.txt`, instead of packaging `src/cgi.txt`.
+    pub cgi_paths: Option<Vec<CgiPath>>,
+
+    /// A file listing the application's CGIs, relative to the package root, to package as
+    /// `cgi.txt` (defaults to `cgi.txt`, or the deprecated `src/cgi.txt`, if either exists).
+    pub http_cgi_paths_file: Option<String>,
+
+    /// Likely mistakes not to warn about, by ID, e.g. `"acap::settings-page-missing"`.
+    pub allow: Option<Vec<String>>,
+
+    /// The D-Bus service which the application provides, whose policy to package.
+    pub dbus: Option<Dbus>,
+
+    /// The application's own web server, to which the device's web server proxies requests.
+    pub web_server: Option<WebServer>,
+}
+
+/// A `[[package.metadata.acap.libraries]]` table.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Library {
+    /// The name of the package's `[lib]` target, which must have `crate-type = ["cdylib"]`.
+    pub name: String,
+
+    /// The directory within the package in which to install the library (defaults to `lib`).
+    pub path: Option<String>,
+}
+
+impl Library {
+    /// The directory within the package in which to install the library.
+    pub fn path(&self) -> &str {
+        self.path.as_deref().unwrap_or("lib")
+    }
+}
+
+/// An entry of `files` in `[package.metadata.acap]`.
+#[derive(Debug, Clone, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FileMapping {
+    /// The file or directory to install, relative to the package root, or a glob, e.g.
+    /// `assets/*.bin`.
+    pub src: String,
+
+    /// Where to install it, relative to the application's directory, or if `src` is a glob, the
+    /// directory in which to install what it matches.
+    pub dst: String,
+
+    /// The mode to install it with, e.g. `0o644`, regardless of its mode on the host.
+    pub mode: Option<u32>,
+}
+
+impl FileMapping {
+    /// Whether `src` is a glob, rather than a path.
+    pub fn is_glob(&self) -> bool {
+        self.src.contains(['*', '?', '['])
+    }
+}
+
+/// A `[[package.metadata.acap.cgi_paths]]` table.
+#[derive(Debug, Clone, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CgiPath {
+    /// The CGI's path, e.g. `example.cgi`.
+    pub name: String,
+
+    /// Who may call the CGI.
+    pub access: CgiAccess,
+
+    /// How the web server passes requests to the application (defaults to `transfer`).
+    #[serde(rename = "type", default)]
+    pub cgi_type: CgiType,
+}
+
+/// The users who may call a CGI, by their role on the device.
+#[derive(Debug, Copy, Clone, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CgiAccess {
+    Admin,
+    Operator,
+    Viewer,
+}
+
+/// How the web server passes requests for a CGI to the application.
+#[derive(Debug, Copy, Clone, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CgiType {
+    #[default]
+    Transfer,
+    Fastcgi,
+}
+
+impl CgiAccess {
+    /// The role's name, e.g. `admin`.
+    pub fn name(self) -> &'static str {
+        match self {
+            CgiAccess::Admin => "admin",
+            CgiAccess::Operator => "operator",
+            CgiAccess::Viewer => "viewer",
+        }
+    }
+}
+
+impl CgiPath {
+    /// This CGI's line in `cgi.txt`, e.g. `transfer example.cgi admin`.
+    pub fn line(&self) -> String {
+        let cgi_type = match self.cgi_type {
+            CgiType::Transfer => "transfer",
+            CgiType::Fastcgi => "fastcgi",
+        };
+        format!("{} {} {}", cgi_type, self.name, self.access.name())
+    }
+}
+
+/// The `[package.metadata.acap.web_server]` table.
+#[derive(Debug, Clone, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WebServer {
+    /// The port on which the application's web server listens on `localhost`.
+    pub port: u16,
+
+    /// The path under `/local/<app_name>/` at which the device serves it, e.g. `api`.
+    pub path: String,
+
+    /// Who may make requests to it (defaults to `admin`).
+    #[serde(default = "WebServer::default_access")]
+    pub access: CgiAccess,
+}
+
+impl WebServer {
+    fn default_access() -> CgiAccess {
+        CgiAccess::Admin
+    }
+
+    /// The path at which the device serves requests for the application's web server, e.g.
+    /// `/local/myapp/api`.
+    pub fn device_path(&self, app_name: &str) -> String {
+        format!("/local/{}/{}", app_name, self.path)
+    }
+}
+
+/// The `[package.metadata.acap.dbus]` table.
+#[derive(Debug, Clone, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Dbus {
+    /// The well-known name which the application owns on the system bus, e.g.
+    /// `com.example.MyApp`.
+    pub service: String,
+
+    /// The policy to package, relative to the package root (defaults to one generated from
+    /// `service`).
+    pub conf_file: Option<String>,
+}
+
+impl Dbus {
+    /// The policy's path within the package, as listed in `OTHERFILES`.
+    pub fn archive_path(&self) -> String {
+        format!("dbus/{}.conf", self.service)
+    }
+}
+
+/// The longest `app_name` which every firmware version installs
+pub(crate) const MAX_APP_NAME_LEN: usize = 32;
+
+/// Why `name` isn't an `app_name` which every firmware version installs, if it isn't.
+///
+/// Some firmware versions refuse uppercase letters and hyphens, failing only with "installation
+/// failed", so this is stricter than the ACAP documentation.
+pub(crate) fn app_name_problem(name: &str) -> Option<String> {
+    if name.len() > MAX_APP_NAME_LEN {
+        Some(format!("is longer than {} characters", MAX_APP_NAME_LEN))
+    } else if !name.starts_with(|c: char| c.is_ascii_lowercase()) {
+        Some("must start with a lowercase ASCII letter".to_string())
+    } else if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        Some("may contain only lowercase ASCII letters, digits, and `_`".to_string())
+    } else {
+        None
+    }
+}
+
+/// Why `name` isn't a well-known D-Bus bus name in reverse-DNS form, if it isn't.
+fn bus_name_problem(name: &str) -> Option<&'static str> {
+    let elements: Vec<&str> = name.split('.').collect();
+    if name.len() > 255 {
+        Some("is longer than 255 characters")
+    } else if elements.len() < 2 {
+        Some("must be in reverse-DNS form, e.g. \"com.example.MyApp\"")
+    } else if elements.iter().any(|element| element.is_empty()) {
+        Some("has an empty element")
+    } else if elements
+        .iter()
+        .any(|element| element.starts_with(|c: char| c.is_ascii_digit()))
+    {
+        Some("has an element starting with a digit")
+    } else if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+    {
+        Some("may contain only ASCII letters, digits, `_`, `-`, and `.`")
+    } else {
+        None
+    }
+}
+
+/// A `[package.metadata.acap.target.<name>]` table.
+#[derive(Debug, Clone, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TargetMetadata {
+    /// Flags to pass to `rustc` when building for this target, after the global `rustflags`.
+    pub rustflags: Option<Vec<String>>,
+
+    /// The `docker` image in which to build this target, instead of `--docker-image`.
+    pub docker_image: Option<String>,
+
+    /// The arguments with which `objcopy` strips the executable for this target, instead of the
+    /// global `strip_args`.
+    pub strip_args: Option<Vec<String>>,
+
+    /// The minimum SDK version required of products running this target's package, instead of
+    /// the global `required_embedded_development_version`.
+    pub required_embedded_development_version: Option<String>,
+
+    /// The command line arguments to pass when this target's application is launched, instead of
+    /// the global `launch_arguments`.
+    pub launch_arguments: Option<String>,
+
+    /// The largest this target's `.eap` may be, instead of the global `max_eap_size`.
+    pub max_eap_size: Option<ByteSize>,
+
+    /// The largest this target's packaged executable may be, instead of the global
+    /// `max_binary_size`.
+    pub max_binary_size: Option<ByteSize>,
+}
+
+/// A number of bytes, given in metadata as an integer or a string with a unit, e.g. `"8 MiB"`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct ByteSize(pub u64);
+
+impl std::str::FromStr for ByteSize {
+    type Err = String;
+
+    /// Parse a number of bytes, optionally followed by `B`, `KB`, `MB`, `GB`, `KiB`, `MiB`, or
+    /// `GiB`, e.g. `"1.5 MiB"`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid size {:?}: expected a number of bytes, optionally followed by a unit, \
+                 e.g. \"8 MiB\"",
+                s
+            )
+        };
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let multiplier: u64 = match unit.trim_start() {
+            "" | "B" => 1,
+            "KB" => 1000,
+            "MB" => 1000 * 1000,
+            "GB" => 1000 * 1000 * 1000,
+            "KiB" => 1 << 10,
+            "MiB" => 1 << 20,
+            "GiB" => 1 << 30,
+            _ => return Err(invalid()),
+        };
+        let number: f64 = number.parse().map_err(|_| invalid())?;
+        Ok(ByteSize((number * multiplier as f64) as u64))
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Bytes(u64),
+            Text(String),
+        }
+
+        match Value::deserialize(deserializer)? {
+            Value::Bytes(bytes) => Ok(ByteSize(bytes)),
+            Value::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl ByteSize {
+    /// The size as displayed, followed by the exact number of bytes if that's rounded.
+    pub fn exactly(self) -> String {
+        if self.0 < 1 << 10 {
+            self.to_string()
+        } else {
+            format!("{} ({} bytes)", self, self.0)
+        }
+    }
+}
+
+impl std::fmt::Display for ByteSize {
+    /// Format the size in the largest binary unit in which it's at least 1, e.g. `8.4 MiB`.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let units = [("GiB", 1 << 30), ("MiB", 1 << 20), ("KiB", 1 << 10)];
+        match units.iter().find(|(_, size)| self.0 >= *size) {
+            Some((unit, size)) => write!(f, "{:.1} {}", self.0 as f64 / *size as f64, unit),
+            None => write!(f, "{} bytes", self.0),
+        }
+    }
+}
+
+impl CargoAcapMetadata {
+    /// The `[package.metadata.acap]` table of `package` as TOML, if it has one.
+    pub fn table(package: &cargo::core::Package) -> Option<String> {
+        package
+            .manifest()
+            .custom_metadata()
+            .and_then(|v| v.as_table())
+            .and_then(|t| t.get("acap"))
+            .map(|v| v.to_string())
+    }
+
+    /// The parsed `[package.metadata.acap]` table of `package`, or the defaults if it has none.
+    pub fn for_package(package: &cargo::core::Package) -> Result<Self, Error> {
+        let metadata_error = |source| Error::Metadata {
+            path: package.manifest_path().to_owned(),
+            source,
+        };
+        let acap = package
+            .manifest()
+            .custom_metadata()
+            .and_then(|metadata| metadata.get("acap"));
+        let table = match acap {
+            // `cargo` parses manifests with another TOML library, whose values are converted
+            Some(acap) => toml::Value::try_from(acap).map_err(|e| {
+                metadata_error(MetadataError::Invalid {
+                    key: String::new(),
+                    message: e.to_string(),
+                })
+            })?,
+            None => return Ok(Self::default()),
+        };
+        Self::parse(table).map_err(metadata_error)
+    }
+
+    /// Parse and validate a `[package.metadata.acap]` table.
+    pub fn parse(table: toml::Value) -> Result<Self, MetadataError> {
+        if !table.is_table() {
+            return Err(MetadataError::Invalid {
+                key: String::new(),
+                message: format!("expected a table, found {}", table.type_str()),
+            });
+        }
+        let mut unknown = unknown_keys(&table, KNOWN_KEYS, "");
+        if let Some(parameters) = table.get("parameters").and_then(|p| p.as_array()) {
+            for (i, parameter) in parameters.iter().enumerate() {
+                let prefix = format!("parameters[{}].", i);
+                unknown.extend(unknown_keys(parameter, KNOWN_PARAMETER_KEYS, &prefix));
+            }
+        }
+        if let Some(libraries) = table.get("libraries").and_then(|l| l.as_array()) {
+            for (i, library) in libraries.iter().enumerate() {
+                let prefix = format!("libraries[{}].", i);
+                unknown.extend(unknown_keys(library, KNOWN_LIBRARY_KEYS, &prefix));
+            }
+        }
+        if let Some(cgi_paths) = table.get("cgi_paths").and_then(|c| c.as_array()) {
+            for (i, cgi_path) in cgi_paths.iter().enumerate() {
+                let prefix = format!("cgi_paths[{}].", i);
+                unknown.extend(unknown_keys(cgi_path, KNOWN_CGI_PATH_KEYS, &prefix));
+            }
+        }
+        if let Some(files) = table.get("files").and_then(|f| f.as_array()) {
+            for (i, file) in files.iter().enumerate() {
+                let prefix = format!("files[{}].", i);
+                unknown.extend(unknown_keys(file, KNOWN_FILE_MAPPING_KEYS, &prefix));
+            }
+        }
+        if let Some(dbus) = table.get("dbus") {
+            unknown.extend(unknown_keys(dbus, KNOWN_DBUS_KEYS, "dbus."));
+        }
+        if let Some(web_server) = table.get("web_server") {
+            unknown.extend(unknown_keys(
+                web_server,
+                KNOWN_WEB_SERVER_KEYS,
+                "web_server.",
+            ));
+        }
+        let mut target_names: Vec<(Target, &String)> = vec![];
+        if let Some(targets) = table.get("target").and_then(|t| t.as_table()) {
+            for (name, target) in targets {
+                match name.parse() {
+                    Ok(t) => target_names.push((t, name)),
+                    Err(_) => {
+                        let names: Vec<&'static str> = Target::all_names().collect();
+                        unknown.push((format!("target.{}", name), suggestion(name, &names)));
+                        continue;
+                    }
+                }
+                let prefix = format!("target.{}.", name);
+                unknown.extend(unknown_keys(target, KNOWN_TARGET_KEYS, &prefix));
+            }
+        }
+        if !unknown.is_empty() {
+            return Err(MetadataError::UnknownKeys(unknown));
+        }
+        // `armv7hf` and `artpec7` name the same target, so one table would silently replace the other
+        for (i, (target, name)) in target_names.iter().enumerate() {
+            if let Some((_, other)) = target_names[..i].iter().find(|(t, _)| t == target) {
+                return Err(MetadataError::Invalid {
+                    key: format!("target.{}", name),
+                    message: format!("names the same target as `target.{}`", other),
+                });
+            }
+        }
+
+        let metadata: Self = serde_path_to_error::deserialize(table).map_err(|e| {
+            let key = e.path().to_string();
+            let mut message = e.into_inner().to_string();
+            // `toml` names the key itself, which is redundant since we name its full path
+            if let Some(i) = message.rfind(" for key `") {
+                message.truncate(i);
+            }
+            MetadataError::Invalid { key, message }
+        })?;
+        metadata.validate()?;
+        Ok(metadata)
+    }
+
+    /// The metadata which applies when building for `target`, with its
+    /// `[package.metadata.acap.target.<name>]` table merged over the rest.
+    ///
+    /// The target's own settings replace the global ones, except `rustflags`, which extend them.
+    /// Only `target`'s table remains, so the methods below give the same answers for `target`,
+    /// still naming the keys which configured each setting.
+    pub fn resolved_for(&self, target: Target) -> Self {
+        let mut resolved = self.clone();
+        let mut overrides = self
+            .target
+            .as_ref()
+            .and_then(|targets| targets.get(&target))
+            .cloned()
+            .unwrap_or_default();
+
+        if let Some(args) = &overrides.launch_arguments {
+            resolved.launch_arguments = Some(args.clone());
+        }
+        if let Some(args) = &overrides.strip_args {
+            resolved.strip_args = Some(args.clone());
+        }
+        if let Some(version) = &overrides.required_embedded_development_version {
+            resolved.required_embedded_development_version = Some(version.clone());
+        }
+        if let Some(size) = overrides.max_eap_size {
+            resolved.max_eap_size = Some(size);
+        }
+        if let Some(size) = overrides.max_binary_size {
+            resolved.max_binary_size = Some(size);
+        }
+        if let Some(rustflags) = overrides.rustflags.take() {
+            resolved
+                .rustflags
+                .get_or_insert_with(Vec::new)
+                .extend(rustflags);
+        }
+
+        resolved.target = Some(std::iter::once((target, overrides)).collect());
+        resolved
+    }
+
+    /// The version of the application built from `package`: `version`, or else the package's.
+    pub fn version(&self, package: &cargo::core::Package) -> semver::Version {
+        self.version
+            .clone()
+            .unwrap_or_else(|| package.version().clone())
+    }
+
+    /// The `docker` image configured for `target`, if any.
+    pub fn docker_image(&self, target: Target) -> Option<&str> {
+        self.target
+            .as_ref()
+            .and_then(|targets| targets.get(&target))
+            .and_then(|target| target.docker_image.as_deref())
+    }
+
+    /// The arguments with which to strip the executable for `target`, if configured, along with
+    /// the key which configured them.
+    pub fn strip_args(&self, target: Target) -> Option<(String, &[String])> {
+        let target_strip_args = self
+            .target
+            .as_ref()
+            .and_then(|targets| targets.get(&target))
+            .and_then(|target| target.strip_args.as_deref());
+        match (target_strip_args, &self.strip_args) {
+            (Some(args), _) => Some((format!("target.{}.strip_args", target.name()), args)),
+            (None, Some(args)) => Some(("strip_args".to_string(), args)),
+            (None, None) => None,
+        }
+    }
+
+    /// The command line arguments with which to launch the application built for `target`, if
+    /// configured, along with the key which configured them.
+    pub fn launch_arguments(&self, target: Target) -> Option<(String, &str)> {
+        let target_launch_arguments = self
+            .target
+            .as_ref()
+            .and_then(|targets| targets.get(&target))
+            .and_then(|target| target.launch_arguments.as_deref());
+        match (target_launch_arguments, &self.launch_arguments) {
+            (Some(args), _) => Some((format!("target.{}.launch_arguments", target.name()), args)),
+            (None, Some(args)) => Some(("launch_arguments".to_string(), args)),
+            (None, None) => None,
+        }
+    }
+
+    /// The minimum SDK version required of products running the package for `target`, if
+    /// configured.
+    pub fn required_embedded_development_version(&self, target: Target) -> Option<&str> {
+        self.target
+            .as_ref()
+            .and_then(|targets| targets.get(&target))
+            .and_then(|target| target.required_embedded_development_version.as_deref())
+            .or(self.required_embedded_development_version.as_deref())
+    }
+
+    /// The flags to pass to `rustc` when building for `target`: the global `rustflags`, extended
+    /// by the target's own.
+    pub fn rustflags(&self, target: Target) -> Vec<&str> {
+        let target_rustflags = self
+            .target
+            .as_ref()
+            .and_then(|targets| targets.get(&target))
+            .and_then(|target| target.rustflags.as_ref());
+        self.rustflags
+            .iter()
+            .chain(target_rustflags)
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Check constraints which can't be expressed by the types of the fields.
+    fn validate(&self) -> Result<(), MetadataError> {
+        let invalid = |key: &str, message: String| {
+            Err(MetadataError::Invalid {
+                key: key.to_string(),
+                message,
+            })
+        };
+
+        for (key, name) in [
+            ("app_name", &self.app_name),
+            ("executable_name", &self.executable_name),
+        ] {
+            if let Some(name) = name {
+                if let Some(problem) = app_name_problem(name) {
+                    return invalid(key, format!("{:?} {}", name, problem));
+                }
+            }
+        }
+
+        // The product's web pages show these as they are, so markup would be rendered
+        for (key, value) in [
+            ("display_name", &self.display_name),
+            ("menu_name", &self.menu_name),
+        ] {
+            if let Some(value) = value {
+                if value.contains(['<', '>']) {
+                    return invalid(key, format!("{:?} may not contain `<` or `>`", value));
+                }
+            }
+        }
+
+        if let Some(id) = &self.axis_application_id {
+            if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+                return invalid("axis_application_id", format!("{:?} must be numeric", id));
+            }
+        }
+        if self.license_key_file.is_some() && self.axis_application_id.is_none() {
+            return invalid(
+                "license_key_file",
+                "license keys are only for applications using Axis licensing: set \
+                 axis_application_id too"
+                    .to_string(),
+            );
+        }
+
+        let versions = self
+            .required_embedded_development_version
+            .iter()
+            .map(|version| ("required_embedded_development_version".to_string(), version))
+            .chain(
+                self.target
+                    .iter()
+                    .flatten()
+                    .filter_map(|(target, metadata)| {
+                        let version = metadata.required_embedded_development_version.as_ref()?;
+                        let key = format!(
+                            "target.{}.required_embedded_development_version",
+                            target.name()
+                        );
+                        Some((key, version))
+                    }),
+            );
+        for (key, version) in versions {
+            let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+            match version.split_once('.') {
+                Some((major, minor)) if is_number(major) && is_number(minor) => {}
+                _ => {
+                    return invalid(
+                        &key,
+                        format!("{:?} must be a version like \"3.0\": MAJOR.MINOR", version),
+                    )
+                }
+            }
+        }
+
+        for (key, value) in [
+            ("unix_user", &self.unix_user),
+            ("unix_group", &self.unix_group),
+        ] {
+            if let Some(value) = value {
+                if !KNOWN_UNIX_NAMES.contains(&value.as_str()) {
+                    return invalid(
+                        key,
+                        format!("{:?} must be one of {:?}", value, KNOWN_UNIX_NAMES),
+                    );
+                }
+            }
+        }
+
+        for (i, name) in self.forward_env.iter().flatten().enumerate() {
+            if name.is_empty() || name.contains('=') {
+                return invalid(
+                    &format!("forward_env[{}]", i),
+                    format!("{:?} is not a valid environment variable name", name),
+                );
+            }
+        }
+
+        for (i, library) in self.libraries.iter().flatten().enumerate() {
+            let path = std::path::Path::new(library.path());
+            if path.is_absolute()
+                || library.path().contains(' ')
+                || path
+                    .components()
+                    .any(|c| matches!(c, std::path::Component::ParentDir))
+            {
+                return invalid(
+                    &format!("libraries[{}].path", i),
+                    format!(
+                        "{:?} must be a relative path within the package, without spaces",
+                        library.path()
+                    ),
+                );
+            }
+        }
+
+        for (i, id) in self.allow.iter().flatten().enumerate() {
+            if !LINT_IDS.contains(&id.as_str()) {
+                let suggestion = suggestion(id, LINT_IDS)
+                    .map(|id| format!(" (did you mean `{}`?)", id))
+                    .unwrap_or_default();
+                return invalid(
+                    &format!("allow[{}]", i),
+                    format!(
+                        "unknown lint {:?}{}: expected one of {}",
+                        id,
+                        suggestion,
+                        LINT_IDS.join(", ")
+                    ),
+                );
+            }
+        }
+
+        for (i, file) in self.files.iter().flatten().enumerate() {
+            if file.src.is_empty()
+                || !Path::new(&file.src)
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                return invalid(
+                    &format!("files[{}].src", i),
+                    format!("{:?} must be a path within the package root", file.src),
+                );
+            }
+            let dst = Path::new(&file.dst);
+            if file.dst.is_empty() || !dst.components().all(|c| matches!(c, Component::Normal(_))) {
+                return invalid(
+                    &format!("files[{}].dst", i),
+                    format!(
+                        "{:?} must be a path within the application's directory, e.g. \
+                         \"models/model.bin\"",
+                        file.dst
+                    ),
+                );
+            }
+            if file.dst.contains(char::is_whitespace) {
+                return invalid(
+                    &format!("files[{}].dst", i),
+                    format!(
+                        "{:?} can't be listed in OTHERFILES, which doesn't permit spaces",
+                        file.dst
+                    ),
+                );
+            }
+            if let Some(mode) = file.mode.filter(|mode| *mode > 0o777) {
+                return invalid(
+                    &format!("files[{}].mode", i),
+                    format!("0o{:o} is not a file mode, e.g. 0o644", mode),
+                );
+            }
+        }
+
+        if self.cgi_paths.is_some() && self.http_cgi_paths_file.is_some() {
+            return invalid(
+                "http_cgi_paths_file",
+                "can't be used with cgi_paths, from which cgi.txt is generated: remove one"
+                    .to_string(),
+            );
+        }
+        let cgi_paths = self.cgi_paths.as_deref().unwrap_or_default();
+        for (i, cgi_path) in cgi_paths.iter().enumerate() {
+            let key = format!("cgi_paths[{}].name", i);
+            if cgi_path.name.is_empty() || cgi_path.name.contains(char::is_whitespace) {
+                return invalid(
+                    &key,
+                    format!("{:?} must be a path, without spaces", cgi_path.name),
+                );
+            }
+            if cgi_paths[..i]
+                .iter()
+                .any(|other| other.name == cgi_path.name)
+            {
+                return invalid(&key, format!("{:?} is declared twice", cgi_path.name));
+            }
+        }
+
+        if let Some(dbus) = &self.dbus {
+            if let Some(problem) = bus_name_problem(&dbus.service) {
+                return invalid("dbus.service", format!("{:?} {}", dbus.service, problem));
+            }
+        }
+
+        if let Some(web_server) = &self.web_server {
+            if web_server.port == 0 {
+                return invalid("web_server.port", "must not be 0".to_string());
+            }
+            let path = &web_server.path;
+            if path.split('/').any(|segment| {
+                segment.is_empty()
+                    || segment == "."
+                    || segment == ".."
+                    || !segment
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+            }) {
+                return invalid(
+                    "web_server.path",
+                    format!(
+                        "{:?} must be a relative path, e.g. \"api\", of ASCII letters, digits, \
+                         `-`, `_`, and `.`",
+                        path
+                    ),
+                );
+            }
+            if self.manifest_version.is_none() {
+                return invalid(
+                    "web_server",
+                    "needs manifest_version = \"1.5\", since it's declared in manifest.json"
+                        .to_string(),
+                );
+            }
+        }
+
+        if let Some(template) = &self.artifact_name {
+            let literal = ARTIFACT_NAME_PLACEHOLDERS
+                .iter()
+                .fold(template.clone(), |s, name| {
+                    s.replace(&format!("{{{}}}", name), "")
+                });
+            if literal.contains(['{', '}']) {
+                return invalid(
+                    "artifact_name",
+                    format!(
+                        "{:?} may contain only the placeholders {{{}}}",
+                        template,
+                        ARTIFACT_NAME_PLACEHOLDERS.join("}, {")
+                    ),
+                );
+            }
+            if template.is_empty() || template.contains(['/', '\\']) {
+                return invalid(
+                    "artifact_name",
+                    format!("{:?} must be a file name, without `/` or `\\`", template),
+                );
+            }
+        }
+
+        if let Some(url) = &self.vendor_homepage_url {
+            if let Err(e) = url::Url::parse(url) {
+                return invalid(
+                    "vendor_homepage_url",
+                    format!("{:?} is not a valid URL: {}", url, e),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The keys of `table` which are not in `known`, each with the most similar known key, if any.
+fn unknown_keys(
+    table: &toml::Value,
+    known: &[&'static str],
+    prefix: &str,
+) -> Vec<(String, Option<&'static str>)> {
+    let table = match table.as_table() {
+        Some(table) => table,
+        None => return vec![],
+    };
+    table
+        .keys()
+        .filter(|key| !known.contains(&key.as_str()))
+        .map(|key| (format!("{}{}", prefix, key), suggestion(key, known)))
+        .collect()
+}
+
+/// The candidate most similar to `key`, if any is similar enough to be a likely misspelling.
+fn suggestion(key: &str, candidates: &[&'static str]) -> Option<&'static str> {
+    candidates
+        .iter()
+        .map(|candidate| (strsim::jaro_winkler(key, candidate), *candidate))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, candidate)| candidate)
+}
+
+/// A problem with a `[package.metadata.acap]` table.
+#[derive(Debug)]
+pub enum MetadataError {
+    /// Keys which `cargo acap` does not recognize, each with a suggested replacement if one is
+    /// similar
+    UnknownKeys(Vec<(String, Option<&'static str>)>),
+    /// A value which can't be used, at `key` within the table
+    Invalid { key: String, message: String },
+}
+
+impl std::error::Error for MetadataError {}
+
+impl std::fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MetadataError::UnknownKeys(keys) => {
+                for (i, (key, suggestion)) in keys.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "unknown key `package.metadata.acap.{}`", key)?;
+                    if let Some(suggestion) = suggestion {
+                        write!(f, " (did you mean `{}`?)", suggestion)?;
+                    }
+                }
+                Ok(())
+            }
+            MetadataError::Invalid { key, message } if key.is_empty() => {
+                write!(f, "package.metadata.acap: {}", message)
+            }
+            MetadataError::Invalid { key, message } => {
+                write!(f, "package.metadata.acap.{}: {}", key, message)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const PACKAGE: &str = "[package]\nname = \"app\"\nversion = \"0.1.0\"\n";
+
+    #[test]
+    fn for_package_rejects_metadata_which_is_not_a_table() {
+        for value in ["\"x\"", "1", "[1, 2]"] {
+            let manifest = format!("{}\n[package.metadata]\nacap = {}\n", PACKAGE, value);
+            let (_dir, package) = testing::package(&manifest, &[]);
+            match CargoAcapMetadata::for_package(&package) {
+                Err(Error::Metadata { path, source }) => {
+                    assert_eq!(path, package.manifest_path());
+                    assert!(
+                        matches!(source, MetadataError::Invalid { .. }),
+                        "{}",
+                        source
+                    );
+                }
+                other => panic!("expected a metadata error for {}, got {:?}", value, other),
+            }
+        }
+    }
+
+    #[test]
+    fn for_package_reads_the_acap_table() {
+        let manifest = format!(
+            "{}\n[package.metadata.acap]\napp_name = \"my_app\"\n",
+            PACKAGE
+        );
+        let (_dir, package) = testing::package(&manifest, &[]);
+        let metadata = CargoAcapMetadata::for_package(&package).unwrap();
+        assert_eq!(metadata.app_name.as_deref(), Some("my_app"));
+
+        let (_dir, package) = testing::package(PACKAGE, &[]);
+        let metadata = CargoAcapMetadata::for_package(&package).unwrap();
+        assert_eq!(metadata.app_name, None);
+    }
+
+    fn parse(text: &str) -> CargoAcapMetadata {
+        CargoAcapMetadata::parse(toml::from_str(text).unwrap()).unwrap()
+    }
+
+    const TARGETED: &str = r#"
+launch_arguments = "--global"
+strip_args = ["--strip-debug"]
+required_embedded_development_version = "2.0"
+max_eap_size = "8 MiB"
+max_binary_size = 1000
+rustflags = ["-C", "opt-level=z"]
+
+[target.aarch64]
+launch_arguments = "--aarch64"
+strip_args = ["--strip-all"]
+required_embedded_development_version = "3.0"
+max_eap_size = "16 MiB"
+rustflags = ["-C", "target-cpu=cortex-a53"]
+docker_image = "example/aarch64"
+
+[target.mips]
+max_binary_size = 2000
+"#;
+
+    #[test]
+    fn resolved_for_replaces_scalars_with_the_targets() {
+        let metadata = parse(TARGETED);
+
+        let aarch64 = metadata.resolved_for(Target::Aarch64);
+        assert_eq!(aarch64.launch_arguments.as_deref(), Some("--aarch64"));
+        assert_eq!(
+            aarch64.strip_args.as_deref(),
+            Some(&["--strip-all".to_string()][..])
+        );
+        assert_eq!(
+            aarch64.required_embedded_development_version.as_deref(),
+            Some("3.0")
+        );
+        assert_eq!(aarch64.max_eap_size, Some(ByteSize(16 << 20)));
+        assert_eq!(aarch64.max_binary_size, Some(ByteSize(1000)));
+        assert_eq!(
+            aarch64.docker_image(Target::Aarch64),
+            Some("example/aarch64")
+        );
+        assert_eq!(
+            aarch64.launch_arguments(Target::Aarch64),
+            Some(("target.aarch64.launch_arguments".to_string(), "--aarch64"))
+        );
+
+        let mips = metadata.resolved_for(Target::Mips);
+        assert_eq!(mips.launch_arguments.as_deref(), Some("--global"));
+        assert_eq!(
+            mips.required_embedded_development_version.as_deref(),
+            Some("2.0")
+        );
+        assert_eq!(mips.max_eap_size, Some(ByteSize(8 << 20)));
+        assert_eq!(mips.max_binary_size, Some(ByteSize(2000)));
+        assert_eq!(mips.docker_image(Target::Mips), None);
+        assert_eq!(
+            mips.launch_arguments(Target::Mips),
+            Some(("launch_arguments".to_string(), "--global"))
+        );
+    }
+
+    #[test]
+    fn resolved_for_extends_the_global_rustflags() {
+        let metadata = parse(TARGETED);
+
+        let aarch64 = metadata.resolved_for(Target::Aarch64);
+        let expected = ["-C", "opt-level=z", "-C", "target-cpu=cortex-a53"];
+        assert_eq!(aarch64.rustflags(Target::Aarch64), expected);
+        // Resolving again must not add the target's flags twice
+        assert_eq!(
+            aarch64
+                .resolved_for(Target::Aarch64)
+                .rustflags(Target::Aarch64),
+            expected
+        );
+
+        let mips = metadata.resolved_for(Target::Mips);
+        assert_eq!(mips.rustflags(Target::Mips), ["-C", "opt-level=z"]);
+
+        let only_targeted = parse("[target.mips]\nrustflags = [\"-g\"]\n");
+        assert_eq!(
+            only_targeted
+                .resolved_for(Target::Mips)
+                .rustflags(Target::Mips),
+            ["-g"]
+        );
+    }
+
+    #[test]
+    fn resolved_for_keeps_only_the_targets_table() {
+        let resolved = parse(TARGETED).resolved_for(Target::Mips);
+        let targets = resolved.target.as_ref().unwrap();
+        assert_eq!(targets.keys().collect::<Vec<_>>(), [&Target::Mips]);
+        assert_eq!(resolved.docker_image(Target::Aarch64), None);
+    }
+
+    #[test]
+    fn unix_user_and_group_must_be_ones_products_accept() {
+        for key in ["unix_user", "unix_group"] {
+            for value in ["sdk", "root"] {
+                let metadata = parse(&format!("{} = {:?}\n", key, value));
+                let field = match key {
+                    "unix_user" => &metadata.unix_user,
+                    _ => &metadata.unix_group,
+                };
+                assert_eq!(field.as_deref(), Some(value));
+            }
+            for value in ["", "nobody", "SDK", "sdk "] {
+                let table = toml::from_str(&format!("{} = {:?}\n", key, value)).unwrap();
+                match CargoAcapMetadata::parse(table) {
+                    Err(MetadataError::Invalid { key: k, message }) => {
+                        assert_eq!(k, key);
+                        assert!(message.contains("\"sdk\", \"root\""), "{}", message);
+                    }
+                    other => panic!(
+                        "expected {} = {:?} to be invalid, got {:?}",
+                        key, value, other
+                    ),
+                }
+            }
+        }
+    }
 }
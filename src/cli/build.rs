@@ -1,297 +1,2733 @@
-use crate::cli::Invocation;
-use crate::package_dot_conf::PackageDotConf;
-use crate::target::Target;
+use crate::bloat::{Analyzer, Bloat, CargoBloatOutput};
+use crate::build_info::{utc_timestamp, BuildInfo};
+use crate::cargo_config::{ByteSize, CargoAcapMetadata, Library};
+use crate::cli::environment::copy_lines;
+use crate::cli::{package, verify, BuildEnvironment, Invocation};
+use crate::eap::{entry_name, read_eap, Compression, EapBuilder, Source};
+use crate::error::{Error, IoResultExt};
+use crate::interrupt;
+use crate::manifest_dot_json::ManifestDotJson;
+use crate::output::{self, status, Style};
+use crate::package_dot_conf::{files_in, normalized_app_name, Lint, PackageDotConf};
+use crate::param_dot_conf::ParamDotConf;
+use crate::target::{Target, SOC};
+use cargo::util::toml::{StringOrBool, TomlProfile};
 use clap::Parser;
-use std::io::Write;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
 use std::fs::File;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 
 /// Build an ACAP application
 #[derive(Parser)]
 pub struct Build {
-    /// Which target(s) to build (defaults to all)
+    /// Which target(s) to build, by name, triple, or SoC (defaults to `targets` in
+    /// `[package.metadata.acap]`, or all)
     #[clap(short, long, alias = "target")]
     targets: Vec<Target>,
 
+    /// Target(s) not to build, even if otherwise selected
+    #[clap(long, value_name = "TARGET")]
+    exclude_target: Vec<Target>,
+
     #[clap(short = 'v', long = "version")]
     show_version: bool,
+
+    #[clap(flatten)]
+    options: BuildOptions,
+
+    /// Build every workspace member which has `[package.metadata.acap]`
+    #[clap(long)]
+    workspace: bool,
+
+    /// Remove each target's build directory before building it
+    #[clap(long)]
+    clean: bool,
+
+    /// Write a SHA-256 checksum file next to each `.eap`
+    #[clap(long)]
+    checksum: bool,
+
+    /// Continue building the remaining targets after one fails, then summarize
+    #[clap(long)]
+    keep_going: bool,
+
+    /// The output format for messages about built artifacts
+    #[clap(long, arg_enum, value_name = "FMT", default_value = "human")]
+    message_format: MessageFormat,
+
+    /// Build up to this many targets concurrently
+    #[clap(long, value_name = "N", default_value = "1")]
+    jobs_targets: usize,
+
+    /// Also write `cargo`'s HTML report of how long each crate took to compile, under
+    /// `target/acap/`
+    #[clap(long)]
+    timings: bool,
+
+    /// Fail, rather than warn, about likely mistakes in the package's configuration, or a
+    /// profile which makes packages larger or slower than they need to be
+    #[clap(long)]
+    strict: bool,
+
+    /// Warn, rather than fail, if a package exceeds `max_eap_size` or `max_binary_size`
+    #[clap(long)]
+    no_size_check: bool,
+
+    /// Build and package the specified example instead of a binary, under `examples/` in the
+    /// output directory
+    #[clap(long, value_name = "NAME", conflicts_with_all = &["bin", "workspace"])]
+    example: Option<String>,
+
+    /// The app name for the packaged --example (defaults to `<package>_<example>`)
+    #[clap(long, value_name = "NAME", requires = "example")]
+    app_name: Option<String>,
+
+    /// Skip targets whose `.eap` was built from the same sources, `Cargo.lock`, metadata,
+    /// options, and Docker image, as recorded in a `.eap.fingerprint` file beside it
+    #[clap(long)]
+    skip_unchanged: bool,
+
+    /// Rebuild every target, even if --skip-unchanged finds it up to date
+    #[clap(long)]
+    force: bool,
+
+    /// Stop after compiling and stripping each target, writing its `.elf` but no `.eap`
+    #[clap(long, conflicts_with_all = &["checksum", "skip-unchanged"])]
+    no_package: bool,
+
+    /// Package each target's most recently built executable instead of compiling it, e.g. after
+    /// changing only metadata
+    #[clap(long, conflicts_with_all = &["no-package", "clean", "skip-unchanged", "timings"])]
+    package_only: bool,
+
+    /// Print the crates and functions taking the most space in each target's executable,
+    /// measured by `cargo bloat` if it's installed, or else from the executable's symbol table
+    #[clap(long)]
+    bloat: bool,
+
+    /// How many crates and functions --bloat lists
+    #[clap(long, value_name = "N", default_value = "10", requires = "bloat")]
+    bloat_top: usize,
+
+    /// Also name each target's `.eap` after every SoC using that target, e.g. `_artpec7` for
+    /// armv7hf, as hard links where possible
+    #[clap(long, conflicts_with = "no-package")]
+    soc_artifacts: bool,
+}
+
+/// Options controlling how a package is built, shared by subcommands which build first
+#[derive(Debug, Clone, Parser)]
+pub struct BuildOptions {
+    /// Build using the locally installed toolchain instead of a `docker` container
+    #[clap(long)]
+    no_docker: bool,
+
+    /// Build artifacts with the specified `cargo` profile (defaults to `release`)
+    #[clap(long, value_name = "PROFILE-NAME", conflicts_with = "debug")]
+    profile: Option<String>,
+
+    /// Build artifacts in debug mode, i.e. `--profile dev`
+    #[clap(long)]
+    debug: bool,
+
+    /// Build only the specified binary
+    #[clap(long, value_name = "NAME")]
+    bin: Option<String>,
+
+    #[clap(flatten)]
+    features: FeatureOptions,
+
+    #[clap(flatten)]
+    manifest_options: ManifestOptions,
+
+    /// What to do with the executable's debug info
+    #[clap(long, arg_enum, value_name = "MODE", default_value = "strip")]
+    debug_info: DebugInfo,
+
+    /// Package the executable as built, without stripping it, i.e. `--debug-info keep`
+    #[clap(long, conflicts_with = "debug-info")]
+    no_strip: bool,
+
+    /// How hard to gzip the `.eap`, from 0 (store only) to 9 (defaults to the package's
+    /// `compression` metadata, or 6)
+    #[clap(long, value_name = "0-9|fast|best")]
+    compression: Option<Compression>,
+
+    /// Write the `.eap` and other artifacts to this directory instead of `target/acap`
+    #[clap(long, value_name = "PATH")]
+    out_dir: Option<PathBuf>,
+
+    /// Fail if compiling produces warnings, by building with `-D warnings` after any other
+    /// rustflags
+    #[clap(long)]
+    deny_warnings: bool,
+}
+
+/// What to do with the executable's debug info when packaging it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum DebugInfo {
+    /// Strip it, along with every other symbol
+    Strip,
+    /// Package the executable as built, symbols and all
+    Keep,
+    /// Strip it into a separate `.debug` file, which the packaged executable names in a GNU
+    /// debuglink
+    Split,
+}
+
+impl BuildOptions {
+    /// The `cargo` profile selected by `--profile` or `--debug`.
+    pub fn profile(&self) -> &str {
+        match (&self.profile, self.debug) {
+            (Some(profile), _) => profile,
+            (None, true) => "dev",
+            (None, false) => "release",
+        }
+    }
+
+    /// What to do with the executable's debug info, as selected by `--debug-info` or
+    /// `--no-strip`.
+    pub fn debug_info(&self) -> DebugInfo {
+        if self.no_strip {
+            DebugInfo::Keep
+        } else {
+            self.debug_info
+        }
+    }
+
+    /// The arguments to pass to `cargo` to select features and apply lockfile and network
+    /// restrictions.
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut args = self.features.cargo_args();
+        args.extend(
+            self.manifest_options
+                .cargo_args()
+                .into_iter()
+                .map(String::from),
+        );
+        args
+    }
+
+    /// Apply the package's `metadata` and these options to `environment`, forwarding variables
+    /// and cutting off the network if building offline.
+    pub(crate) fn configure_environment(
+        &self,
+        environment: BuildEnvironment,
+        invocation: &Invocation,
+        metadata: &CargoAcapMetadata,
+    ) -> BuildEnvironment {
+        let environment = environment.forward_env(
+            metadata
+                .forward_env
+                .iter()
+                .flatten()
+                .chain(&invocation.global_options().forward_env),
+        );
+        if self.manifest_options.offline() {
+            environment.without_network()
+        } else {
+            environment
+        }
+    }
+
+    /// The environment in which subcommands other than `build` run `cargo`, configured like
+    /// `configure_environment()`, making sure the Docker image is available.
+    pub(crate) fn cargo_environment(
+        &self,
+        invocation: &Invocation,
+        metadata: &CargoAcapMetadata,
+    ) -> Result<BuildEnvironment, Error> {
+        let environment = self.configure_environment(
+            invocation.build_environment(self.no_docker),
+            invocation,
+            metadata,
+        );
+        if !self.no_docker {
+            environment.ensure_image(invocation.global_options().pull)?;
+        }
+        environment.ensure_sccache()?;
+        Ok(environment)
+    }
+
+    /// The environment in which to build `target`, which may have its own Docker image, and its
+    /// own rustflags, which `cargo` reads from its environment.
+    pub(crate) fn target_environment(
+        &self,
+        invocation: &Invocation,
+        metadata: &CargoAcapMetadata,
+        environment: &BuildEnvironment,
+        target: Target,
+    ) -> Result<BuildEnvironment, Error> {
+        let global_options = invocation.global_options();
+        let mut target_environment = environment.clone();
+
+        let docker_image = invocation.docker_image_for(target, metadata);
+        if !self.no_docker && docker_image != environment.docker_image() {
+            status!(
+                "cargo-acap: using Docker image {} for target {}",
+                docker_image,
+                target.name()
+            );
+            target_environment = target_environment.with_docker_image(docker_image);
+            target_environment.ensure_image(global_options.pull)?;
+            target_environment.ensure_sccache()?;
+        }
+
+        let mut rustflags: Vec<&str> = metadata.rustflags(target);
+        if self.deny_warnings {
+            rustflags.push("-Dwarnings");
+        }
+        if !rustflags.is_empty() {
+            let rustflags = merged_rustflags(
+                target_environment.rustflags(),
+                || invocation.configured_rustflags(target),
+                rustflags,
+            )?;
+            if global_options.verbose > 0 {
+                eprintln!(
+                    "cargo-acap: building target {} with rustflags: {}",
+                    target.name(),
+                    rustflags.join(" ")
+                );
+            }
+            target_environment =
+                target_environment.with_env("CARGO_ENCODED_RUSTFLAGS", rustflags.join("\x1f"));
+        }
+
+        Ok(target_environment)
+    }
+
+    /// The name of the binary to build, as selected by `--bin` or by the package's layout.
+    pub fn binary_name<'a>(&'a self, invocation: &'a Invocation) -> Result<&'a str, Error> {
+        invocation.binary_name(self.bin.as_deref())
+    }
+
+    /// The directory to which artifacts are written: the one given by `--out-dir`, or else
+    /// `target/acap`.
+    pub fn out_dir(&self, invocation: &Invocation) -> PathBuf {
+        self.out_dir
+            .clone()
+            .unwrap_or_else(|| invocation.acap_target())
+    }
+
+    /// The path of the `.eap` which building the invocation's current package for `target`
+    /// produces.
+    pub fn eap_path(&self, invocation: &Invocation, target: Target) -> Result<PathBuf, Error> {
+        let package_conf = PackageDotConf::from_cargo_package(
+            &invocation.cargo_package,
+            self.binary_name(invocation)?,
+            None,
+            target,
+        )?;
+        let metadata = CargoAcapMetadata::for_package(&invocation.cargo_package)?;
+        Ok(self.out_dir(invocation).join(artifact_file_name(
+            metadata.artifact_name.as_deref(),
+            &package_conf.app_name,
+            &invocation.package_version()?,
+            target,
+            None,
+            self.profile(),
+            ".eap",
+        )))
+    }
+}
+
+/// Warn about `lints`, or with `strict`, fail, unless `metadata` allows them.
+pub(crate) fn report_lints(
+    lints: &[Lint],
+    metadata: &CargoAcapMetadata,
+    strict: bool,
+) -> Result<(), Error> {
+    let allow = metadata.allow.as_deref().unwrap_or_default();
+    let lints: Vec<&Lint> = lints
+        .iter()
+        .filter(|lint| !allow.iter().any(|id| id == lint.id))
+        .collect();
+    if lints.is_empty() {
+        return Ok(());
+    }
+
+    if strict {
+        let messages: Vec<String> = lints
+            .iter()
+            .map(|lint| format!("{} [{}]", lint.message, lint.id))
+            .collect();
+        return Err(Error::Package(messages.join("\n")));
+    }
+    for lint in lints {
+        eprintln!(
+            "cargo-acap: warning: {} [{}]: to allow this, add \"{}\" to allow in \
+             [package.metadata.acap]",
+            lint.message, lint.id, lint.id
+        );
+    }
+    Ok(())
+}
+
+/// The file name of an artifact of building `app_name` for `target`, as named by `template` if
+/// given, followed by `suffix`.
+///
+/// Given a `soc` using `target`, this names the artifact after the SoC instead, in place of
+/// `{target}`.
+fn artifact_file_name(
+    template: Option<&str>,
+    app_name: &str,
+    version: &str,
+    target: Target,
+    soc: Option<SOC>,
+    profile: &str,
+    suffix: &str,
+) -> String {
+    let target_name = soc.map_or(target.name(), |soc| soc.alias());
+    if let Some(template) = template {
+        // The metadata's validation ensures these are the only placeholders
+        return template
+            .replace("{app_name}", app_name)
+            .replace("{version}", version)
+            .replace("{target}", target_name)
+            .replace("{triple}", target.rust_target_triple())
+            .replace("{profile}", profile)
+            + suffix;
+    }
+
+    // Name non-release artifacts after their profile, so they don't overwrite release builds
+    let profile = if profile == "release" {
+        String::new()
+    } else {
+        format!("_{}", profile)
+    };
+
+    format!(
+        "{}_{}_{}{}{}",
+        app_name, version, target_name, profile, suffix
+    )
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum MessageFormat {
+    /// Messages for people, on stderr
+    Human,
+    /// Messages for people on stderr, plus one JSON object per line on stdout for tools
+    Json,
+}
+
+/// A machine-readable message, emitted on stdout with `--message-format json`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+enum Message<'a> {
+    /// A target was built successfully
+    AcapArtifact {
+        package: &'a str,
+        target: Target,
+        triple: &'a str,
+        version: &'a str,
+        /// Absent with `--no-package`, as is `size`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        eap: Option<&'a Path>,
+        elf: &'a Path,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        debug: Option<&'a Path>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        size: Option<u64>,
+        warnings: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha256: Option<&'a str>,
+        timings: Timings,
+        /// Present with `--bloat`, if the executable could be measured
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bloat: Option<&'a Bloat>,
+        /// Each SoC's copy of `eap`, by the SoC's alias, with SoC artifacts
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        soc_eaps: BTreeMap<&'a str, &'a Path>,
+    },
+    /// A summary of every target, emitted with `--keep-going`
+    BuildSummary { targets: Vec<SummaryEntry<'a>> },
+    /// The build is over; no more messages follow
+    BuildFinished { success: bool },
+}
+
+#[derive(Debug, Serialize)]
+struct SummaryEntry<'a> {
+    package: &'a str,
+    target: Target,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    eap: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Feature selection, passed through to `cargo`
+#[derive(Debug, Clone, Parser)]
+pub struct FeatureOptions {
+    /// Space or comma separated list of features to activate
+    #[clap(short = 'F', long, multiple_occurrences = true)]
+    features: Vec<String>,
+
+    /// Activate all available features
+    #[clap(long)]
+    all_features: bool,
+
+    /// Do not activate the `default` feature
+    #[clap(long)]
+    no_default_features: bool,
+}
+
+impl FeatureOptions {
+    /// The requested features, split apart like `cargo` does.
+    pub fn features(&self) -> Vec<&str> {
+        self.features
+            .iter()
+            .flat_map(|s| s.split(|c: char| c == ',' || c.is_whitespace()))
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    /// The arguments to pass to `cargo` to select these features.
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let features = self.features();
+        if !features.is_empty() {
+            args.push("--features".to_string());
+            args.push(features.join(","));
+        }
+        if self.all_features {
+            args.push("--all-features".to_string());
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".to_string());
+        }
+        args
+    }
+
+    /// A human-readable description of the selected features.
+    pub fn describe(&self) -> String {
+        let mut description = if self.all_features {
+            "all features".to_string()
+        } else if self.no_default_features {
+            "no default features".to_string()
+        } else {
+            "default features".to_string()
+        };
+        let features = self.features();
+        if !features.is_empty() {
+            description += &format!(" plus {}", features.join(", "));
+        }
+        description
+    }
+}
+
+/// Lockfile and network restrictions, passed through to `cargo`
+#[derive(Debug, Clone, Parser)]
+pub struct ManifestOptions {
+    /// Require Cargo.lock and the dependency cache to be up to date, i.e. --locked and --offline
+    #[clap(long)]
+    frozen: bool,
+
+    /// Require Cargo.lock to be up to date
+    #[clap(long)]
+    locked: bool,
+
+    /// Build without accessing the network, which `docker` builds enforce with `--network none`
+    #[clap(long)]
+    offline: bool,
+}
+
+impl ManifestOptions {
+    /// Whether builds must not access the network.
+    pub fn offline(&self) -> bool {
+        self.offline || self.frozen
+    }
+
+    /// Whether Cargo.lock must not change.
+    pub fn locked(&self) -> bool {
+        self.locked || self.frozen
+    }
+
+    /// The arguments to pass to `cargo` to apply these restrictions.
+    pub fn cargo_args(&self) -> Vec<&'static str> {
+        let mut args = Vec::new();
+        if self.frozen {
+            args.push("--frozen");
+        }
+        if self.locked {
+            args.push("--locked");
+        }
+        if self.offline {
+            args.push("--offline");
+        }
+        args
+    }
+}
+
+impl Build {
+    /// The name of the example selected by `--example`, or else of the binary to build.
+    fn binary_name<'a>(&'a self, invocation: &'a Invocation) -> Result<&'a str, Error> {
+        let example = match &self.example {
+            Some(example) => example.as_str(),
+            None => return self.options.binary_name(invocation),
+        };
+        let examples: Vec<&str> = invocation
+            .cargo_package
+            .targets()
+            .iter()
+            .filter(|t| t.is_exe_example())
+            .map(|t| t.name())
+            .collect();
+        if examples.contains(&example) {
+            return Ok(example);
+        }
+
+        let mut message = format!("no example named `{}`", example);
+        if !examples.is_empty() {
+            message += "\nuse --example to specify one of:";
+            for example in examples {
+                message += &format!("\n  * {}", example);
+            }
+        }
+        Err(Error::Usage(message))
+    }
+
+    /// The app name for the package, if not the metadata's: `--app-name`, or for an example,
+    /// the package's name and the example's, so that it can't be mistaken for the application.
+    fn app_name(&self, invocation: &Invocation) -> Option<String> {
+        let example = self.example.as_ref()?;
+        Some(self.app_name.clone().unwrap_or_else(|| {
+            normalized_app_name(&format!("{}_{}", invocation.cargo_package_name(), example))
+        }))
+    }
+
+    /// Whether `--workspace` was requested.
+    pub(crate) fn workspace(&self) -> bool {
+        self.workspace
+    }
+
+    /// Build `targets` as part of another subcommand, e.g. `install`.
+    pub fn for_targets(targets: Vec<Target>, options: BuildOptions) -> Self {
+        Build {
+            targets,
+            exclude_target: vec![],
+            show_version: false,
+            options,
+            workspace: false,
+            clean: false,
+            checksum: false,
+            keep_going: false,
+            message_format: MessageFormat::Human,
+            jobs_targets: 1,
+            timings: false,
+            strict: false,
+            no_size_check: false,
+            skip_unchanged: false,
+            force: false,
+            no_package: false,
+            package_only: false,
+            bloat: false,
+            bloat_top: 10,
+            soc_artifacts: false,
+            example: None,
+            app_name: None,
+        }
+    }
+
+    /// Leave `targets` out, even if otherwise selected, as if by `--exclude-target`.
+    pub fn excluding(mut self, targets: Vec<Target>) -> Self {
+        self.exclude_target = targets;
+        self
+    }
+
+    pub(crate) fn invoke(self, mut invocation: Invocation) -> Result<(), Error> {
+        let result = self.build_all(&mut invocation);
+        self.emit(Message::BuildFinished {
+            success: result.is_ok(),
+        });
+        result
+    }
+
+    /// Warn, or with `--strict` fail, if the profile being built would make packages larger or
+    /// slower on devices than they need to be.
+    ///
+    /// `cargo`'s own defaults for `release` are checked too, since most of them favor build time
+    /// over size.
+    fn check_profile(&self, invocation: &Invocation) -> Result<(), Error> {
+        let name = self.options.profile();
+
+        // Follow `inherits` to the built-in profile, taking each setting from the first profile
+        // which sets it
+        let mut chain = vec![];
+        let mut base = name;
+        while let Some(profile) = invocation.profile(base) {
+            chain.push(profile);
+            match profile.inherits.as_deref() {
+                Some(parent) if chain.len() <= 16 => base = parent,
+                _ => break,
+            }
+        }
+        if base != "release" && base != "bench" {
+            // Debug builds are meant to be unoptimized
+            return Ok(());
+        }
+        let (problems, fixes): (Vec<_>, Vec<_>) = profile_problems(&chain).into_iter().unzip();
+        if problems.is_empty() {
+            return Ok(());
+        }
+
+        let message = format!(
+            "profile `{}` makes packages larger and slower on devices than they need to be, \
+             since {}: add this to {}, or set suppress_profile_warnings = true in \
+             [package.metadata.acap]\n\n[profile.{}]\n{}",
+            name,
+            problems.join(", "),
+            invocation.root_manifest_path().display(),
+            name,
+            fixes.join("\n")
+        );
+        if self.strict {
+            return Err(Error::Package(message));
+        }
+        eprintln!("cargo-acap: warning: {}", message);
+        Ok(())
+    }
+
+    /// Build the current package, or with `--workspace`, every member with ACAP metadata.
+    fn build_all(&self, invocation: &mut Invocation) -> Result<(), Error> {
+        self.announce(invocation)?;
+
+        if !self.workspace {
+            let outcomes = self.build_package(invocation)?;
+            print_timings(&outcomes, false);
+            return self.finish(outcomes).map(|_| ());
+        }
+
+        let mut outcomes = Vec::new();
+        let members = invocation.workspace_members().to_vec();
+        for member in members {
+            if interrupt::interrupted() {
+                break;
+            }
+            if CargoAcapMetadata::table(&member).is_none() {
+                status!(
+                    "cargo-acap: skipping package `{}`: no [package.metadata.acap]",
+                    member.name()
+                );
+                continue;
+            }
+            if !member.targets().iter().any(|t| t.is_bin()) {
+                status!(
+                    "cargo-acap: skipping package `{}`: no binaries",
+                    member.name()
+                );
+                continue;
+            }
+
+            status!("cargo-acap: building package `{}`", member.name());
+            invocation.select_member(member);
+            outcomes.extend(self.build_package(invocation)?);
+        }
+        print_timings(&outcomes, true);
+        self.finish(outcomes).map(|_| ())
+    }
+
+    /// Build the invocation's current package, returning the `.eap` built for each target.
+    pub(crate) fn build_eaps(&self, invocation: &Invocation) -> Result<Vec<PathBuf>, Error> {
+        self.announce(invocation)?;
+        let outcomes = self.build_package(invocation)?;
+        let artifacts = self.finish(outcomes)?;
+        Ok(artifacts
+            .into_iter()
+            .filter_map(|artifacts| artifacts.eap)
+            .collect())
+    }
+
+    /// Describe the toolchain and features with which we're about to build, making sure the
+    /// Docker image is available.
+    fn announce(&self, invocation: &Invocation) -> Result<(), Error> {
+        let global_options = invocation.global_options();
+
+        let environment = self.environment(invocation);
+        if self.options.no_docker {
+            status!("cargo-acap: using the host toolchain");
+        } else {
+            status!(
+                "cargo-acap: using Docker image {}",
+                &global_options.docker_image
+            );
+            if global_options.verbose > 0 {
+                eprintln!(
+                    "cargo-acap: using container engine {}",
+                    environment.container_engine().program()
+                );
+            }
+            environment.ensure_image(global_options.pull)?;
+        }
+        environment.ensure_sccache()?;
+
+        if self.options.no_docker && (self.show_version || global_options.verbose > 0) {
+            let mut rustc = environment.host_command("rustc");
+            rustc.arg("--version");
+            environment.run(rustc, None)?;
+        } else if self.show_version || global_options.verbose > 0 {
+            let mut docker = environment.engine_command();
+            docker.args(["images", &global_options.docker_image]);
+            environment.run(docker, None)?;
+
+            let mut docker = environment.command("rustc")?;
+            docker.arg("version");
+            environment.run(docker, None)?;
+        }
+
+        if global_options.verbose > 0 {
+            eprintln!(
+                "cargo-acap: building with {}",
+                self.options.features.describe()
+            );
+        }
+        Ok(())
+    }
+
+    /// The environment in which to build the invocation's current package.
+    fn environment(&self, invocation: &Invocation) -> BuildEnvironment {
+        // Keep stdout free for messages, e.g. so that `docker pull`'s progress doesn't end up in
+        // a file of them
+        invocation
+            .build_environment(self.options.no_docker)
+            .stdout_to_stderr()
+    }
+
+    /// Write `message` to stdout if building with `--message-format json`.
+    fn emit(&self, message: Message) {
+        if self.message_format == MessageFormat::Json {
+            let json = serde_json::to_string(&message).expect("error serializing message");
+            println!("{}", json);
+        }
+    }
+
+    /// Summarize `outcomes` if building with `--keep-going`, and return every target's artifacts
+    /// if they all succeeded.
+    fn finish(&self, outcomes: Vec<TargetOutcome>) -> Result<Vec<Artifacts>, Error> {
+        if self.keep_going && !output::quiet() {
+            eprintln!();
+            super::targets::print_table(
+                std::io::stderr(),
+                ["Target", "Status", "Artifact or error"],
+                outcomes.iter().map(|outcome| match &outcome.result {
+                    Ok(artifacts) => vec![
+                        outcome.target.name().to_string(),
+                        "ok".to_string(),
+                        artifacts
+                            .eap
+                            .as_ref()
+                            .unwrap_or(&artifacts.elf)
+                            .display()
+                            .to_string(),
+                    ],
+                    Err(e) => vec![
+                        outcome.target.name().to_string(),
+                        "failed".to_string(),
+                        e.to_string(),
+                    ],
+                }),
+            );
+
+            self.emit(Message::BuildSummary {
+                targets: outcomes
+                    .iter()
+                    .map(|outcome| SummaryEntry {
+                        package: &outcome.package,
+                        target: outcome.target,
+                        success: outcome.result.is_ok(),
+                        eap: outcome.result.as_ref().ok().and_then(|a| a.eap.as_deref()),
+                        error: outcome.result.as_ref().err().map(|e| e.to_string()),
+                    })
+                    .collect(),
+            });
+        }
+
+        Ok(self
+            .check(outcomes)?
+            .into_iter()
+            .filter_map(|outcome| outcome.result.ok())
+            .collect())
+    }
+
+    /// Return `outcomes` if every target succeeded, or else why the build failed.
+    fn check(&self, outcomes: Vec<TargetOutcome>) -> Result<Vec<TargetOutcome>, Error> {
+        let total = outcomes.len();
+        let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+        if failed == 0 {
+            return Ok(outcomes);
+        }
+
+        // A lone failure hasn't been reported yet, so report it in full
+        if failed == 1 && !self.keep_going && self.jobs_targets <= 1 {
+            if let Some(e) = outcomes.into_iter().find_map(|o| o.result.err()) {
+                return Err(e);
+            }
+        }
+        Err(Error::TargetsFailed { failed, total })
+    }
+
+    /// The targets to build for the invocation's current package.
+    ///
+    /// These are the targets given by `--targets`, or else by `targets` in the package's
+    /// `[package.metadata.acap]`, or else all targets, minus any given by `--exclude-target`.
+    pub(crate) fn targets(&self, invocation: &Invocation) -> Result<Vec<Target>, Error> {
+        let (targets, source) = if !self.targets.is_empty() {
+            (self.targets.clone(), "--targets")
+        } else if let Some(targets) =
+            CargoAcapMetadata::for_package(&invocation.cargo_package)?.targets
+        {
+            (targets, "[package.metadata.acap] targets")
+        } else {
+            (Target::all().to_vec(), "all targets")
+        };
+
+        let targets: Vec<Target> = targets
+            .into_iter()
+            .filter(|t| !self.exclude_target.contains(t))
+            .collect();
+
+        if invocation.global_options().verbose > 0 {
+            let names: Vec<&str> = targets.iter().map(|t| t.name()).collect();
+            let excluded: Vec<&str> = self.exclude_target.iter().map(|t| t.name()).collect();
+            eprint!(
+                "cargo-acap: building targets [{}] from {}",
+                names.join(", "),
+                source
+            );
+            if excluded.is_empty() {
+                eprintln!();
+            } else {
+                eprintln!(", excluding [{}]", excluded.join(", "));
+            }
+        }
+
+        if targets.is_empty() {
+            return Err(Error::Usage(format!(
+                "no targets left to build for package `{}`",
+                invocation.cargo_package_name()
+            )));
+        }
+
+        Ok(targets)
+    }
+
+    /// Build the invocation's current package for each of its targets.
+    ///
+    /// Unless building with `--keep-going`, this fails once a target fails.
+    fn build_package(&self, invocation: &Invocation) -> Result<Vec<TargetOutcome>, Error> {
+        let targets = &self.targets(invocation)?;
+        if self.options.no_docker {
+            check_host_toolchain(invocation, targets)?;
+        }
+        let acap_target = invocation.acap_target();
+        let version = invocation.package_version()?;
+        let package_root = invocation.package_root();
+        let manifest_path = invocation.manifest_path();
+        let binary_name = self.binary_name(invocation)?;
+        let app_name = self.app_name(invocation);
+        let jobs = self.jobs_targets.max(1);
+
+        let metadata = CargoAcapMetadata::for_package(&invocation.cargo_package)?;
+        if !metadata.suppress_profile_warnings.unwrap_or(false) {
+            self.check_profile(invocation)?;
+        }
+        let mut environment =
+            self.options
+                .configure_environment(self.environment(invocation), invocation, &metadata);
+
+        // Concurrent builds can't share a terminal
+        if jobs > 1 {
+            environment = environment.non_interactive();
+        }
+
+        // Resolve each target's package.conf up front, since `cargo` packages can't be shared
+        // between threads
+        let package_confs = targets
+            .iter()
+            .map(|&target| {
+                let package_dot_conf = PackageDotConf::from_cargo_package(
+                    &invocation.cargo_package,
+                    binary_name,
+                    app_name.as_deref(),
+                    target,
+                )?;
+                Ok((target, package_dot_conf))
+            })
+            .collect::<Result<Vec<(Target, PackageDotConf)>, Error>>()?;
+        if let Some((_, package_conf)) = package_confs.first() {
+            package_conf.warn_if_app_name_normalized();
+        }
+        let mut lints: Vec<Lint> = vec![];
+        for (_, package_conf) in &package_confs {
+            for lint in package_conf.lints() {
+                if !lints.contains(&lint) {
+                    lints.push(lint);
+                }
+            }
+        }
+        report_lints(&lints, &metadata, self.strict)?;
+
+        let package_conf = &package_confs[0].1;
+        if !package_conf.cgi_paths.is_empty() {
+            for cgi_txt in [
+                package_root.join("cgi.txt"),
+                package_root.join("src/cgi.txt"),
+            ] {
+                if cgi_txt.is_file() {
+                    eprintln!(
+                        "cargo-acap: warning: ignoring {}, since cgi_paths in \
+                         [package.metadata.acap] takes precedence: remove one or the other",
+                        cgi_txt.display()
+                    );
+                }
+            }
+        } else if let Some(path) = &package_conf.http_cgi_paths_path {
+            if path == &package_root.join("src").join("cgi.txt") {
+                eprintln!(
+                    "cargo-acap: warning: {} is deprecated, since src/ is for Rust sources: move \
+                     it to the package root, or set http_cgi_paths_file in [package.metadata.acap]",
+                    path.display()
+                );
+            }
+        }
+        if package_conf.unix_user == "root" || package_conf.unix_group == "root" {
+            eprintln!(
+                "cargo-acap: warning: {} will run as {}:{}, with privileges over the whole \
+                 device: use `sdk` unless it really needs them",
+                package_conf.app_name, package_conf.unix_user, package_conf.unix_group
+            );
+        }
+
+        // SoCs' copies of each package take the SoC's name in place of the target's, so a template
+        // must name the target for them to be told apart
+        let soc_artifacts = self.soc_artifacts || metadata.artifact_per_soc.unwrap_or(false);
+        if let Some(template) = metadata.artifact_name.as_deref() {
+            if soc_artifacts && !template.contains("{target}") {
+                return Err(Error::Package(format!(
+                    "artifact_name {:?} has no {{target}}, which names the copy of each package \
+                     for an SoC after the SoC: include it, or build without --soc-artifacts or artifact_per_soc",
+                    template
+                )));
+            }
+        }
+
+        // Packages for different targets can't share a name
+        let mut eap_names: HashMap<String, Target> = HashMap::new();
+        for (target, package_conf) in &package_confs {
+            let socs: Vec<SOC> = if soc_artifacts {
+                target.socs().collect()
+            } else {
+                vec![]
+            };
+            for soc in std::iter::once(None).chain(socs.into_iter().map(Some)) {
+                let name = artifact_file_name(
+                    metadata.artifact_name.as_deref(),
+                    &package_conf.app_name,
+                    &version,
+                    *target,
+                    soc,
+                    self.options.profile(),
+                    ".eap",
+                );
+                if let Some(other) = eap_names.insert(name.clone(), *target) {
+                    return Err(Error::Package(format!(
+                        "artifact_name {:?} names the packages for targets {} and {} both `{}`: \
+                         include {{target}} or {{triple}}",
+                        metadata.artifact_name.as_deref().unwrap_or_default(),
+                        other.name(),
+                        target.name(),
+                        name
+                    )));
+                }
+            }
+        }
+        // Examples go in a directory of their own, like `cargo` puts them, so that they're never
+        // mistaken for the application
+        let mut out_dir = self.options.out_dir(invocation);
+        if self.example.is_some() {
+            out_dir.push("examples");
+        }
+        std::fs::create_dir_all(&out_dir).with_path(&out_dir)?;
+        let lockfile = invocation.root_manifest_path().with_file_name("Cargo.lock");
+
+        let build_info = metadata
+            .embed_build_info
+            .unwrap_or(false)
+            .then(|| BuildInfo::new(package_root, invocation.rustc_version()));
+
+        let param_conf = ParamDotConf::from_cargo_package(&invocation.cargo_package)?;
+        // Like package.conf, manifest.json can differ between targets, e.g. in its launch arguments
+        let mut manifests = HashMap::new();
+        for (target, package_conf) in &package_confs {
+            let manifest = ManifestDotJson::from_cargo_package(
+                &invocation.cargo_package,
+                package_conf,
+                param_conf.as_ref(),
+            )?;
+            manifests.insert(*target, manifest);
+        }
+
+        let manifest_options = &self.options.manifest_options;
+        if self.package_only {
+            // Nothing is compiled, so there's nothing to fetch
+        } else if let Err(e) = environment.fetch_dependencies(&manifest_options.cargo_args()) {
+            if manifest_options.locked() {
+                eprintln!(
+                    "cargo-acap: `cargo fetch` failed: if Cargo.lock is out of date, update it \
+                     with `cargo update`, or build without --locked or --frozen"
+                );
+            }
+            return Err(e);
+        }
+
+        // `cargo bloat` builds for itself, so it can't measure what was built before
+        let bloat = if !self.bloat {
+            None
+        } else if !self.package_only && cargo_bloat_installed(&environment) {
+            Some(Analyzer::CargoBloat)
+        } else {
+            status!(
+                "cargo-acap: measuring executables by their symbol tables, since {}",
+                if self.package_only {
+                    "--package-only compiles nothing for `cargo bloat` to measure"
+                } else if self.options.no_docker {
+                    "`cargo bloat` is not installed"
+                } else {
+                    "the Docker image does not include `cargo bloat`"
+                }
+            );
+            Some(Analyzer::Symbols)
+        };
+
+        let mut environments = HashMap::new();
+        for &target in targets {
+            let target_environment =
+                self.options
+                    .target_environment(invocation, &metadata, &environment, target)?;
+            invocation.check_image_rustc(&target_environment, self.strict)?;
+            environments.insert(target, target_environment);
+        }
+
+        let target_metadata: HashMap<Target, CargoAcapMetadata> = targets
+            .iter()
+            .map(|&target| (target, metadata.resolved_for(target)))
+            .collect();
+        let build_op = |target: Target, package_conf| BuildOp {
+            environment: &environments[&target],
+            package_conf,
+            param_conf: param_conf.as_ref(),
+            manifest: manifests[&target].as_ref(),
+            binary_name,
+            example: self.example.is_some(),
+            version: &version,
+            package_root,
+            acap_target: &acap_target,
+            out_dir: &out_dir,
+            artifact_name: metadata.artifact_name.as_deref(),
+            manifest_path,
+            profile: self.options.profile(),
+            features: &self.options.features,
+            manifest_options,
+            concurrent: jobs > 1,
+            timings: self.timings,
+            position: (targets.len() > 1).then(|| {
+                let n = targets.iter().position(|t| *t == target).unwrap_or(0) + 1;
+                (n, targets.len())
+            }),
+            clean: self.clean,
+            checksum: self.checksum,
+            debug_info: self.options.debug_info(),
+            strip_args: metadata.strip_args(target),
+            libraries: metadata.libraries.as_deref().unwrap_or_default(),
+            compression: self
+                .options
+                .compression
+                .or(metadata.compression)
+                .unwrap_or_default(),
+            build_info: build_info.as_ref(),
+            max_eap_size: target_metadata[&target].max_eap_size,
+            max_binary_size: target_metadata[&target].max_binary_size,
+            enforce_sizes: !self.no_size_check,
+            skip_unchanged: self.skip_unchanged && !self.force && !self.clean,
+            lockfile: &lockfile,
+            no_package: self.no_package,
+            package_only: self.package_only,
+            bloat,
+            bloat_top: self.bloat_top,
+            soc_artifacts,
+            target,
+        };
+
+        let package_name = invocation.cargo_package_name();
+        let build = |target: Target, package_conf| {
+            let result = build_op(target, package_conf).invoke();
+            if let Some(bloat) = result.as_ref().ok().and_then(|a| a.bloat.as_ref()) {
+                if !output::quiet() {
+                    bloat.print(&format!("target {}", target.name()));
+                }
+            }
+            match &result {
+                Ok(artifacts) => self.emit(Message::AcapArtifact {
+                    package: package_name,
+                    target,
+                    triple: target.rust_target_triple(),
+                    version: &version,
+                    eap: artifacts.eap.as_deref(),
+                    elf: &artifacts.elf,
+                    debug: artifacts.debug.as_deref(),
+                    size: artifacts
+                        .eap
+                        .as_ref()
+                        .map(|eap| std::fs::metadata(eap).map_or(0, |m| m.len())),
+                    sha256: artifacts.sha256.as_deref(),
+                    warnings: artifacts.warnings,
+                    timings: artifacts.timings,
+                    bloat: artifacts.bloat.as_ref(),
+                    soc_eaps: artifacts
+                        .soc_eaps
+                        .iter()
+                        .map(|(soc, eap)| (soc.alias(), eap.as_path()))
+                        .collect(),
+                }),
+                Err(e) if self.keep_going || jobs > 1 => {
+                    eprintln!("cargo-acap: target {} failed: {}", target.name(), e);
+                }
+                Err(_) => {}
+            }
+            TargetOutcome {
+                package: package_name.to_string(),
+                target,
+                result,
+            }
+        };
+
+        let outcomes = if jobs == 1 {
+            let mut outcomes = Vec::new();
+            for (target, package_conf) in &package_confs {
+                let outcome = build(*target, package_conf);
+                let failed = outcome.result.is_err();
+                outcomes.push(outcome);
+                if (failed && !self.keep_going) || interrupt::interrupted() {
+                    break;
+                }
+            }
+            outcomes
+        } else {
+            // Run `jobs` workers, each taking the next target until none remain, until one
+            // fails unless we're keeping going, or until interrupted
+            let queue = Mutex::new(package_confs.iter());
+            let outcomes = Mutex::new(Vec::new());
+            let failed = AtomicBool::new(false);
+            std::thread::scope(|scope| {
+                for _ in 0..jobs.min(package_confs.len()) {
+                    scope.spawn(|| loop {
+                        if (failed.load(Ordering::SeqCst) && !self.keep_going)
+                            || interrupt::interrupted()
+                        {
+                            break;
+                        }
+                        let next = queue.lock().unwrap().next();
+                        let (target, package_conf) = match next {
+                            Some(next) => next,
+                            None => break,
+                        };
+                        let outcome = build(*target, package_conf);
+                        if outcome.result.is_err() {
+                            failed.store(true, Ordering::SeqCst);
+                        }
+                        outcomes.lock().unwrap().push(outcome);
+                    });
+                }
+            });
+
+            // Report in target order, regardless of which finished first
+            let mut outcomes = outcomes.into_inner().unwrap();
+            outcomes.sort_by_key(|outcome| targets.iter().position(|t| *t == outcome.target));
+            outcomes
+        };
+
+        if self.keep_going {
+            Ok(outcomes)
+        } else {
+            self.check(outcomes)
+        }
+    }
+}
+
+/// The result of building one target: its artifacts, or why it failed.
+struct TargetOutcome {
+    package: String,
+    target: Target,
+    result: BuildResult<Artifacts>,
+}
+
+/// The files produced by building one target.
+#[derive(Debug)]
+struct Artifacts {
+    /// The package, unless built with `--no-package`
+    eap: Option<PathBuf>,
+    elf: PathBuf,
+    /// The debug info split out of the executable, if requested
+    debug: Option<PathBuf>,
+    /// The hex SHA-256 digest of `eap`, if requested
+    sha256: Option<String>,
+    timings: Timings,
+    /// How many warnings compiling reported
+    warnings: usize,
+    /// What takes up space in the executable, with `--bloat`
+    bloat: Option<Bloat>,
+    /// Each SoC's copy of `eap`, if requested
+    soc_eaps: Vec<(SOC, PathBuf)>,
+    /// Whether these were built before, and skipped by `--skip-unchanged`
+    up_to_date: bool,
+}
+
+/// How long each phase of building one target took.
+#[derive(Debug, Default, Copy, Clone, Serialize)]
+struct Timings {
+    /// Running `cargo build`
+    #[serde(serialize_with = "serialize_seconds")]
+    compile: Duration,
+    /// Stripping the executable and libraries, and splitting out debug info
+    #[serde(serialize_with = "serialize_seconds")]
+    strip: Duration,
+    /// Writing the `.eap`, and its checksum
+    #[serde(serialize_with = "serialize_seconds")]
+    package: Duration,
+}
+
+impl Timings {
+    fn total(&self) -> Duration {
+        self.compile + self.strip + self.package
+    }
+}
+
+/// Serialize `duration` as fractional seconds.
+fn serialize_seconds<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// Print how long each successfully built target spent in each phase, and how many warnings
+/// compiling it reported, naming their packages if `by_package`.
+fn print_timings(outcomes: &[TargetOutcome], by_package: bool) {
+    let built: Vec<(String, Timings, usize)> = outcomes
+        .iter()
+        .filter_map(|outcome| {
+            let artifacts = outcome.result.as_ref().ok().filter(|a| !a.up_to_date)?;
+            let name = if by_package {
+                format!("{} {}", outcome.package, outcome.target.name())
+            } else {
+                outcome.target.name().to_string()
+            };
+            Some((name, artifacts.timings, artifacts.warnings))
+        })
+        .collect();
+    if built.is_empty() || output::quiet() {
+        return;
+    }
+
+    let totals = built
+        .iter()
+        .fold(Timings::default(), |sum, (_, t, _)| Timings {
+            compile: sum.compile + t.compile,
+            strip: sum.strip + t.strip,
+            package: sum.package + t.package,
+        });
+    let total_warnings = built.iter().map(|(_, _, warnings)| warnings).sum();
+    let row = |name: &str, t: &Timings, warnings: usize| {
+        let seconds = |d: Duration| format!("{:.1}s", d.as_secs_f64());
+        vec![
+            name.to_string(),
+            seconds(t.compile),
+            seconds(t.strip),
+            seconds(t.package),
+            seconds(t.total()),
+            warnings.to_string(),
+        ]
+    };
+
+    eprintln!();
+    super::targets::print_table(
+        std::io::stderr(),
+        ["Target", "Compile", "Strip", "Package", "Total", "Warnings"],
+        built
+            .iter()
+            .map(|(name, timings, warnings)| row(name, timings, *warnings))
+            .chain(Some(row("total", &totals, total_warnings))),
+    );
+}
+
+type BuildResult<T> = Result<T, Error>;
+
+#[derive(Debug)]
+struct BuildOp<'a> {
+    environment: &'a BuildEnvironment,
+    package_conf: &'a PackageDotConf,
+    param_conf: Option<&'a ParamDotConf>,
+    manifest: Option<&'a ManifestDotJson>,
+    binary_name: &'a str,
+    /// Whether `binary_name` names an example rather than a `[[bin]]`
+    example: bool,
+    version: &'a str,
+    package_root: &'a Path,
+    acap_target: &'a Path,
+    /// The directory to which artifacts are written
+    out_dir: &'a Path,
+    /// How artifacts are named, if not the default
+    artifact_name: Option<&'a str>,
+    manifest_path: &'a Path,
+    profile: &'a str,
+    features: &'a FeatureOptions,
+    manifest_options: &'a ManifestOptions,
+    /// Whether other targets are being built at the same time
+    concurrent: bool,
+    /// Whether to have `cargo` write its HTML timings report
+    timings: bool,
+    /// Which of the package's targets this is, counting from 1, and how many there are, if
+    /// there are several
+    position: Option<(usize, usize)>,
+    /// Whether to remove this target's build directory before building
+    clean: bool,
+    /// Whether to write a checksum file for the `.eap`
+    checksum: bool,
+    debug_info: DebugInfo,
+    /// The arguments with which to strip the executable instead of `--strip-all`, if
+    /// configured, and the metadata key which configured them
+    strip_args: Option<(String, &'a [String])>,
+    /// The `cdylib` libraries to build and package alongside the executable
+    libraries: &'a [Library],
+    compression: Compression,
+    /// What to embed about this build, if `embed_build_info` is set
+    build_info: Option<&'a BuildInfo>,
+    /// The largest the `.eap` may be, if limited
+    max_eap_size: Option<ByteSize>,
+    /// The largest the packaged executable may be, if limited
+    max_binary_size: Option<ByteSize>,
+    /// Whether exceeding a size limit fails the build, rather than warning
+    enforce_sizes: bool,
+    /// Whether to skip building if the `.eap` is up to date with its fingerprint
+    skip_unchanged: bool,
+    /// The workspace's `Cargo.lock`, which goes into the fingerprint
+    lockfile: &'a Path,
+    /// Whether to stop once the executable is stripped, without packaging it
+    no_package: bool,
+    /// Whether to package the executable built before, rather than compiling it
+    package_only: bool,
+    /// How to measure what takes up space in the executable, if at all
+    bloat: Option<Analyzer>,
+    /// How many crates and functions to list when measuring it
+    bloat_top: usize,
+    /// Whether to copy the `.eap` for each SoC using the target
+    soc_artifacts: bool,
+    target: Target,
+}
+
+/// A `compiler-artifact` message from `cargo build --message-format=json`.
+#[derive(Deserialize)]
+struct CompilerArtifact {
+    reason: String,
+    target: Option<ArtifactTarget>,
+    #[serde(default)]
+    filenames: Vec<PathBuf>,
+    executable: Option<PathBuf>,
+}
+
+/// A `compiler-message` from `cargo build --message-format=json`.
+#[derive(Deserialize)]
+struct CompilerMessage {
+    reason: String,
+    message: Diagnostic,
+}
+
+/// A diagnostic from `rustc`, as `cargo` reports it.
+#[derive(Deserialize)]
+struct Diagnostic {
+    level: String,
+    /// The diagnostic as `rustc` would have printed it
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ArtifactTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+impl CompilerArtifact {
+    /// Whether this is the artifact of the `kind` target called `name`.
+    fn is(&self, kind: &str, name: &str) -> bool {
+        // `cargo` names targets as written in Cargo.toml, but `-` and `_` are interchangeable
+        self.target.as_ref().is_some_and(|target| {
+            target.name.replace('-', "_") == name.replace('-', "_")
+                && target.kind.iter().any(|k| k == kind)
+        })
+    }
 }
 
-impl Build {
-    pub(crate) fn invoke(self, invocation: Invocation) {
-        let acap_target = invocation.acap_target();
-        let version = invocation.package_version();
-        let global_options = invocation.global_options();
-        let project_source_path = invocation.package_source_path();
+impl<'a> BuildOp<'a> {
+    pub(crate) fn invoke(&self) -> BuildResult<Artifacts> {
+        let started = SystemTime::now();
+        let result = self.build();
+        if result.is_err() && interrupt::interrupted() {
+            self.remove_partial_artifacts(started);
+        }
+        result
+    }
 
-        let targets = if !self.targets.is_empty() {
-            self.targets.clone()
+    fn build(&self) -> BuildResult<Artifacts> {
+        let position = match self.position {
+            Some((n, total)) => format!(" ({}/{})", n, total),
+            None => String::new(),
+        };
+
+        // Any fingerprint describes the artifacts about to be replaced, so it's removed first
+        // and only rewritten once they're complete
+        let fingerprint_path = self.artifact_path(".eap.fingerprint");
+        let fingerprint = if self.skip_unchanged {
+            let fingerprint = self.fingerprint()?;
+            if let Some(mut artifacts) = self.up_to_date(&fingerprint_path, &fingerprint)? {
+                status!(
+                    "cargo-acap: target {}{} skipped (up to date)",
+                    self.target.name(),
+                    position
+                );
+                artifacts.bloat = self.measure_bloat(&artifacts.elf);
+                if let Some(eap) = &artifacts.eap {
+                    artifacts.soc_eaps = self.copy_for_socs(eap)?;
+                }
+                return Ok(artifacts);
+            }
+            Some(fingerprint)
         } else {
-            Target::all().to_vec()
+            None
         };
+        match std::fs::remove_file(&fingerprint_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_path(&fingerprint_path)
+            }
+            _ => {}
+        }
 
-        println!(
-            "cargo-acap: using Docker image {}",
-            &global_options.docker_image
+        status!(
+            "cargo-acap: {} target {}{}",
+            output::paint(
+                Style::Progress,
+                if self.package_only {
+                    "packaging"
+                } else {
+                    "building"
+                }
+            ),
+            self.target.name(),
+            position
         );
+        if self.clean {
+            self.clean()?;
+        }
+        let mut timings = Timings::default();
 
-        if self.show_version || global_options.verbose > 0 {
-            let mut docker = std::process::Command::new("docker");
-            docker.args(&["images", &global_options.docker_image]);
-            invocation.run_to_completion(docker);
-
-            let mut docker = invocation.docker_run_command();
-            docker.args(&["rustc", "version"]);
-            invocation.run_to_completion(docker);
-        }
-
-        for target in targets {
-            let package_dot_conf =
-                PackageDotConf::from_cargo_package(&invocation.cargo_package, target);
-            BuildOp {
-                invocation: &invocation,
-                package_conf: &package_dot_conf,
-                cargo_package_name: invocation.cargo_package_name(),
-                version: &version,
-                project_source_path: &project_source_path,
-                acap_target: &acap_target,
-                manifest_path: &global_options.manifest_path,
-                target,
+        let (built_executable_path, built_libraries, warnings) = if self.package_only {
+            let (executable, libraries) = self.previously_built()?;
+            (executable, libraries, 0)
+        } else {
+            let started = Instant::now();
+            let built = self.cargo_build_in_docker()?;
+            timings.compile = started.elapsed();
+            built
+        };
+
+        let started = Instant::now();
+        let elf = self.copy_executable_with_symbols(&built_executable_path)?;
+        let (executable_path, debug) = match self.debug_info {
+            DebugInfo::Strip => (self.strip(&built_executable_path, None)?, None),
+            DebugInfo::Keep => (built_executable_path, None),
+            DebugInfo::Split => {
+                let built_debug = self.split_debug_info(&built_executable_path)?;
+                let stripped = self.strip(&built_executable_path, Some(&built_debug))?;
+                (stripped, Some(self.copy_debug_info(&built_debug)?))
+            }
+        };
+
+        let mut libraries = Vec::new();
+        for (library, built_path) in self.libraries.iter().zip(built_libraries) {
+            let file_name = built_path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            // Libraries have no debuglink, so their debug info is either kept or stripped
+            let path = match self.debug_info {
+                DebugInfo::Keep => built_path,
+                DebugInfo::Strip | DebugInfo::Split => self.strip(&built_path, None)?,
+            };
+            libraries.push((format!("{}/{}", library.path(), file_name), path));
+        }
+        timings.strip = started.elapsed();
+        let bloat = self.measure_bloat(&elf);
+
+        if self.no_package {
+            self.check_sizes(&executable_path, None)?;
+            return Ok(Artifacts {
+                eap: None,
+                elf,
+                debug,
+                sha256: None,
+                timings,
+                warnings,
+                bloat,
+                soc_eaps: vec![],
+                up_to_date: false,
+            });
+        }
+
+        let started = Instant::now();
+        let eap = self.package(&executable_path, &libraries)?;
+        let sha256 = if self.checksum {
+            Some(self.write_checksum(&eap)?)
+        } else {
+            None
+        };
+        timings.package = started.elapsed();
+
+        self.check_sizes(&executable_path, Some(&eap))?;
+        let soc_eaps = self.copy_for_socs(&eap)?;
+
+        if let Some(fingerprint) = fingerprint {
+            std::fs::write(&fingerprint_path, format!("{}\n", fingerprint))
+                .with_path(&fingerprint_path)?;
+        }
+
+        Ok(Artifacts {
+            eap: Some(eap),
+            elf,
+            debug,
+            sha256,
+            timings,
+            warnings,
+            bloat,
+            soc_eaps,
+            up_to_date: false,
+        })
+    }
+
+    /// A digest of what goes into this target's `.eap`: the options with which it's built, the
+    /// rustflags `cargo` is given, including `-Dwarnings` for `--deny-warnings`, its metadata, the
+    /// files in the package, `Cargo.lock`, and the Docker image or host toolchain.
+    ///
+    /// Files outside the package, e.g. path dependencies elsewhere in the workspace, are not
+    /// included, nor are the package's `target/` and hidden files and directories.
+    fn fingerprint(&self) -> Result<String, Error> {
+        let mut sha256 = cargo_util::Sha256::new();
+        let settings = [
+            format!("cargo-acap {}", env!("CARGO_PKG_VERSION")),
+            format!("toolchain {}", self.environment.toolchain_id()?),
+            format!("target {}", self.target.name()),
+            format!("profile {}", self.profile),
+            format!("binary {} {}", self.binary_name, self.version),
+            format!("features {:?}", self.features.cargo_args()),
+            // As forwarded from RUSTFLAGS or CARGO_ENCODED_RUSTFLAGS, or set from the metadata
+            format!("rustflags {:?}", self.environment.rustflags()),
+            format!("manifest options {:?}", self.manifest_options.cargo_args()),
+            format!("debug info {:?}", self.debug_info),
+            format!("strip args {:?}", self.strip_args),
+            format!("libraries {:?}", self.libraries),
+            format!("compression {:?}", self.compression),
+            // Not when it was built, or it would never be up to date
+            format!(
+                "build info {:?}",
+                self.build_info.map(|info| (&info.commit, &info.rustc))
+            ),
+            format!(
+                "sizes {:?} {:?} {}",
+                self.max_eap_size, self.max_binary_size, self.enforce_sizes
+            ),
+            format!("package.conf {:?}", self.package_conf),
+            format!("param.conf {:?}", self.param_conf),
+            format!("manifest.json {:?}", self.manifest),
+        ];
+        for setting in &settings {
+            sha256.update(setting.as_bytes()).update(b"\n");
+        }
+
+        let mut files = vec![];
+        if self.lockfile.is_file() {
+            files.push(self.lockfile.to_owned());
+        }
+        let mut entries = std::fs::read_dir(self.package_root)
+            .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+            .with_path(self.package_root)?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let name = entry.file_name();
+            if name == "target" || name.to_string_lossy().starts_with('.') {
+                continue;
+            }
+            if path.is_dir() && !self.out_dir.starts_with(&path) {
+                for relative in files_in(&path).with_path(&path)? {
+                    files.push(path.join(relative));
+                }
+            } else if path.is_file() {
+                files.push(path);
+            }
+        }
+        for path in files {
+            if path.starts_with(self.out_dir) {
+                continue;
             }
-            .invoke()
+            let file = File::open(&path).with_path(&path)?;
+            sha256
+                .update(path.to_string_lossy().as_bytes())
+                .update(b"\n");
+            sha256.update_file(&file).with_path(&path)?;
         }
+
+        Ok(sha256.finish_hex())
     }
-}
 
-#[derive(Debug)]
-struct BuildOp<'a> {
-    invocation: &'a Invocation,
-    package_conf: &'a PackageDotConf,
-    cargo_package_name: &'a str,
-    version: &'a str,
-    project_source_path: &'a Path,
-    acap_target: &'a Path,
-    manifest_path: &'a Path,
-    target: Target,
-}
+    /// This target's existing artifacts, if they're complete and `fingerprint_path` says they
+    /// were built with `fingerprint`.
+    fn up_to_date(
+        &self,
+        fingerprint_path: &Path,
+        fingerprint: &str,
+    ) -> Result<Option<Artifacts>, Error> {
+        let recorded = std::fs::read_to_string(fingerprint_path).unwrap_or_default();
+        let eap = self.artifact_path(".eap");
+        let elf = self.artifact_path(".elf");
+        let debug = match self.debug_info {
+            DebugInfo::Split => Some(self.artifact_path(".debug")),
+            DebugInfo::Strip | DebugInfo::Keep => None,
+        };
+        if recorded.trim_end() != fingerprint
+            || !eap.is_file()
+            || !elf.is_file()
+            || debug.as_ref().is_some_and(|debug| !debug.is_file())
+        {
+            if self.environment.verbose() > 0 {
+                eprintln!(
+                    "cargo-acap: target {} is out of date with {}",
+                    self.target.name(),
+                    fingerprint_path.display()
+                );
+            }
+            return Ok(None);
+        }
 
-impl<'a> BuildOp<'a> {
-    pub(crate) fn invoke(&self) {
-        eprintln!("cargo-acap: building target {}", self.target.name());
-        let built_executable_path = self.cargo_build_in_docker();
-        self.copy_executable_with_symbols(&built_executable_path);
-        let stripped_executable_path = self.strip_executable(&built_executable_path);
-        self.package(&stripped_executable_path)
-            .expect("error building package");
-    }
-
-    fn cargo_build_in_docker(&self) -> PathBuf {
-        let mut docker = self.invocation.docker_run_command();
-        docker.args(&[
-            "cargo",
+        let sha256 = if self.checksum {
+            Some(self.write_checksum(&eap)?)
+        } else {
+            None
+        };
+        Ok(Some(Artifacts {
+            eap: Some(eap),
+            elf,
+            debug,
+            sha256,
+            timings: Timings::default(),
+            warnings: 0,
+            bloat: None,
+            soc_eaps: vec![],
+            up_to_date: true,
+        }))
+    }
+
+    /// Fail, or warn if sizes aren't enforced, if the packaged executable or the `.eap`, if
+    /// there is one, exceed their limits.
+    fn check_sizes(&self, executable: &Path, eap: Option<&Path>) -> Result<(), Error> {
+        let size = |path: &Path| -> Result<ByteSize, Error> {
+            Ok(ByteSize(std::fs::metadata(path).with_path(path)?.len()))
+        };
+        let mut exceeded = vec![];
+        for (what, path, limit, key) in [
+            (
+                "executable",
+                Some(executable),
+                self.max_binary_size,
+                "max_binary_size",
+            ),
+            ("package", eap, self.max_eap_size, "max_eap_size"),
+        ] {
+            let (path, limit) = match (path, limit) {
+                (Some(path), Some(limit)) => (path, limit),
+                _ => continue,
+            };
+            let actual = size(path)?;
+            if actual > limit {
+                exceeded.push(format!(
+                    "the {} is {}, over its {} of {}",
+                    what,
+                    actual.exactly(),
+                    key,
+                    limit.exactly()
+                ));
+            }
+        }
+        if exceeded.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(eap) = eap.filter(|_| self.environment.verbose() > 0) {
+            self.print_largest_contents(eap)?;
+        }
+        let message = format!(
+            "{} for target {} is too large: {}",
+            eap.unwrap_or(executable).display(),
+            self.target.name(),
+            exceeded.join(", and ")
+        );
+        if self.enforce_sizes {
+            return Err(Error::Package(message));
+        }
+        eprintln!("cargo-acap: warning: {}", message);
+        Ok(())
+    }
+
+    /// Print what takes the most space in the `.eap` at `path`, by the uncompressed size of each
+    /// file or top-level directory.
+    fn print_largest_contents(&self, path: &Path) -> Result<(), Error> {
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+        read_eap(path, |entry| {
+            let name = entry_name(entry)?;
+            let name = match name.split_once('/') {
+                Some((dir, _)) => format!("{}/", dir),
+                None if entry.header().entry_type().is_dir() => format!("{}/", name),
+                None => name,
+            };
+            *sizes.entry(name).or_default() += entry.header().size()?;
+            Ok(())
+        })?;
+
+        let mut sizes: Vec<(String, u64)> = sizes.into_iter().collect();
+        sizes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        eprintln!(
+            "cargo-acap: largest contents of {}, uncompressed:",
+            path.display()
+        );
+        for (name, size) in sizes.iter().take(5) {
+            eprintln!("  {:>10}  {}", ByteSize(*size).to_string(), name);
+        }
+        Ok(())
+    }
+
+    /// Measure what takes up space in the executable, whose unstripped copy is `elf`, if building
+    /// with `--bloat`.
+    ///
+    /// This only warns if it fails, since the build itself succeeded.
+    fn measure_bloat(&self, elf: &Path) -> Option<Bloat> {
+        let result = match self.bloat? {
+            Analyzer::CargoBloat => self.cargo_bloat(),
+            Analyzer::Symbols => Bloat::from_symbols(elf, self.bloat_top),
+        };
+        match result {
+            Ok(bloat) => Some(bloat),
+            Err(e) => {
+                eprintln!(
+                    "cargo-acap: warning: error measuring the executable for target {}: {}",
+                    self.target.name(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Measure the executable with `cargo bloat`, which builds it again, finding it up to date.
+    fn cargo_bloat(&self) -> Result<Bloat, Error> {
+        let bloat = |crates: bool| -> Result<CargoBloatOutput, Error> {
+            let mut cargo = self.environment.command("cargo")?;
+            cargo.args([
+                "bloat",
+                "--target",
+                self.target.rust_target_triple(),
+                "--profile",
+                self.profile,
+            ]);
+            cargo.args(self.features.cargo_args());
+            cargo.args(self.manifest_options.cargo_args());
+            if let Some(package) = self.environment.selected_package() {
+                cargo.args(["--package", package]);
+            }
+            if self.manifest_path != self.package_root.join("Cargo.toml") {
+                cargo.arg("--manifest-path");
+                cargo.arg(self.environment.container_path(self.manifest_path)?);
+            }
+            cargo.args([self.binary_kind().0, self.binary_name]);
+            if self.concurrent {
+                cargo.arg("--target-dir");
+                cargo.arg(
+                    self.environment
+                        .acap_target_path(self.target.rust_target_triple()),
+                );
+            }
+            cargo.args(["--message-format", "json", "-n"]);
+            cargo.arg(self.bloat_top.to_string());
+            if crates {
+                cargo.arg("--crates");
+            }
+
+            let output = self.environment.output(cargo, self.output_prefix())?;
+            serde_json::from_str(output.trim()).map_err(|e| {
+                Error::Package(format!("error parsing the output of `cargo bloat`: {}", e))
+            })
+        };
+        Ok(Bloat::from_cargo_bloat(bloat(false)?, bloat(true)?))
+    }
+
+    /// Run `command` in the build environment, prefixing its output if other targets are
+    /// building concurrently.
+    fn run(&self, command: std::process::Command) -> Result<(), Error> {
+        self.environment.run(command, self.output_prefix())
+    }
+
+    /// The prefix for command output, if other targets are building concurrently.
+    fn output_prefix(&self) -> Option<&str> {
+        if self.concurrent {
+            Some(self.target.name())
+        } else {
+            None
+        }
+    }
+
+    /// Build the executable and any libraries, returning their host paths, and how many warnings
+    /// `rustc` reported.
+    fn cargo_build_in_docker(&self) -> Result<(PathBuf, Vec<PathBuf>, usize), Error> {
+        let cargo_args = self.features.cargo_args().into_iter().chain(
+            self.manifest_options
+                .cargo_args()
+                .into_iter()
+                .map(String::from),
+        );
+        let mut docker = cargo_command(
+            self.environment,
             "build",
-            "--target",
-            self.target.rust_target_triple(),
-            "--release",
-        ]);
+            self.target,
+            self.profile,
+            cargo_args,
+            self.manifest_path,
+        )?;
+        docker.args([self.binary_kind().0, self.binary_name]);
+
+        // Concurrent builds each need their own target directory, since `cargo` locks it
+        if self.concurrent {
+            docker.arg("--target-dir");
+            docker.arg(
+                self.environment
+                    .acap_target_path(self.target.rust_target_triple()),
+            );
+        }
+
+        if self.timings {
+            docker.arg("--timings");
+        }
 
-        if self.manifest_path != Path::new("Cargo.toml") {
-            docker.arg("--manifest-path");
-            docker.arg(&self.manifest_path);
+        // Ask `cargo` where it put everything, rather than guessing from the profile, target
+        // directory, and `[[bin]]` names, and for diagnostics, to count warnings while showing
+        // them as `cargo` would
+        if output::color() {
+            docker.arg("--message-format=json-diagnostic-rendered-ansi");
+        } else {
+            docker.arg("--message-format=json");
+        }
+        if !self.libraries.is_empty() {
+            docker.arg("--lib");
         }
+        let mut warnings = 0;
+        let messages = self
+            .environment
+            .output_lines(docker, self.output_prefix(), |line| {
+                let message = match serde_json::from_str::<CompilerMessage>(line) {
+                    Ok(message) if message.reason == "compiler-message" => message.message,
+                    _ => return,
+                };
+                if message.level == "warning" {
+                    warnings += 1;
+                }
+                if let Some(rendered) = message.rendered {
+                    copy_lines(
+                        rendered.trim_end().as_bytes(),
+                        std::io::stderr(),
+                        self.output_prefix(),
+                    );
+                }
+            })?;
+        let artifacts: Vec<CompilerArtifact> = messages
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .filter(|artifact: &CompilerArtifact| artifact.reason == "compiler-artifact")
+            .collect();
+
+        Ok((
+            self.built_executable(&artifacts)?,
+            self.built_libraries(&artifacts)?,
+            warnings,
+        ))
+    }
+
+    /// Find the host path of the executable among `cargo`'s `artifacts`.
+    fn built_executable(&self, artifacts: &[CompilerArtifact]) -> Result<PathBuf, Error> {
+        artifacts
+            .iter()
+            .filter(|artifact| artifact.is(self.binary_kind().1, self.binary_name))
+            .find_map(|artifact| artifact.executable.as_deref())
+            .map(|path| self.environment.host_path(path))
+            .ok_or_else(|| {
+                Error::Package(format!(
+                    "`cargo build` did not report building the executable `{}`",
+                    self.binary_name
+                ))
+            })
+    }
 
-        for _ in 1..self.invocation.global_options().verbose {
-            docker.arg("--verbose");
+    /// The `cargo build` option which selects the executable, and the kind of target `cargo`
+    /// reports building.
+    fn binary_kind(&self) -> (&'static str, &'static str) {
+        if self.example {
+            ("--example", "example")
+        } else {
+            ("--bin", "bin")
         }
+    }
+
+    /// Find the host path of each library among `cargo`'s `artifacts`.
+    fn built_libraries(&self, artifacts: &[CompilerArtifact]) -> Result<Vec<PathBuf>, Error> {
+        self.libraries
+            .iter()
+            .map(|library| {
+                artifacts
+                    .iter()
+                    .filter(|artifact| artifact.is("cdylib", &library.name))
+                    .flat_map(|artifact| &artifact.filenames)
+                    .find(|path| path.extension().is_some_and(|ext| ext == "so"))
+                    .map(|path| self.environment.host_path(path))
+                    .ok_or_else(|| {
+                        Error::Package(format!(
+                            "library `{}` was not built: the package's [lib] must be named `{}` \
+                             and have crate-type = [\"cdylib\"]",
+                            library.name, library.name
+                        ))
+                    })
+            })
+            .collect()
+    }
+
+    /// Find the host paths of the executable and libraries `cargo` built most recently for this
+    /// target and profile, in either the shared target directory or this target's own, for
+    /// `--package-only`.
+    fn previously_built(&self) -> Result<(PathBuf, Vec<PathBuf>), Error> {
+        let triple = self.target.rust_target_triple();
+        let profile_dir = match self.profile {
+            "dev" | "test" => "debug",
+            "bench" => "release",
+            profile => profile,
+        };
+        let dirs: Vec<PathBuf> = [
+            self.acap_target.join(triple),
+            self.acap_target.join(triple).join(triple),
+        ]
+        .iter()
+        .map(|dir| {
+            let dir = dir.join(profile_dir);
+            if self.example {
+                dir.join("examples")
+            } else {
+                dir
+            }
+        })
+        .collect();
+        let newest = |file_name: &str| -> Option<(PathBuf, SystemTime)> {
+            dirs.iter()
+                .map(|dir| dir.join(file_name))
+                .filter_map(|path| {
+                    let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                    Some((path, modified))
+                })
+                .max_by_key(|(_, modified)| *modified)
+        };
+        let not_built = |what: String| {
+            Error::Package(format!(
+                "{} has not been built for target {} with profile `{}`, so --package-only has \
+                 nothing to package: build it without --package-only first",
+                what,
+                self.target.name(),
+                self.profile
+            ))
+        };
+
+        let (executable, built_at) = newest(self.binary_name)
+            .ok_or_else(|| not_built(format!("the executable `{}`", self.binary_name)))?;
+        // It may well be older than the sources, so say which build is being packaged
+        status!(
+            "cargo-acap: packaging {}, built at {}",
+            executable.display(),
+            utc_timestamp(
+                built_at
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs())
+            )
+        );
+        let libraries = self
+            .libraries
+            .iter()
+            .map(|library| {
+                newest(&format!("lib{}.so", library.name.replace('-', "_")))
+                    .map(|(path, _)| path)
+                    .ok_or_else(|| not_built(format!("the library `{}`", library.name)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok((executable, libraries))
+    }
 
-        self.invocation.run_to_completion(docker);
+    /// Remove this target's build directory, leaving other targets' directories intact.
+    fn clean(&self) -> Result<(), Error> {
+        let dir = self.acap_target.join(self.target.rust_target_triple());
+        if self.environment.verbose() > 0 {
+            eprintln!("cargo-acap: removing {}", dir.display());
+        }
+        match std::fs::remove_dir_all(&dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).with_path(&dir),
+            _ => Ok(()),
+        }
+    }
 
-        self.acap_target
-            .join(self.target.rust_target_triple())
-            .join("release")
-            .join(self.cargo_package_name)
+    /// Remove the artifacts this build wrote since `started`, which an interruption left
+    /// inconsistent with one another, if not incomplete.
+    fn remove_partial_artifacts(&self, started: SystemTime) {
+        for suffix in [".elf", ".debug", ".eap.tmp"] {
+            let path = self.artifact_path(suffix);
+            let written = std::fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified >= started);
+            if written && std::fs::remove_file(&path).is_ok() && self.environment.verbose() > 0 {
+                eprintln!("cargo-acap: removed {}", path.display());
+            }
+        }
     }
 
     fn artifact_path(&self, suffix: &str) -> PathBuf {
-        self.acap_target.join(format!(
-            "{}_{}_{}{}",
+        self.out_dir.join(artifact_file_name(
+            self.artifact_name,
             &self.package_conf.app_name,
             self.version,
-            self.target.name(),
+            self.target,
+            None,
+            self.profile,
             suffix,
         ))
     }
 
-    fn copy_executable_with_symbols(&self, built_executable_path: &Path) {
+    /// Copy `eap` for each SoC using this target, if requested, named after the SoC, returning
+    /// each copy's path.
+    ///
+    /// The copies are hard links where the filesystem allows, since they're identical, and each
+    /// gets its own checksum file, since that names the file it checks.
+    fn copy_for_socs(&self, eap: &Path) -> Result<Vec<(SOC, PathBuf)>, Error> {
+        if !self.soc_artifacts {
+            return Ok(vec![]);
+        }
+
+        let mut copies = vec![];
+        for soc in self.target.socs() {
+            let copy = self.out_dir.join(artifact_file_name(
+                self.artifact_name,
+                &self.package_conf.app_name,
+                self.version,
+                self.target,
+                Some(soc),
+                self.profile,
+                ".eap",
+            ));
+            match std::fs::remove_file(&copy) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).with_path(&copy)
+                }
+                _ => {}
+            }
+            if std::fs::hard_link(eap, &copy).is_err() {
+                std::fs::copy(eap, &copy).with_path(&copy)?;
+            }
+            if self.checksum {
+                self.write_checksum(&copy)?;
+            }
+            copies.push((soc, copy));
+        }
+
+        status!(
+            "cargo-acap: copied the package for target {} for each SoC using it:",
+            self.target.name()
+        );
+        for (soc, copy) in &copies {
+            status!(
+                "  {:<16} {}",
+                soc.display_name(),
+                copy.file_name().unwrap().to_string_lossy()
+            );
+        }
+        Ok(copies)
+    }
+
+    fn copy_executable_with_symbols(&self, built_executable_path: &Path) -> Result<PathBuf, Error> {
         // copy the executable
         let elf_executable_path = self.artifact_path(".elf");
-        std::fs::copy(&built_executable_path, &elf_executable_path)
-            .expect("error copying built executable");
+        std::fs::copy(built_executable_path, &elf_executable_path)
+            .with_path(built_executable_path)?;
 
-        if self.invocation.global_options().verbose > 0 {
-            let stat = std::fs::metadata(&elf_executable_path).unwrap();
+        if self.environment.verbose() > 0 {
+            let stat = std::fs::metadata(&elf_executable_path).with_path(&elf_executable_path)?;
             eprintln!(
                 "built executable {} ({} bytes)",
                 elf_executable_path.display(),
                 stat.len()
             );
         }
+        Ok(elf_executable_path)
     }
 
-    fn strip_executable(&self, built_executable_path: &Path) -> PathBuf {
-        let stripped_executable_path = built_executable_path.with_extension("stripped");
+    /// Copy the executable's debug info to a file beside it named like the `.debug` artifact,
+    /// returning its path.
+    fn split_debug_info(&self, built_executable_path: &Path) -> Result<PathBuf, Error> {
+        let debug_path = built_debug_path(built_executable_path, &self.artifact_path(".debug"));
 
-        let mut docker = self.invocation.docker_run_command();
-        docker.args(&[self.target.docker_objcopy_command(), "--strip-all"]);
-        docker.arg(&built_executable_path);
-        docker.arg(&stripped_executable_path);
+        let mut docker = self
+            .environment
+            .command(self.target.docker_objcopy_command())?;
+        docker.args(split_debug_info_args(
+            self.environment,
+            built_executable_path,
+            &debug_path,
+        )?);
 
-        self.invocation.run_to_completion(docker);
+        self.run(docker)?;
+        Ok(debug_path)
+    }
+
+    /// Copy the debug info split into `built_debug_path` to `<name>_<version>_<target>.debug`,
+    /// returning its path.
+    fn copy_debug_info(&self, built_debug_path: &Path) -> Result<PathBuf, Error> {
+        let debug_path = self.artifact_path(".debug");
+        if debug_path != built_debug_path {
+            std::fs::copy(built_debug_path, &debug_path).with_path(&debug_path)?;
+        }
 
-        if self.invocation.global_options().verbose > 1 {
-            let stat = std::fs::metadata(&stripped_executable_path).unwrap();
+        if self.environment.verbose() > 0 {
+            let stat = std::fs::metadata(&debug_path).with_path(&debug_path)?;
             eprintln!(
-                "stripped {} ({} bytes without symbols)",
-                stripped_executable_path.display(),
+                "split debug info into {} ({} bytes)",
+                debug_path.display(),
                 stat.len()
             );
         }
-
-        stripped_executable_path
+        Ok(debug_path)
     }
 
-    fn package(&self, stripped_executable_path: &Path) -> Result<PathBuf, std::io::Error> {
-        let eap = self.artifact_path(".eap");
-        let mut file = std::fs::File::create(&eap)?;
-        let mut gz = deflate::write::GzEncoder::new(&mut file, deflate::Compression::Default);
-        let mut tar = tar::Builder::new(&mut gz);
+    /// Strip the symbols of a built executable or library, linking it to `debug_path` if its
+    /// debug info was split there.
+    fn strip(&self, built_path: &Path, debug_path: Option<&Path>) -> Result<PathBuf, Error> {
+        let stripped_path = built_path.with_extension("stripped");
 
-        let mut package_conf = self.package_conf.clone();
+        let mut docker = self
+            .environment
+            .command(self.target.docker_objcopy_command())?;
+        docker.args(strip_args(
+            self.environment,
+            self.strip_args.as_ref().map(|(_, args)| *args),
+            built_path,
+            &stripped_path,
+            debug_path,
+        )?);
 
-        // write cgi.txt, if any
-        {
-            let cgi_txt = self.project_source_path.join("cgi.txt");
-            match std::fs::File::open(cgi_txt) {
-                Ok(mut f) => {
-                    tar.append_file("cgi.txt", &mut f)?;
-                    package_conf.http_cgi_paths = Some("cgi.txt".into());
-                }
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::NotFound {
-                        // ignore
-                    } else {
-                        return Err(e);
-                    }
-                }
+        match (self.run(docker), &self.strip_args) {
+            (Err(Error::Command(e)), Some((key, args))) => {
+                return Err(Error::Package(format!(
+                    "stripping target {} with package.metadata.acap.{} = {:?} failed: {}",
+                    self.target.name(),
+                    key,
+                    args,
+                    e
+                )))
             }
-        };
-
-        // write package.conf
-        {
-            let package_conf = package_conf.to_string();
-            let package_conf_bytes = package_conf.as_bytes();
-            tar.append(
-                &tar_header(
-                    Path::new("package.conf"),
-                    package_conf_bytes.len() as _,
-                    Some(SystemTime::now()),
-                ),
-                std::io::Cursor::new(package_conf_bytes),
-            )?;
+            (result, _) => result?,
         }
 
-        // write "otherfiles"
-        {
-            // TODO: the "otherfiles" folder is only intended for static files.
-            // Since it might be of interest to also copy built files such as
-            // .so files, we should also provide another folder to copy from
-            // for those files.
-            match tar.append_dir_all(".", "otherfiles") {
-                Ok(_) => (),
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::NotFound {
-                        // ignore
-                    } else {
-                        return Err(e);
-                    }
-                }
-            }
+        if self.environment.verbose() > 1 {
+            let stat = std::fs::metadata(&stripped_path).with_path(&stripped_path)?;
+            eprintln!(
+                "stripped {} ({} bytes without symbols)",
+                stripped_path.display(),
+                stat.len()
+            );
         }
 
-        // write postinstall.sh
-        {
-            match File::open("postinstall.sh") {
-                Ok(mut f) => {
-                    tar.append_file("postinstall.sh", &mut f)?;
-                },
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::NotFound {
-                        // ignore
-                    } else {
-                        return Err(e);
+        Ok(stripped_path)
+    }
+
+    fn package(
+        &self,
+        stripped_executable_path: &Path,
+        libraries: &[(String, PathBuf)],
+    ) -> Result<PathBuf, Error> {
+        let contents = self.contents(stripped_executable_path, libraries)?;
+        if self.environment.verbose() > 0 {
+            for entry in contents.entries() {
+                if let Source::Generated(bytes) = &entry.source {
+                    if entry.name != "package.conf" {
+                        eprintln!("{}:\n{}", entry.name, String::from_utf8_lossy(bytes));
                     }
                 }
             }
         }
 
-        // write executable
-        {
-            let mut executable = std::fs::File::open(stripped_executable_path)?;
-            tar.append_file(&self.package_conf.app_name, &mut executable)?;
+        // Write to a temporary file in the same directory, and rename it into place only once it's
+        // complete and on disk, so that a package is either whole or absent
+        let eap = self.artifact_path(".eap");
+        let temp = self.artifact_path(".eap.tmp");
+        let write = || -> Result<(), std::io::Error> {
+            let file = contents.finish(File::create(&temp)?, self.compression)?;
+            file.sync_all()?;
+            std::fs::rename(&temp, &eap)
+        };
+        if let Err(e) = write() {
+            let _ = std::fs::remove_file(&temp);
+            return Err(e).with_path(&eap);
         }
 
-        tar.finish()?;
-        drop(tar);
-        gz.finish()?;
-        file.flush()?;
-        drop(file);
+        // Catch what the device would otherwise refuse, or worse, accept and fail to run
+        verify::verify(&eap, Some(self.target))?;
 
-        if self.invocation.global_options().verbose > 0 {
-            let stat = std::fs::metadata(&eap).unwrap();
+        if self.environment.verbose() > 0 {
+            let stat = std::fs::metadata(&eap).with_path(&eap)?;
             eprintln!("built package {} ({} bytes)", eap.display(), stat.len());
         }
 
         Ok(eap)
     }
+
+    /// Plan the contents of the package containing `executable` and `libraries`.
+    fn contents(
+        &self,
+        executable: &Path,
+        libraries: &[(String, PathBuf)],
+    ) -> Result<EapBuilder, Error> {
+        package::contents(
+            self.package_root,
+            self.package_conf,
+            self.param_conf,
+            self.manifest,
+            executable,
+            libraries,
+            self.build_info,
+        )
+    }
+
+    /// Write `<eap>.sha256` in the format of `sha256sum`, returning the hex digest.
+    ///
+    /// `eap` must be the final package, not the temporary file it was written to.
+    fn write_checksum(&self, eap: &Path) -> Result<String, Error> {
+        // Stream the file, since packages can be large
+        let digest = File::open(eap)
+            .and_then(|file| Ok(cargo_util::Sha256::new().update_file(&file)?.finish_hex()))
+            .with_path(eap)?;
+
+        let file_name = eap.file_name().unwrap().to_string_lossy();
+        let mut checksum_path = eap.as_os_str().to_owned();
+        checksum_path.push(".sha256");
+        std::fs::write(&checksum_path, format!("{}  {}\n", digest, file_name))
+            .with_path(&checksum_path)?;
+
+        if self.environment.verbose() > 0 {
+            eprintln!("sha256 {} {}", digest, file_name);
+        }
+
+        Ok(digest)
+    }
 }
 
-fn tar_header(path: &Path, size: u64, mtime: Option<SystemTime>) -> tar::Header {
-    let mut header = tar::Header::new_gnu();
-    header.set_path(path).unwrap();
-    header.set_mode(0o644);
-    header.set_uid(0);
-    header.set_gid(0);
-    header.set_mtime(
-        mtime
-            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
-            .map(|d| d.as_secs())
-            .unwrap_or(0),
-    );
-    header.set_size(size);
-    header.set_entry_type(tar::EntryType::Regular);
-    header.set_cksum();
-    header
-}
-
-/*
-fn tar_header(path: &Path, size: u64, mtime: Option<SystemTime>) -> tar::Header {
-    let mut header = tar::Header::new_gnu();
-    let name = b"././@LongLink";
-    header.as_gnu_mut().unwrap().name[..name.len()].clone_from_slice(&name[..]);
-    header.set_mode(0o644);
-    header.set_uid(0);
-    header.set_gid(0);
-    header.set_mtime(
-        mtime
-            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
-            .map(|d| d.as_secs())
-            .unwrap_or(0),
-    );
-    // + 1 to be compliant with GNU tar
-    header.set_size(size + 1);
-    header.set_entry_type(tar::EntryType::new(b'L'));
-    header.set_cksum();
-    header
+/// Ensure the host can build `targets` without `docker`, failing with a helpful message if not.
+fn check_host_toolchain(invocation: &Invocation, targets: &[Target]) -> Result<(), Error> {
+    let sysroot = invocation.host_rustc_sysroot()?;
+    let mut problems = Vec::new();
+
+    for target in targets {
+        let triple = target.rust_target_triple();
+        if !sysroot.join("lib").join("rustlib").join(triple).is_dir() {
+            problems.push(format!(
+                "rustc has no standard library for `{}` (target {}) in {}",
+                triple,
+                target.name(),
+                sysroot.display()
+            ));
+        }
+
+        let objcopy = target.docker_objcopy_command();
+        if find_in_path(objcopy).is_none() {
+            problems.push(format!(
+                "`{}` (needed for target {}) was not found in PATH",
+                objcopy,
+                target.name()
+            ));
+        }
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let mut message = "the host toolchain cannot build the requested targets:".to_string();
+    for problem in problems {
+        message += &format!("\n  * {}", problem);
+    }
+    message +=
+        "\nInstall the missing components, select other targets, or build without --no-docker.";
+    Err(Error::Usage(message))
+}
+
+/// The rustflags with which to build with `extra` as well as those `cargo` would use: those given
+/// by its `environment`, or failing that, by its `configured` rustflags, since
+/// `CARGO_ENCODED_RUSTFLAGS` replaces both.
+fn merged_rustflags(
+    environment: Vec<String>,
+    configured: impl FnOnce() -> Result<Vec<String>, Error>,
+    extra: Vec<&str>,
+) -> Result<Vec<String>, Error> {
+    let base = if environment.is_empty() {
+        configured()?
+    } else {
+        environment
+    };
+    Ok(base
+        .into_iter()
+        .chain(extra.into_iter().map(String::from))
+        .collect())
+}
+
+/// What the profile whose settings are taken from the first of `chain` to set each makes worse on
+/// devices, each with the setting which fixes it.
+fn profile_problems(chain: &[&TomlProfile]) -> Vec<(&'static str, &'static str)> {
+    let mut problems = vec![];
+    let panic = chain.iter().find_map(|profile| profile.panic.as_deref());
+    if panic != Some("abort") {
+        problems.push(("panics unwind", "panic = \"abort\""));
+    }
+    match chain.iter().find_map(|profile| profile.lto.as_ref()) {
+        // `false` is thin-local LTO, within each crate only
+        None | Some(StringOrBool::Bool(false)) => {
+            problems.push(("LTO is neither fat nor thin", "lto = true"))
+        }
+        Some(StringOrBool::String(lto)) if lto == "off" => {
+            problems.push(("LTO is off", "lto = true"))
+        }
+        Some(_) => {}
+    }
+    let unstripped = match chain.iter().find_map(|profile| profile.strip.as_ref()) {
+        None | Some(StringOrBool::Bool(false)) => true,
+        Some(StringOrBool::String(strip)) => strip == "none",
+        Some(StringOrBool::Bool(true)) => false,
+    };
+    if unstripped {
+        problems.push(("symbols aren't stripped", "strip = true"));
+    }
+    let opt_level = chain
+        .iter()
+        .find_map(|profile| profile.opt_level.as_ref())
+        .map(|opt_level| opt_level.0.as_str());
+    if opt_level == Some("0") {
+        problems.push(("optimizations are off", "opt-level = 3"));
+    }
+    problems
+}
+
+/// Construct `cargo <subcommand>` for `target` in `environment`, with the arguments every
+/// subcommand which builds shares: the `profile`, `cargo_args` such as features, and the selected
+/// package, manifest path, and verbosity.
+pub(crate) fn cargo_command(
+    environment: &BuildEnvironment,
+    subcommand: &str,
+    target: Target,
+    profile: &str,
+    cargo_args: impl IntoIterator<Item = String>,
+    manifest_path: &Path,
+) -> Result<Command, Error> {
+    let mut cargo = environment.command("cargo")?;
+    cargo.args([
+        subcommand,
+        "--target",
+        target.rust_target_triple(),
+        "--profile",
+        profile,
+    ]);
+    cargo.args(cargo_args);
+
+    if let Some(package) = environment.selected_package() {
+        cargo.args(["--package", package]);
+    }
+
+    // `cargo` runs in the package's directory, where it finds the package's manifest itself, so
+    // fail clearly if the container can't see it rather than with a missing working directory
+    environment.container_path(&environment.package_root)?;
+    if manifest_path != environment.package_root.join("Cargo.toml") {
+        cargo.arg("--manifest-path");
+        cargo.arg(environment.container_path(manifest_path)?);
+    }
+
+    for _ in 1..environment.verbose() {
+        cargo.arg("--verbose");
+    }
+    if output::quiet() {
+        cargo.arg("--quiet");
+    }
+    // `cargo` may not see the terminal from inside a container, so tell it what we decided
+    cargo.args(["--color", if output::color() { "always" } else { "never" }]);
+    Ok(cargo)
+}
+
+/// Whether `cargo bloat` is installed in `environment`.
+fn cargo_bloat_installed(environment: &BuildEnvironment) -> bool {
+    let environment = environment.clone().non_interactive();
+    let mut cargo = match environment.command("cargo") {
+        Ok(cargo) => cargo,
+        Err(_) => return false,
+    };
+    cargo.args(["bloat", "--version"]).stderr(Stdio::null());
+    environment.output(cargo, None).is_ok()
+}
+
+/// Find `program` in the directories listed in `PATH`.
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| {
+            dir.join(program)
+                .with_extension(std::env::consts::EXE_EXTENSION)
+        })
+        .find(|candidate| candidate.is_file())
+}
+
+/// Where `objcopy` splits the debug info of `built_path` into: beside it, within the target
+/// directory, which containers can always see, unlike the output directory.
+///
+/// It's named like the `.debug` artifact at `artifact_path`, since the stripped executable's
+/// debuglink names it.
+fn built_debug_path(built_path: &Path, artifact_path: &Path) -> PathBuf {
+    built_path.with_file_name(artifact_path.file_name().unwrap())
+}
+
+/// The `objcopy` arguments which copy the debug info of `built_path` to `debug_path`, as seen by
+/// `environment`.
+fn split_debug_info_args(
+    environment: &BuildEnvironment,
+    built_path: &Path,
+    debug_path: &Path,
+) -> Result<Vec<OsString>, Error> {
+    Ok(vec![
+        "--only-keep-debug".into(),
+        environment.container_path(built_path)?.into(),
+        environment.container_path(debug_path)?.into(),
+    ])
+}
+
+/// The `objcopy` arguments which strip `built_path` into `stripped_path` with `args`, or else
+/// `--strip-all`, linking it to `debug_path` if given, as seen by `environment`.
+fn strip_args(
+    environment: &BuildEnvironment,
+    args: Option<&[String]>,
+    built_path: &Path,
+    stripped_path: &Path,
+    debug_path: Option<&Path>,
+) -> Result<Vec<OsString>, Error> {
+    let mut strip_args: Vec<OsString> = match args {
+        Some(args) => args.iter().map(OsString::from).collect(),
+        None => vec!["--strip-all".into()],
+    };
+    if let Some(debug_path) = debug_path {
+        let mut debuglink = OsString::from("--add-gnu-debuglink=");
+        debuglink.push(environment.container_path(debug_path)?);
+        strip_args.push(debuglink);
+    }
+    strip_args.push(environment.container_path(built_path)?.into());
+    strip_args.push(environment.container_path(stripped_path)?.into());
+    Ok(strip_args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::environment::tests::environment;
+    use crate::cli::ContainerEngine;
+
+    const BUILT: &str = "/fast-ssd/targets/acap/mipsel-axis-linux-gnu/release/app";
+
+    #[test]
+    fn strip_args_use_container_paths_for_an_out_of_tree_target_dir() {
+        let environment = environment(
+            "/work/app",
+            "/fast-ssd/targets/acap",
+            ContainerEngine::Docker,
+        );
+        let built = Path::new(BUILT);
+        let debug = built.with_file_name("app_mips.debug");
+        let args = strip_args(
+            &environment,
+            None,
+            built,
+            &built.with_extension("stripped"),
+            Some(&debug),
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "--strip-all",
+                "--add-gnu-debuglink=/target/mipsel-axis-linux-gnu/release/app_mips.debug",
+                "/target/mipsel-axis-linux-gnu/release/app",
+                "/target/mipsel-axis-linux-gnu/release/app.stripped",
+            ]
+        );
+    }
+
+    #[test]
+    fn strip_args_use_host_paths_without_docker() {
+        let mut environment = environment(
+            "/work/app",
+            "/fast-ssd/targets/acap",
+            ContainerEngine::Docker,
+        );
+        environment.no_docker = true;
+        let built = Path::new(BUILT);
+        let custom = vec!["--strip-debug".to_string()];
+        let args = strip_args(
+            &environment,
+            Some(&custom),
+            built,
+            &built.with_extension("stripped"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "--strip-debug",
+                BUILT,
+                "/fast-ssd/targets/acap/mipsel-axis-linux-gnu/release/app.stripped",
+            ]
+        );
+    }
+
+    #[test]
+    fn split_debug_info_args_avoid_an_out_dir_outside_the_workspace() {
+        let environment = environment(
+            "/work/app",
+            "/work/app/target/acap",
+            ContainerEngine::Docker,
+        );
+        let built = Path::new("/work/app/target/acap/mipsel-axis-linux-gnu/release/app");
+        let artifact = Path::new("/tmp/out/app_0.1.0_mips.debug");
+        assert!(environment.container_path(artifact).is_err());
+
+        let debug = built_debug_path(built, artifact);
+        assert_eq!(
+            split_debug_info_args(&environment, built, &debug).unwrap(),
+            [
+                "--only-keep-debug",
+                "/target/mipsel-axis-linux-gnu/release/app",
+                "/target/mipsel-axis-linux-gnu/release/app_0.1.0_mips.debug",
+            ]
+        );
+    }
+
+    fn profile(text: &str) -> TomlProfile {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn merged_rustflags_follow_those_cargo_would_use() {
+        let strings = |flags: &[&str]| flags.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        let configured = || Ok(strings(&["-Ctarget-cpu=cortex-a53"]));
+
+        // The environment's rustflags are used in preference to the configuration's
+        assert_eq!(
+            merged_rustflags(strings(&["--cfg", "env"]), configured, vec!["-Dwarnings"]).unwrap(),
+            ["--cfg", "env", "-Dwarnings"]
+        );
+        assert_eq!(
+            merged_rustflags(vec![], configured, vec!["-Dwarnings"]).unwrap(),
+            ["-Ctarget-cpu=cortex-a53", "-Dwarnings"]
+        );
+        assert!(matches!(
+            merged_rustflags(
+                vec![],
+                || Err(Error::Usage("cfg".to_string())),
+                vec!["-Dwarnings"]
+            ),
+            Err(Error::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn profile_problems_include_cargos_defaults() {
+        assert_eq!(
+            profile_problems(&[]),
+            [
+                ("panics unwind", "panic = \"abort\""),
+                ("LTO is neither fat nor thin", "lto = true"),
+                ("symbols aren't stripped", "strip = true"),
+            ]
+        );
+        assert_eq!(
+            profile_problems(&[&profile(
+                "panic = \"abort\"\nlto = \"thin\"\nstrip = \"symbols\"\nopt-level = \"z\"\n"
+            )]),
+            []
+        );
+    }
+
+    #[test]
+    fn profile_problems_name_size_hostile_settings() {
+        let fine = profile("panic = \"abort\"\nlto = true\nstrip = true\n");
+        for (text, problem) in [
+            (
+                "panic = \"unwind\"\n",
+                ("panics unwind", "panic = \"abort\""),
+            ),
+            (
+                "lto = false\n",
+                ("LTO is neither fat nor thin", "lto = true"),
+            ),
+            ("lto = \"off\"\n", ("LTO is off", "lto = true")),
+            (
+                "strip = \"none\"\n",
+                ("symbols aren't stripped", "strip = true"),
+            ),
+            (
+                "opt-level = 0\n",
+                ("optimizations are off", "opt-level = 3"),
+            ),
+        ] {
+            assert_eq!(
+                profile_problems(&[&profile(text), &fine]),
+                [problem],
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn profile_problems_take_each_setting_from_the_nearest_profile() {
+        let parent = profile("panic = \"unwind\"\nlto = false\nstrip = true\n");
+        let child = profile("inherits = \"release\"\npanic = \"abort\"\n");
+        assert_eq!(
+            profile_problems(&[&child, &parent]),
+            [("LTO is neither fat nor thin", "lto = true")]
+        );
+    }
 }
- */
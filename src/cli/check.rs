@@ -0,0 +1,105 @@
+use crate::cargo_config::CargoAcapMetadata;
+use crate::cli::build::{cargo_command, Build, BuildOptions};
+use crate::cli::{BuildEnvironment, Invocation};
+use crate::error::Error;
+use crate::output::status;
+use crate::target::Target;
+use clap::Parser;
+
+/// Check an ACAP application for errors without building it, for each target
+#[derive(Parser)]
+pub struct Check {
+    /// Which target(s) to check, by name, triple, or SoC (defaults to `targets` in
+    /// `[package.metadata.acap]`, or all)
+    #[clap(short, long, alias = "target")]
+    targets: Vec<Target>,
+
+    /// Target(s) not to check, even if otherwise selected
+    #[clap(long, value_name = "TARGET")]
+    exclude_target: Vec<Target>,
+
+    #[clap(flatten)]
+    options: BuildOptions,
+
+    /// Arguments for the checker, e.g. `-- -D warnings` for clippy
+    #[clap(last = true, value_name = "ARGS")]
+    args: Vec<String>,
+}
+
+/// The `cargo` subcommand with which to check.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Checker {
+    Check,
+    Clippy,
+}
+
+impl Checker {
+    fn name(self) -> &'static str {
+        match self {
+            Checker::Check => "check",
+            Checker::Clippy => "clippy",
+        }
+    }
+}
+
+impl Check {
+    pub(crate) fn invoke(self, invocation: Invocation, checker: Checker) -> Result<(), Error> {
+        let targets = Build::for_targets(self.targets.clone(), self.options.clone())
+            .excluding(self.exclude_target.clone())
+            .targets(&invocation)?;
+
+        let metadata = CargoAcapMetadata::for_package(&invocation.cargo_package)?;
+        let environment = self.options.cargo_environment(&invocation, &metadata)?;
+        if checker == Checker::Clippy {
+            ensure_clippy(&environment)?;
+        }
+
+        for &target in &targets {
+            let environment =
+                self.options
+                    .target_environment(&invocation, &metadata, &environment, target)?;
+            invocation.check_image_rustc(&environment, false)?;
+            status!(
+                "cargo-acap: running cargo {} for target {}",
+                checker.name(),
+                target.name()
+            );
+
+            let mut cargo = cargo_command(
+                &environment,
+                checker.name(),
+                target,
+                self.options.profile(),
+                self.options.cargo_args(),
+                invocation.manifest_path(),
+            )?;
+            if !self.args.is_empty() {
+                cargo.arg("--");
+                cargo.args(&self.args);
+            }
+            environment.run(cargo, None)?;
+        }
+        Ok(())
+    }
+}
+
+/// Make sure `cargo clippy` can run in `environment`, failing with advice if it can't.
+fn ensure_clippy(environment: &BuildEnvironment) -> Result<(), Error> {
+    let mut cargo = environment.command("cargo")?;
+    cargo.args(["clippy", "--version"]);
+    if environment.output(cargo, None).is_ok() {
+        return Ok(());
+    }
+
+    if environment.no_docker {
+        return Err(Error::Usage(
+            "clippy is not installed: install it with `rustup component add clippy`".to_string(),
+        ));
+    }
+    Err(Error::Image(format!(
+        "the Docker image {} does not include clippy: images built from the current \
+         cargo-acap-build do, so pull it again with `--pull always`, or select one with \
+         `--docker-image`",
+        environment.docker_image()
+    )))
+}
@@ -0,0 +1,117 @@
+use crate::cargo_config::CargoAcapMetadata;
+use crate::cli::device::DeviceOptions;
+use crate::cli::Invocation;
+use crate::device::{Application, Device};
+use crate::error::Error;
+use crate::output::status;
+use crate::package_dot_conf::normalized_app_name;
+use clap::Parser;
+use std::time::Duration;
+
+/// Control an application installed on an Axis product
+#[derive(Parser)]
+pub struct Control {
+    #[clap(flatten)]
+    device: DeviceOptions,
+
+    /// The application to control (defaults to the `app_name` of the current package)
+    #[clap(long, value_name = "NAME")]
+    app_name: Option<String>,
+
+    /// Control the application of the specified binary
+    #[clap(long, value_name = "NAME", conflicts_with = "app-name")]
+    bin: Option<String>,
+}
+
+/// Something to do to an installed application.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Action {
+    Start,
+    Stop,
+    Remove,
+}
+
+impl Action {
+    /// The `action` understood by `applications/control.cgi`.
+    fn name(self) -> &'static str {
+        match self {
+            Action::Start => "start",
+            Action::Stop => "stop",
+            Action::Remove => "remove",
+        }
+    }
+
+    /// Whether `application` is in the state this action should leave it in.
+    fn is_done(self, application: Option<&Application>) -> bool {
+        match self {
+            Action::Start => application.is_some_and(|app| app.is_running()),
+            Action::Stop => application.is_some_and(|app| !app.is_running()),
+            Action::Remove => application.is_none(),
+        }
+    }
+}
+
+/// How many times to check whether an action took effect
+const CONFIRM_ATTEMPTS: u32 = 10;
+
+impl Control {
+    pub(crate) fn invoke(self, invocation: Invocation, action: Action) -> Result<(), Error> {
+        let device = self.device.device(invocation.global_options().verbose)?;
+        let app_name = app_name(&invocation, self.app_name.as_deref(), self.bin.as_deref())?;
+
+        if device.application(&app_name)?.is_none() {
+            return Err(Error::Usage(format!(
+                "{} is not installed on {}",
+                app_name,
+                device.base_url()
+            )));
+        }
+
+        device.control(action.name(), &app_name)?;
+        confirm(&device, &app_name, action)
+    }
+}
+
+/// The application named by `--app-name`, or else the `app_name` of the current package, or of
+/// the binary given by `--bin`.
+pub(crate) fn app_name(
+    invocation: &Invocation,
+    app_name: Option<&str>,
+    bin: Option<&str>,
+) -> Result<String, Error> {
+    Ok(match app_name {
+        Some(app_name) => app_name.to_string(),
+        None => match CargoAcapMetadata::for_package(&invocation.cargo_package)?.app_name {
+            Some(app_name) => app_name,
+            None => normalized_app_name(invocation.binary_name(bin)?),
+        },
+    })
+}
+
+/// Wait for `action` to take effect on `app_name`, failing if it doesn't.
+fn confirm(device: &Device, app_name: &str, action: Action) -> Result<(), Error> {
+    let mut application = None;
+    for attempt in 0..CONFIRM_ATTEMPTS {
+        if attempt > 0 {
+            std::thread::sleep(Duration::from_secs(1));
+        }
+        application = device.application(app_name)?;
+        if action.is_done(application.as_ref()) {
+            let state = match action {
+                Action::Start => "started",
+                Action::Stop => "stopped",
+                Action::Remove => "removed",
+            };
+            status!("cargo-acap: {} {}", state, app_name);
+            return Ok(());
+        }
+    }
+
+    let status = application.map_or_else(|| "not installed".to_string(), |app| app.status);
+    Err(Error::Usage(format!(
+        "the device reports {} is {} after `{}`",
+        app_name,
+        status,
+        action.name()
+    )))
+}
@@ -0,0 +1,271 @@
+use crate::cli::build::{Build, BuildOptions};
+use crate::cli::Invocation;
+use crate::device::DeviceError;
+use crate::devices_dot_toml;
+use crate::eap::{entry_name, read_eap};
+use crate::error::Error;
+use crate::output::status;
+use crate::package_dot_conf::PackageDotConf;
+use crate::target::Target;
+use clap::Parser;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Build an ACAP application and copy its executable onto an Axis product which already has it
+/// installed, restarting it, which is faster than installing the whole package
+#[derive(Parser)]
+pub struct Deploy {
+    /// How to copy the executable
+    #[clap(long, arg_enum, value_name = "METHOD", default_value = "ssh")]
+    method: Method,
+
+    /// A device named in `devices.toml`, whose host applies unless overridden
+    #[clap(long = "device", env = "CARGO_ACAP_DEVICE", value_name = "NAME")]
+    device_name: Option<String>,
+
+    /// The device's hostname or address, e.g. `192.168.0.90`
+    #[clap(long, env = "CARGO_ACAP_HOST", value_name = "HOST")]
+    host: Option<String>,
+
+    /// The user to log in to the device as, who must be able to replace the executable and
+    /// restart the application
+    #[clap(
+        long,
+        env = "CARGO_ACAP_SSH_USER",
+        value_name = "USER",
+        default_value = "root"
+    )]
+    ssh_user: String,
+
+    /// The private key with which to log in, if not one `ssh` finds itself
+    #[clap(long, value_name = "PATH")]
+    identity_file: Option<PathBuf>,
+
+    /// The device's SSH port, if not 22
+    #[clap(long, value_name = "PORT")]
+    ssh_port: Option<u16>,
+
+    /// The target to deploy (defaults to the package's only target, if it has just one)
+    #[clap(short, long)]
+    target: Option<Target>,
+
+    #[clap(flatten)]
+    build: BuildOptions,
+}
+
+/// How `cargo acap deploy` copies the executable onto the device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum Method {
+    /// Copy it with `scp` and restart the application with `ssh`, which needs SSH enabled on the
+    /// device
+    Ssh,
+}
+
+impl Deploy {
+    pub(crate) fn invoke(self, invocation: Invocation) -> Result<(), Error> {
+        let host = self.host()?;
+        let target = self.target(&invocation)?;
+        let package_conf = PackageDotConf::from_cargo_package(
+            &invocation.cargo_package,
+            self.build.binary_name(&invocation)?,
+            None,
+            target,
+        )?;
+
+        let eap = Build::for_targets(vec![target], self.build.clone())
+            .build_eaps(&invocation)?
+            .remove(0);
+        // The packaged executable is the one to deploy, stripped or not as the build options say
+        let executable = invocation
+            .acap_target()
+            .join(target.rust_target_triple())
+            .join(format!("{}.deploy", package_conf.executable_name));
+        extract(&eap, &package_conf.executable_name, &executable)?;
+
+        let verbose = invocation.global_options().verbose;
+        match self.method {
+            Method::Ssh => self.deploy_over_ssh(&host, &package_conf, &executable, verbose)?,
+        }
+        status!(
+            "cargo-acap: deployed and restarted {} on {}",
+            package_conf.app_name,
+            host
+        );
+        Ok(())
+    }
+
+    /// Replace `package_conf`'s executable on `host` with `executable` using `scp`, and restart
+    /// the application using `ssh`.
+    fn deploy_over_ssh(
+        &self,
+        host: &str,
+        package_conf: &PackageDotConf,
+        executable: &Path,
+        verbose: u8,
+    ) -> Result<(), Error> {
+        let app_name = &package_conf.app_name;
+        let executable_name = &package_conf.executable_name;
+        let destination = format!("{}@{}", self.ssh_user, host);
+        let dir = format!("/usr/local/packages/{}", app_name);
+
+        let mut ssh = self.ssh_command("ssh", "-p");
+        ssh.arg(&destination).arg(format!(
+            "[ -d {dir} ] || {{ echo '{app} is not installed on the device: install it once \
+             with `cargo acap install`, then deploy' >&2; exit 1; }}",
+            dir = dir,
+            app = app_name
+        ));
+        run(ssh, "checking that the application is installed", verbose)?;
+
+        status!(
+            "cargo-acap: copying {} to {}:{}",
+            executable_name,
+            destination,
+            dir
+        );
+        let mut scp = self.ssh_command("scp", "-P");
+        scp.arg("-q")
+            .arg(executable)
+            .arg(format!("{}:{}/{}.new", destination, dir, executable_name));
+        run(scp, "copying the executable", verbose)?;
+
+        // Replace the executable atomically, keeping its owner, which the application runs as
+        let script = format!(
+            "set -e\n\
+             cd {dir}\n\
+             chmod 755 {exe}.new\n\
+             if [ -e {exe} ]; then chown \"$(stat -c %u:%g {exe})\" {exe}.new; fi\n\
+             mv -f {exe}.new {exe}\n\
+             if ! command -v systemctl >/dev/null 2>&1; then\n\
+             echo 'systemctl not found: restart the application from the web pages' >&2\n\
+             exit 1\n\
+             fi\n\
+             systemctl restart sdk{app}.service\n",
+            dir = dir,
+            exe = executable_name,
+            app = app_name,
+        );
+        let mut ssh = self.ssh_command("ssh", "-p");
+        ssh.arg(&destination).arg(script);
+        run(ssh, "restarting the application", verbose)
+    }
+
+    /// The device's host, as given by --host, or else by the `devices.toml` entry selected by
+    /// --device, without any URL scheme.
+    fn host(&self) -> Result<String, Error> {
+        let host = match (&self.host, &self.device_name) {
+            (Some(host), _) => host.clone(),
+            (None, Some(name)) => devices_dot_toml::device(name)?.host.ok_or_else(|| {
+                Error::Usage(format!("device `{}` in devices.toml has no host", name))
+            })?,
+            (None, None) => {
+                return Err(Error::Usage(
+                    "no device specified: use --host, CARGO_ACAP_HOST, or --device".to_string(),
+                ))
+            }
+        };
+        // `devices.toml` may well give a URL, for VAPIX
+        Ok(match url::Url::parse(&host) {
+            Ok(url) if url.has_host() => url.host_str().unwrap_or_default().to_string(),
+            _ => host,
+        })
+    }
+
+    /// The target to deploy: the one given by --target, or else the package's only one.
+    ///
+    /// Only one executable can be deployed, so several targets need a choice to be made.
+    fn target(&self, invocation: &Invocation) -> Result<Target, Error> {
+        if let Some(target) = self.target {
+            return Ok(target);
+        }
+        match Build::for_targets(vec![], self.build.clone())
+            .targets(invocation)?
+            .as_slice()
+        {
+            [target] => Ok(*target),
+            targets => {
+                let names: Vec<&str> = targets.iter().map(|t| t.name()).collect();
+                Err(Error::Usage(format!(
+                    "the package builds targets {}, but only one can be deployed: choose it with \
+                     --target",
+                    names.join(", ")
+                )))
+            }
+        }
+    }
+
+    /// Construct `program`, i.e. `ssh` or `scp`, with the key and port options, the latter given
+    /// by `port_flag`, since they differ.
+    fn ssh_command(&self, program: &str, port_flag: &str) -> Command {
+        let mut command = Command::new(program);
+        if let Some(identity_file) = &self.identity_file {
+            command.arg("-i").arg(identity_file);
+        }
+        if let Some(port) = self.ssh_port {
+            command.arg(port_flag).arg(port.to_string());
+        }
+        command
+    }
+}
+
+/// Write the entry `name` in `eap` to `path`.
+fn extract(eap: &Path, name: &str, path: &Path) -> Result<(), Error> {
+    let mut found = false;
+    read_eap(eap, |entry| {
+        if !found && entry_name(entry)? == name {
+            let mut file = File::create(path)?;
+            std::io::copy(entry, &mut file)?;
+            found = true;
+        }
+        Ok(())
+    })?;
+    if found {
+        Ok(())
+    } else {
+        Err(Error::Package(format!(
+            "{} has no executable `{}`",
+            eap.display(),
+            name
+        )))
+    }
+}
+
+/// Run `command`, an `ssh` or `scp` doing `what`, failing with what it said if it fails, and
+/// saying why if it was refused permission.
+fn run(mut command: Command, what: &str, verbose: u8) -> Result<(), Error> {
+    if verbose > 1 {
+        eprintln!("+ {:?}", &command);
+    }
+    let program = command.get_program().to_string_lossy().into_owned();
+    // Passwords are prompted for on the terminal itself, not on stdin
+    let output = command
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|source| Error::Spawn {
+            program: program.clone(),
+            source,
+        })?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let message = if stderr.contains("Permission denied (") {
+        format!(
+            "the device refused to log in over SSH while {}: check --ssh-user and \
+             --identity-file, and that SSH is enabled on the device: {}",
+            what, stderr
+        )
+    } else if stderr.contains("Permission denied") || stderr.contains("Operation not permitted") {
+        format!(
+            "the device refused permission while {}: deploy as a user who can replace the \
+             executable and restart the application, e.g. --ssh-user root: {}",
+            what, stderr
+        )
+    } else {
+        format!("`{}` failed while {}: {}", program, what, stderr)
+    };
+    Err(DeviceError::Ssh(message).into())
+}
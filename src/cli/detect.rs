@@ -0,0 +1,158 @@
+use crate::cli::device::DeviceOptions;
+use crate::device::{target_for_architecture, DeviceError};
+use crate::error::Error;
+use crate::target::{Architecture, Target, SOC};
+use clap::Parser;
+
+/// Show which target an Axis product needs, from its architecture and SoC
+#[derive(Parser)]
+pub struct Detect {
+    #[clap(flatten)]
+    device: DeviceOptions,
+}
+
+/// Where to report architectures `cargo acap` doesn't recognize
+const ISSUES_URL: &str = "https://github.com/trunnion/cargo-acap/issues";
+
+impl Detect {
+    pub(crate) fn invoke(self, verbose: u8) -> Result<(), Error> {
+        let device = self.device.device(verbose)?;
+        let parameters = device.parameters("root.Properties.System")?;
+        let detection = Detection::from_parameters(&parameters)?;
+
+        match detection.soc {
+            Some(Ok(soc)) => println!("SoC:          {} ({})", soc, soc.year()),
+            Some(Err(soc)) => println!("SoC:          {}", soc),
+            None => {}
+        }
+        println!("Architecture: {}", detection.architecture);
+
+        match detection.target {
+            Ok(target) => {
+                println!("Target:       {}", target);
+                println!("Rust triple:  {}", target.rust_target_triple());
+                Ok(())
+            }
+            Err(e) => {
+                if Architecture::from_vapix(detection.architecture).is_none() {
+                    eprintln!(
+                        "cargo-acap: `{}` is not an architecture `cargo acap` recognizes: please \
+                         report it at {}",
+                        detection.architecture, ISSUES_URL
+                    );
+                }
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// What a device's `root.Properties.System` parameters say about the target it needs.
+#[derive(Debug)]
+struct Detection<'a> {
+    /// The SoC, or its name if it isn't one `cargo acap` knows, if the firmware reports it
+    soc: Option<Result<SOC, &'a str>>,
+    architecture: &'a str,
+    target: Result<Target, DeviceError>,
+}
+
+impl<'a> Detection<'a> {
+    fn from_parameters(parameters: &'a [(String, String)]) -> Result<Self, DeviceError> {
+        let parameter = |name: &str| {
+            let name = format!("root.Properties.System.{}", name);
+            parameters
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        // Older firmware doesn't report its SoC
+        let soc = parameter("Soc").map(|soc| SOC::from_alias(soc).ok_or(soc));
+        let architecture = parameter("Architecture").ok_or_else(|| {
+            DeviceError::Response("the device did not report its architecture".to_string())
+        })?;
+        Ok(Detection {
+            soc,
+            architecture,
+            target: target_for_architecture(architecture),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::parse_parameters;
+
+    /// The `root.Properties.System` group as listed by `param.cgi`, with the given parameters.
+    fn response(architecture: Option<&str>, soc: Option<&str>) -> Vec<(String, String)> {
+        let mut body = "root.Properties.System.HardwareID=7A1\r\n".to_string();
+        if let Some(architecture) = architecture {
+            body += &format!("root.Properties.System.Architecture={}\r\n", architecture);
+        }
+        body += "root.Properties.System.Language=English\r\n";
+        if let Some(soc) = soc {
+            body += &format!("root.Properties.System.Soc={}\r\n", soc);
+        }
+        body += "root.Properties.System.SerialNumber=ACCC8E000000\r\n";
+        parse_parameters(body).unwrap()
+    }
+
+    #[test]
+    fn devices_map_to_the_target_for_their_architecture() {
+        for (architecture, soc, expected_soc, target) in [
+            ("aarch64", "Axis Artpec-8", SOC::Artpec8, Target::Aarch64),
+            ("aarch64", "Ambarella CV25", SOC::CV25, Target::Aarch64),
+            ("armv7hf", "Axis Artpec-7", SOC::Artpec7, Target::Armv7Hf),
+            ("armv7hf", "Ambarella S2L", SOC::S2L, Target::Armv7Hf),
+            ("mips", "Axis Artpec-5", SOC::Artpec5, Target::Mips),
+            ("armv6", "Ambarella A5S", SOC::A5S, Target::Armv6),
+        ] {
+            let parameters = response(Some(architecture), Some(soc));
+            let detection = Detection::from_parameters(&parameters).unwrap();
+            assert_eq!(detection.soc, Some(Ok(expected_soc)));
+            assert_eq!(detection.architecture, architecture);
+            assert_eq!(detection.target.unwrap(), target, "{}", soc);
+        }
+    }
+
+    #[test]
+    fn older_firmware_and_unknown_socs_still_map_to_a_target() {
+        let parameters = response(Some("mips"), None);
+        let detection = Detection::from_parameters(&parameters).unwrap();
+        assert_eq!(detection.soc, None);
+        assert_eq!(detection.target.unwrap(), Target::Mips);
+
+        let parameters = response(Some("aarch64"), Some("Axis Artpec-99"));
+        let detection = Detection::from_parameters(&parameters).unwrap();
+        assert_eq!(detection.soc, Some(Err("Axis Artpec-99")));
+        assert_eq!(detection.target.unwrap(), Target::Aarch64);
+    }
+
+    #[test]
+    fn unsupported_architectures_are_errors() {
+        for architecture in ["crisv32", "riscv64"] {
+            let parameters = response(Some(architecture), None);
+            let detection = Detection::from_parameters(&parameters).unwrap();
+            assert_eq!(detection.architecture, architecture);
+            assert!(matches!(
+                detection.target,
+                Err(DeviceError::UnsupportedArchitecture(a)) if a == architecture
+            ));
+        }
+
+        let parameters = response(None, Some("Axis Artpec-7"));
+        assert!(matches!(
+            Detection::from_parameters(&parameters),
+            Err(DeviceError::Response(_))
+        ));
+    }
+
+    #[test]
+    fn responses_without_parameters_are_errors() {
+        assert!(matches!(
+            parse_parameters("Error -1 getting param in group 'root.Properties.System'\r\n".into()),
+            Err(DeviceError::Response(_))
+        ));
+    }
+}
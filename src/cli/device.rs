@@ -0,0 +1,166 @@
+use crate::device::Device;
+use crate::devices_dot_toml::{self, DeviceProfile};
+use crate::error::{Error, IoResultExt};
+use clap::Parser;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+
+/// How to reach and authenticate with an Axis product
+#[derive(Debug, Clone, Parser)]
+pub struct DeviceOptions {
+    /// A device named in `devices.toml`, whose settings apply unless overridden
+    #[clap(long = "device", env = "CARGO_ACAP_DEVICE", value_name = "NAME")]
+    device_name: Option<String>,
+
+    /// The device's hostname, address, or URL, e.g. `192.168.0.90` or `https://camera.local`
+    #[clap(long, env = "CARGO_ACAP_HOST", value_name = "HOST")]
+    host: Option<String>,
+
+    #[clap(flatten)]
+    credentials: Credentials,
+
+    /// Don't verify the device's TLS certificate, e.g. because it is self-signed
+    #[clap(long)]
+    insecure: bool,
+}
+
+impl DeviceOptions {
+    /// The device selected by these options, failing if it's not fully specified.
+    ///
+    /// Options and environment variables take precedence over the `devices.toml` entry selected
+    /// by --device, and the password is prompted for if it's given by neither.
+    pub fn device(&self, verbose: u8) -> Result<Device, Error> {
+        let profile = match &self.device_name {
+            Some(name) => devices_dot_toml::device(name)?,
+            None => DeviceProfile::default(),
+        };
+
+        let host = self.host.clone().or(profile.host.clone()).ok_or_else(|| {
+            Error::Usage(
+                "no device specified: use --host, CARGO_ACAP_HOST, or --device".to_string(),
+            )
+        })?;
+
+        let (user, pass) = match self.credentials.file_credentials()? {
+            Some(credentials) => credentials,
+            None => {
+                let user = self
+                    .credentials
+                    .user
+                    .clone()
+                    .or(profile.user.clone())
+                    .unwrap_or_else(|| "root".to_string());
+                let pass = match self.credentials.pass.clone().or_else(|| profile.password()) {
+                    Some(pass) => pass,
+                    None => prompt_password(&user, &host)?,
+                };
+                (user, pass)
+            }
+        };
+
+        Ok(Device::new(
+            &host,
+            &user,
+            &pass,
+            self.insecure || profile.insecure,
+            verbose,
+        ))
+    }
+}
+
+/// How to authenticate with an Axis product
+#[derive(Debug, Clone, Parser)]
+pub struct Credentials {
+    /// The username with which to authenticate [default: root]
+    #[clap(long, env = "CARGO_ACAP_USER")]
+    user: Option<String>,
+
+    /// The password with which to authenticate
+    #[clap(long, env = "CARGO_ACAP_PASS", hide_env_values = true)]
+    pass: Option<String>,
+
+    /// A file containing `user:password`, as an alternative to --user and --pass
+    #[clap(long, value_name = "PATH", conflicts_with = "pass")]
+    credentials_file: Option<PathBuf>,
+}
+
+impl Credentials {
+    /// The username and password, or `None` if no password was given.
+    pub fn resolve(&self) -> Result<Option<(String, String)>, Error> {
+        if let Some(credentials) = self.file_credentials()? {
+            return Ok(Some(credentials));
+        }
+        Ok(self.pass.clone().map(|pass| {
+            let user = self.user.clone().unwrap_or_else(|| "root".to_string());
+            (user, pass)
+        }))
+    }
+
+    /// The username and password in --credentials-file, if given.
+    fn file_credentials(&self) -> Result<Option<(String, String)>, Error> {
+        let path = match &self.credentials_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let contents = std::fs::read_to_string(path).with_path(path)?;
+        devices_dot_toml::warn_if_readable_by_others(path);
+        match contents.trim_end_matches(&['\r', '\n'][..]).split_once(':') {
+            Some((user, pass)) => Ok(Some((user.to_string(), pass.to_string()))),
+            None => Err(Error::Usage(format!(
+                "{} must contain `user:password`",
+                path.display()
+            ))),
+        }
+    }
+}
+
+/// Ask for `user`'s password on `host`, if there's a terminal to ask it on.
+fn prompt_password(user: &str, host: &str) -> Result<String, Error> {
+    if !std::io::stdin().is_terminal() {
+        return Err(Error::Usage(
+            "no password specified: use --pass, CARGO_ACAP_PASS, --credentials-file, or a \
+             `devices.toml` entry with `pass_env` or `pass`"
+                .to_string(),
+        ));
+    }
+
+    eprint!("Password for {}@{}: ", user, host);
+    std::io::stderr().flush().ok();
+    read_password().with_path("stdin")
+}
+
+/// Read a line from the terminal on stdin without echoing it.
+#[cfg(unix)]
+fn read_password() -> std::io::Result<String> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = std::io::stdin().as_raw_fd();
+    let mut termios = std::mem::MaybeUninit::uninit();
+    // Safety: tcgetattr() initializes termios if it succeeds
+    let original = unsafe {
+        if libc::tcgetattr(fd, termios.as_mut_ptr()) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        termios.assume_init()
+    };
+    let mut silent = original;
+    silent.c_lflag &= !libc::ECHO;
+    silent.c_lflag |= libc::ECHONL;
+
+    // Safety: both termios are fully initialized
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+    let mut line = String::new();
+    let result = std::io::stdin().read_line(&mut line);
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+
+    result.map(|_| line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// Read a line from the terminal on stdin, which is echoed since there's no portable way not to.
+#[cfg(not(unix))]
+fn read_password() -> std::io::Result<String> {
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
@@ -0,0 +1,184 @@
+use crate::cli::device::Credentials;
+use crate::device::{target_for_architecture, Device, DeviceError};
+use crate::discovery::{self, Discovered};
+use crate::error::Error;
+use crate::target::Target;
+use clap::Parser;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Find Axis products on the local network, by mDNS and WS-Discovery
+#[derive(Parser)]
+pub struct Devices {
+    /// How long to wait for devices to respond, in seconds
+    #[clap(long, value_name = "SECONDS", default_value = "3")]
+    timeout: u64,
+
+    /// The output format
+    #[clap(long, arg_enum, value_name = "FMT", default_value = "table")]
+    format: Format,
+
+    /// With a password, each device is also asked for its firmware version and architecture
+    #[clap(flatten)]
+    credentials: Credentials,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum Format {
+    Table,
+    Json,
+}
+
+/// A device, as listed by `--format json`.
+///
+/// The field names are relied upon by tools, so they must not change.
+#[derive(Debug, Serialize)]
+struct DeviceEntry {
+    address: IpAddr,
+    model: Option<String>,
+    serial: Option<String>,
+    /// `null` unless credentials were given and the device accepted them
+    firmware: Option<String>,
+    architecture: Option<String>,
+    target: Option<Target>,
+}
+
+impl Devices {
+    pub(crate) fn invoke(self, verbose: u8) -> Result<(), Error> {
+        let discovered = discovery::discover(Duration::from_secs(self.timeout))
+            .map_err(DeviceError::Discovery)?;
+
+        let credentials = self.credentials.resolve()?;
+        let entries: Vec<DeviceEntry> = std::thread::scope(|scope| {
+            let handles: Vec<_> = discovered
+                .into_iter()
+                .map(|(address, found)| {
+                    let credentials = credentials.as_ref();
+                    scope.spawn(move || entry(address, found, credentials, verbose))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("device query thread panicked"))
+                .collect()
+        });
+
+        match self.format {
+            Format::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&entries).expect("error serializing devices")
+            ),
+            Format::Table if entries.is_empty() => {}
+            Format::Table => print_table(&entries, credentials.is_some()),
+        }
+        if entries.is_empty() {
+            eprintln!(
+                "cargo-acap: no devices responded within {}s: check that they're on this network, \
+                 and that multicast isn't blocked, or try a longer --timeout",
+                self.timeout
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The entry for the device at `address`, asking it for its firmware and architecture if there
+/// are `credentials`.
+fn entry(
+    address: IpAddr,
+    found: Discovered,
+    credentials: Option<&(String, String)>,
+    verbose: u8,
+) -> DeviceEntry {
+    let mut entry = DeviceEntry {
+        address,
+        model: found.model,
+        serial: found.serial,
+        firmware: None,
+        architecture: None,
+        target: None,
+    };
+    let (user, pass) = match credentials {
+        Some(credentials) => credentials,
+        None => return entry,
+    };
+
+    let host = match address {
+        IpAddr::V4(address) => address.to_string(),
+        IpAddr::V6(address) => format!("[{}]", address),
+    };
+    let device = Device::new(&host, user, pass, false, verbose);
+    let parameters = match device.parameters("root.Properties") {
+        Ok(parameters) => parameters,
+        Err(e) => {
+            eprintln!("cargo-acap: warning: {}: {}", address, e);
+            return entry;
+        }
+    };
+    let parameter = |name: &str| {
+        let name = format!("root.Properties.{}", name);
+        parameters
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.clone())
+    };
+
+    entry.firmware = parameter("Firmware.Version");
+    entry.architecture = parameter("System.Architecture");
+    entry.target = entry
+        .architecture
+        .as_deref()
+        .and_then(|a| target_for_architecture(a).ok());
+    entry.serial = entry.serial.or_else(|| parameter("System.SerialNumber"));
+    entry
+}
+
+/// Print `entries` as a table, with the columns which need credentials if `detailed`.
+fn print_table(entries: &[DeviceEntry], detailed: bool) {
+    let mut headings = vec!["ADDRESS", "MODEL", "SERIAL"];
+    if detailed {
+        headings.extend(["FIRMWARE", "ARCHITECTURE", "TARGET"]);
+    }
+
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|entry| {
+            let cell = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+            let mut row = vec![
+                entry.address.to_string(),
+                cell(&entry.model),
+                cell(&entry.serial),
+            ];
+            if detailed {
+                row.push(cell(&entry.firmware));
+                row.push(cell(&entry.architecture));
+                row.push(cell(&entry.target.map(|t| t.name().to_string())));
+            }
+            row
+        })
+        .collect();
+
+    let widths: Vec<usize> = (0..headings.len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].len())
+                .chain(Some(headings[i].len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let print_row = |cells: &[&str]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+
+    print_row(&headings);
+    for row in &rows {
+        print_row(&row.iter().map(String::as_str).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,252 @@
+use crate::cargo_config::CargoAcapMetadata;
+use crate::cli::environment::{self, ContainerEngine};
+use crate::cli::{cargo_config, load_workspace, select_package, GlobalOptions};
+use crate::error::Error;
+use clap::Parser;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Check that `cargo acap` can build here, explaining how to fix anything which would stop it
+#[derive(Parser)]
+pub struct Doctor {
+    /// Skip the checks of `docker` and its image, for building with the host's toolchain
+    #[clap(long)]
+    no_docker: bool,
+}
+
+/// The outcome of one check: what was found, or what's wrong and how to fix it.
+enum Outcome {
+    Pass(String),
+    Fail {
+        problem: String,
+        hint: String,
+    },
+    /// The check can't be made, because of an earlier failure or an option
+    Skip(String),
+}
+
+impl Outcome {
+    fn fail(problem: impl Into<String>, hint: impl Into<String>) -> Self {
+        Outcome::Fail {
+            problem: problem.into(),
+            hint: hint.into(),
+        }
+    }
+}
+
+impl Doctor {
+    pub(crate) fn invoke(self, global_options: GlobalOptions) -> Result<(), Error> {
+        let mut failures = 0;
+        let mut report = |name: &str, outcome: Outcome| match outcome {
+            Outcome::Pass(detail) => println!("ok    {}: {}", name, detail),
+            Outcome::Skip(reason) => println!("skip  {}: {}", name, reason),
+            Outcome::Fail { problem, hint } => {
+                failures += 1;
+                let mut lines = problem.lines();
+                println!("FAIL  {}: {}", name, lines.next().unwrap_or_default());
+                for line in lines.chain(hint.lines()) {
+                    println!("      {}", line);
+                }
+            }
+        };
+
+        let engine = global_options.container_engine.resolve();
+        let engine_ok = if self.no_docker {
+            report("container engine", Outcome::Skip("--no-docker".into()));
+            false
+        } else {
+            let outcome = check_engine(engine);
+            let ok = matches!(outcome, Outcome::Pass(_));
+            report("container engine", outcome);
+            ok
+        };
+
+        let manifest_path = &global_options.manifest_path;
+        let cargo_config = cargo_config(manifest_path);
+        let workspace = cargo_config
+            .as_ref()
+            .map_err(|e| e.to_string())
+            .and_then(|config| {
+                let manifest_path = manifest_path.canonicalize().map_err(|e| {
+                    format!(
+                        "{}: {}: run in a package's directory, or pass --manifest-path",
+                        manifest_path.display(),
+                        e
+                    )
+                })?;
+                load_workspace(&manifest_path, config).map_err(|e| e.to_string())
+            });
+        let workspace = match workspace {
+            Ok(workspace) => {
+                report(
+                    "manifest",
+                    Outcome::Pass(manifest_path.display().to_string()),
+                );
+                Some(workspace)
+            }
+            Err(problem) => {
+                report(
+                    "manifest",
+                    Outcome::fail(
+                        problem,
+                        "fix Cargo.toml, then run `cargo metadata` to check it",
+                    ),
+                );
+                None
+            }
+        };
+
+        let metadata = match &workspace {
+            Some(workspace) => {
+                match select_package(workspace, global_options.package.as_deref(), false)
+                    .and_then(CargoAcapMetadata::for_package)
+                {
+                    Ok(_) => Outcome::Pass("[package.metadata.acap] is valid".into()),
+                    Err(e) => Outcome::fail(
+                        e.to_string(),
+                        "see the README for the keys of [package.metadata.acap]",
+                    ),
+                }
+            }
+            None => Outcome::Skip("the manifest didn't load".into()),
+        };
+        report("metadata", metadata);
+
+        let rustc = match (&workspace, &cargo_config) {
+            (Some(workspace), Ok(config)) => config
+                .load_global_rustc(Some(workspace))
+                .map_err(|e| format!("{:#}", e)),
+            _ => Err("the manifest didn't load".into()),
+        };
+        let rustc_version = match rustc {
+            Ok(rustc) => {
+                report("rustc", Outcome::Pass(rustc.version.to_string()));
+                Some(rustc.version.to_string())
+            }
+            Err(problem) if workspace.is_none() => {
+                report("rustc", Outcome::Skip(problem));
+                None
+            }
+            Err(problem) => {
+                report(
+                    "rustc",
+                    Outcome::fail(problem, "install Rust with rustup: https://rustup.rs"),
+                );
+                None
+            }
+        };
+
+        let image = match &rustc_version {
+            Some(version) => environment::with_default_tag(&global_options.docker_image, version),
+            None => global_options.docker_image.clone(),
+        };
+        let image_outcome = if self.no_docker {
+            Outcome::Skip("--no-docker".into())
+        } else if !engine_ok {
+            Outcome::Skip(format!("`{}` isn't usable", engine.program()))
+        } else {
+            check_image(engine, &image)
+        };
+        report("image", image_outcome);
+
+        let target = match &workspace {
+            Some(workspace) => check_target_dir(workspace.target_dir().as_path_unlocked()),
+            None => Outcome::Skip("the manifest didn't load".into()),
+        };
+        report("target directory", target);
+
+        if failures > 0 {
+            return Err(Error::Usage(format!(
+                "{} check{} failed",
+                failures,
+                if failures == 1 { "" } else { "s" }
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Check that `engine` is installed and can reach its daemon, if it has one.
+fn check_engine(engine: ContainerEngine) -> Outcome {
+    let program = engine.program();
+    let format = match engine {
+        ContainerEngine::Podman => "{{.Client.Version}}",
+        ContainerEngine::Auto | ContainerEngine::Docker => "{{.Server.Version}}",
+    };
+    let output = Command::new(program)
+        .args(["version", "--format", format])
+        .stdin(Stdio::null())
+        .output();
+
+    match output {
+        Ok(output) if output.status.success() => Outcome::Pass(format!(
+            "{} {}",
+            program,
+            String::from_utf8_lossy(&output.stdout).trim()
+        )),
+        Ok(output) => Outcome::fail(
+            format!(
+                "`{} version` failed: {}",
+                program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            format!(
+                "start the {} daemon, and make sure your user may use it, e.g. by joining the \
+                 `docker` group",
+                program
+            ),
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Outcome::fail(
+            format!("`{}` is not installed", program),
+            "install docker or podman, select another --container-engine, or build with \
+             --no-docker",
+        ),
+        Err(e) => Outcome::fail(
+            format!("error running `{}`: {}", program, e),
+            format!("check that `{}` can be run", program),
+        ),
+    }
+}
+
+/// Check that `image` is available locally, or else published so it can be pulled.
+fn check_image(engine: ContainerEngine, image: &str) -> Outcome {
+    let succeeds = |args: &[&str]| {
+        Command::new(engine.program())
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+
+    if succeeds(&["image", "inspect", image]) {
+        Outcome::Pass(format!("{} is available locally", image))
+    } else if succeeds(&["manifest", "inspect", image]) {
+        Outcome::Pass(format!("{} is published, and will be pulled", image))
+    } else {
+        Outcome::fail(
+            format!("{} is neither available locally nor published", image),
+            "images are tagged by Rust version, and the host's `rustc` may be newer than any \
+             published image:\nuse --docker-image to select another tag, install the matching \
+             Rust version, or build with --no-docker",
+        )
+    }
+}
+
+/// Check that `cargo acap` can write its builds within `target_dir`.
+fn check_target_dir(target_dir: &Path) -> Outcome {
+    let acap_target = target_dir.join("acap");
+    let probe = acap_target.join(".cargo-acap-doctor");
+    let result = std::fs::create_dir_all(&acap_target)
+        .and_then(|_| std::fs::write(&probe, b""))
+        .and_then(|_| std::fs::remove_file(&probe));
+    match result {
+        Ok(()) => Outcome::Pass(format!("{} is writable", acap_target.display())),
+        Err(e) => Outcome::fail(
+            format!("{} is not writable: {}", acap_target.display(), e),
+            "builds by older versions or by root-owned containers can leave files you don't \
+             own:\nremove the directory, or take ownership of it, e.g. `sudo chown -R $USER target`",
+        ),
+    }
+}
@@ -0,0 +1,1581 @@
+use crate::error::{Error, IoResultExt};
+use crate::interrupt::{self, Container};
+use crate::output::{self, status};
+use crate::whoami::{whoami, Whoami};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+
+/// The home directory of the user inside the container, when it needs one.
+const CONTAINER_HOME: &str = "/home/cargo-acap";
+
+/// When to pull the `docker` image before building.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum Pull {
+    /// Pull the image every time, so that it's guaranteed to be fresh
+    Always,
+    /// Pull the image only if it isn't available locally
+    Missing,
+    /// Never pull the image, failing if it isn't available locally
+    Never,
+}
+
+/// The program which runs build containers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum ContainerEngine {
+    /// Use `docker` if it's installed, or else `podman`
+    Auto,
+    Docker,
+    Podman,
+}
+
+impl ContainerEngine {
+    /// Choose an engine for `Auto`, based on which programs are installed.
+    pub fn resolve(self) -> Self {
+        match self {
+            ContainerEngine::Auto if !is_installed("docker") && is_installed("podman") => {
+                ContainerEngine::Podman
+            }
+            ContainerEngine::Auto => ContainerEngine::Docker,
+            engine => engine,
+        }
+    }
+
+    /// The engine's executable.
+    pub fn program(self) -> &'static str {
+        match self {
+            ContainerEngine::Auto | ContainerEngine::Docker => "docker",
+            ContainerEngine::Podman => "podman",
+        }
+    }
+}
+
+/// Whom to run containers as, instead of the invoking user.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ContainerUser {
+    /// The image's default user
+    ImageDefault,
+    /// The user and group with these IDs
+    Id { uid: u32, gid: u32 },
+}
+
+impl std::str::FromStr for ContainerUser {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "none" {
+            return Ok(ContainerUser::ImageDefault);
+        }
+        s.split_once(':')
+            .and_then(|(uid, gid)| Some((uid.parse().ok()?, gid.parse().ok()?)))
+            .map(|(uid, gid)| ContainerUser::Id { uid, gid })
+            .ok_or_else(|| format!("expected UID:GID or `none`, found `{}`", s))
+    }
+}
+
+/// The repository of the images this project publishes, which are built only for x86-64
+pub const PUBLISHED_IMAGE: &str = "ghcr.io/trunnion/cargo-acap";
+
+/// Whether `program` can be found in `PATH`.
+fn is_installed(program: &str) -> bool {
+    let file_name = format!("{}{}", program, std::env::consts::EXE_SUFFIX);
+    std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).any(|dir| dir.join(&file_name).is_file()))
+        .unwrap_or(false)
+}
+
+/// Where and how build commands run: inside a `docker` container, or directly on the host.
+///
+/// This is a snapshot of the relevant parts of an `Invocation`. Unlike `Invocation`, it can be
+/// shared between threads, which allows building several targets at once.
+#[derive(Debug, Clone)]
+pub struct BuildEnvironment {
+    pub(super) verbose: u8,
+    pub(super) container_engine: ContainerEngine,
+    pub(super) docker_image: String,
+    pub(super) docker_args: Vec<String>,
+    pub(super) container_user: Option<ContainerUser>,
+    pub(super) env: Vec<(String, String)>,
+    pub(super) ssh_agent: bool,
+    pub(super) sccache: bool,
+    pub(super) no_docker: bool,
+    pub(super) interactive: bool,
+    pub(super) stdout_to_stderr: bool,
+    pub(super) cargo_home: PathBuf,
+    pub(super) workspace_root: PathBuf,
+    pub(super) package_root: PathBuf,
+    pub(super) package_name: String,
+    pub(super) selected_package: Option<String>,
+    pub(super) acap_target: PathBuf,
+    pub(super) reused_containers: Option<Arc<ReusedContainers>>,
+    pub(super) platforms: Arc<Platforms>,
+}
+
+impl BuildEnvironment {
+    pub fn verbose(&self) -> u8 {
+        self.verbose
+    }
+
+    /// The package name to pass to the inner `cargo`, if one was selected explicitly.
+    pub fn selected_package(&self) -> Option<&str> {
+        self.selected_package.as_deref()
+    }
+
+    /// Run commands without attaching them to the terminal, e.g. because their output will be
+    /// captured.
+    pub fn non_interactive(mut self) -> Self {
+        self.interactive = false;
+        self
+    }
+
+    /// Send commands' stdout to stderr, e.g. because stdout is reserved for machine-readable
+    /// output.
+    pub fn stdout_to_stderr(mut self) -> Self {
+        self.stdout_to_stderr = true;
+        self
+    }
+
+    /// The engine which runs containers, after resolving `auto`.
+    pub fn container_engine(&self) -> ContainerEngine {
+        self.container_engine
+    }
+
+    /// Construct a command which runs the container engine, e.g. `docker`, with no arguments.
+    pub fn engine_command(&self) -> Command {
+        Command::new(self.container_engine.program())
+    }
+
+    /// Forward the host's value of each variable in `names` to build commands, warning about
+    /// those which aren't set.
+    ///
+    /// Variables set explicitly with `--env` take precedence.
+    pub fn forward_env<'a>(mut self, names: impl IntoIterator<Item = &'a String>) -> Self {
+        let mut env = Vec::new();
+        for name in names {
+            match std::env::var(name) {
+                Ok(value) => env.push((name.clone(), value)),
+                Err(std::env::VarError::NotPresent) => {
+                    eprintln!(
+                        "cargo-acap: warning: not forwarding {}: it is not set",
+                        name
+                    )
+                }
+                Err(std::env::VarError::NotUnicode(_)) => {
+                    eprintln!(
+                        "cargo-acap: warning: not forwarding {}: its value is not valid Unicode",
+                        name
+                    )
+                }
+            }
+        }
+        env.append(&mut self.env);
+        self.env = env;
+        self
+    }
+
+    /// The `docker` image in which build commands run.
+    pub fn docker_image(&self) -> &str {
+        &self.docker_image
+    }
+
+    /// Run build commands in `docker_image` instead.
+    pub fn with_docker_image(mut self, docker_image: String) -> Self {
+        self.docker_image = docker_image;
+        self
+    }
+
+    /// Run containers without network access, so that builds can't reach it even by accident.
+    pub fn without_network(mut self) -> Self {
+        self.docker_args.push("--network=none".to_string());
+        self
+    }
+
+    /// Set `key` to `value` for build commands, replacing any value given with `--env`.
+    pub fn with_env(mut self, key: &str, value: String) -> Self {
+        self.env.retain(|(k, _)| k != key);
+        self.env.push((key.to_string(), value));
+        self
+    }
+
+    /// The rustflags which `cargo` would take from this environment, as forwarded to build
+    /// commands, or on the host, inherited by them.
+    pub fn rustflags(&self) -> Vec<String> {
+        // As with `docker run --env`, the last value given for a variable is the one it has
+        let var = |key: &str| {
+            self.env
+                .iter()
+                .rev()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.clone())
+                .or_else(|| std::env::var(key).ok().filter(|_| self.no_docker))
+        };
+        // `cargo` reads the encoded form in preference to the other
+        if let Some(encoded) = var("CARGO_ENCODED_RUSTFLAGS") {
+            encoded
+                .split('\x1f')
+                .filter(|flag| !flag.is_empty())
+                .map(String::from)
+                .collect()
+        } else if let Some(rustflags) = var("RUSTFLAGS") {
+            rustflags.split_whitespace().map(String::from).collect()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Construct a command which runs `program` in this environment.
+    ///
+    /// This fails if a directory the container needs can't be mounted.
+    pub fn command(&self, program: &str) -> Result<Command, Error> {
+        let mut docker = if self.no_docker {
+            return Ok(self.host_command(program));
+        } else if let Some(docker) = self.docker_exec_command()? {
+            docker
+        } else {
+            self.docker_run_command()?
+        };
+        docker.arg(program);
+        Ok(docker)
+    }
+
+    /// The path of `relative` within `target/acap/`, as seen by commands in this environment.
+    pub fn acap_target_path(&self, relative: &str) -> PathBuf {
+        if self.no_docker {
+            self.acap_target.join(relative)
+        } else {
+            PathBuf::from("/target").join(relative)
+        }
+    }
+
+    pub fn docker_run_command(&self) -> Result<Command, Error> {
+        // Start constructing the command
+        let mut docker = self.engine_command();
+        docker.args(["run", "--rm"]);
+        // Name the container so Ctrl-C can stop it, and run an init which passes on the signal
+        docker.args(["--init", "--name", &interrupt::container_name()]);
+        self.terminal_args(&mut docker);
+        self.container_args(&mut docker)?;
+        Ok(docker)
+    }
+
+    /// Construct a command which runs a program in this environment's long-lived container, or
+    /// `None` if containers aren't reused or the container couldn't be started.
+    fn docker_exec_command(&self) -> Result<Option<Command>, Error> {
+        let reused_containers = match self.reused_containers.as_ref() {
+            Some(reused_containers) => reused_containers,
+            None => return Ok(None),
+        };
+        let mut container_args = self.engine_command();
+        self.container_args(&mut container_args)?;
+        let options: Vec<OsString> = container_args.get_args().map(OsStr::to_owned).collect();
+        let name = match reused_containers.get(self, options) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+
+        let mut docker = self.engine_command();
+        docker.arg("exec");
+        self.terminal_args(&mut docker);
+        docker.arg(name);
+        Ok(Some(docker))
+    }
+
+    /// Attach the container to the terminal, if commands are interactive and there is one.
+    fn terminal_args(&self, docker: &mut Command) {
+        if self.interactive && std::io::stdin().is_terminal() {
+            docker.arg("--interactive");
+            let output_is_terminal = if self.stdout_to_stderr {
+                std::io::stderr().is_terminal()
+            } else {
+                std::io::stdout().is_terminal()
+            };
+            if output_is_terminal {
+                docker.arg("--tty");
+            }
+        }
+    }
+
+    /// Add the options of `docker run` which configure the container, followed by the image.
+    fn container_args(&self, docker: &mut Command) -> Result<(), Error> {
+        if let Some(platform) = self.platform() {
+            docker.args(["--platform", platform]);
+        }
+
+        match self.container_user {
+            Some(ContainerUser::ImageDefault) => {}
+            Some(ContainerUser::Id { uid, gid }) => {
+                docker.args(["--user", &format!("{}:{}", uid, gid)]);
+            }
+            // Docker Desktop's file sharing gives files written by any container user to the
+            // invoking user, and the invoking user's uid has no passwd entry in the image
+            None if cfg!(target_os = "macos") => {}
+            None => {
+                docker.args(self.invoking_user_args(&whoami()));
+            }
+        }
+
+        // Mount the workspace at the same path and use the package as the current directory
+        self.bind(
+            docker,
+            &self.workspace_root,
+            &self.workspace_root,
+            false,
+            true,
+        )?;
+        docker.arg("--workdir").arg(&self.package_root);
+
+        // `cargo` reads `.cargo/config.toml` in the current directory and each of its ancestors,
+        // so those within the workspace are already visible, but those above it must be mounted
+        // too. They aren't relabeled, since they're outside the workspace.
+        for path in ancestor_cargo_configs(&self.workspace_root, &self.cargo_home) {
+            self.bind(docker, &path, &path, true, false)?;
+        }
+
+        // Mount target_path at /target and tell `cargo` to use it
+        self.bind(docker, &self.acap_target, Path::new("/target"), false, true)?;
+        docker.args(["--env", "CARGO_TARGET_DIR=/target"]);
+
+        // Give `cargo` a writable scratch CARGO_HOME at /.cargo, and mount the host's registry,
+        // git checkouts, and configuration inside it read-only so that the container can't
+        // corrupt them
+        docker.args(["--tmpfs", "/.cargo:exec,mode=1777"]);
+        docker.args(["--env", "CARGO_HOME=/.cargo"]);
+        for dir in ["registry", "git"] {
+            let target = Path::new("/.cargo").join(dir);
+            self.bind(docker, &self.cargo_home.join(dir), &target, true, true)?;
+        }
+        for file in ["config", "config.toml"] {
+            let path = self.cargo_home.join(file);
+            if path.is_file() {
+                let target = Path::new("/.cargo").join(file);
+                self.bind(docker, &path, &target, true, true)?;
+            }
+        }
+
+        // Pass through some env vars to docker container that cargo might need to fetch dependencies
+        let pass_through_env = [
+            "http_proxy",
+            "https_proxy",
+            "ftp_proxy",
+            "all_proxy",
+            "no_proxy",
+        ];
+        for var in pass_through_env {
+            if let Ok(val) = std::env::var(var) {
+                docker.args(["--env", &format!("{}={}", var, val)]);
+            }
+        }
+
+        if self.ssh_agent {
+            self.mount_ssh_agent(docker)?;
+        }
+
+        if self.sccache {
+            self.configure_sccache(docker)?;
+        }
+
+        for (key, value) in &self.env {
+            docker.args(["--env", &format!("{}={}", key, value)]);
+        }
+        docker.args(&self.docker_args);
+
+        docker.arg(&self.docker_image);
+        Ok(())
+    }
+
+    /// The options which run the container as `whoami`, the invoking user: with the right uid,
+    /// gid, and USER env var.
+    fn invoking_user_args(&self, whoami: &Whoami) -> Vec<String> {
+        let mut args = vec![];
+        // Rootless `podman` maps root inside the container to the invoking user, so it must be
+        // told to keep our uid instead
+        if self.container_engine == ContainerEngine::Podman && whoami.uid != 0 {
+            args.push("--userns=keep-id".to_string());
+        } else {
+            args.push("--user".to_string());
+            args.push(format!("{}:{}", whoami.uid, whoami.gid));
+        }
+        if let Some(username) = whoami.username.as_ref() {
+            args.push("--env".to_string());
+            args.push(format!("USER={}", username));
+        }
+        args
+    }
+
+    /// Give the container the host's SSH agent, and the user's git config and known hosts, so
+    /// that `cargo` can fetch private git dependencies.
+    fn mount_ssh_agent(&self, docker: &mut Command) -> Result<(), Error> {
+        if let Some(socket) = std::env::var_os("SSH_AUTH_SOCK") {
+            let socket = PathBuf::from(socket);
+            self.bind(docker, &socket, Path::new("/ssh-agent.sock"), false, false)?;
+            docker.args(["--env", "SSH_AUTH_SOCK=/ssh-agent.sock"]);
+        }
+
+        // `git` and `cargo` look for these in $HOME, so give the container a home of its own.
+        // Unlike the other volumes, these aren't relabeled for SELinux, which would stop the host
+        // from using them.
+        let home = match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home),
+            None => return Ok(()),
+        };
+        docker.args(["--env", &format!("HOME={}", CONTAINER_HOME)]);
+        for file in [".gitconfig", ".ssh/known_hosts"] {
+            let path = home.join(file);
+            if path.is_file() {
+                let target = Path::new(CONTAINER_HOME).join(file);
+                self.bind(docker, &path, &target, true, false)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compile with `sccache`, configured like it would be on the host, with the host's local cache
+    /// mounted at /sccache.
+    fn configure_sccache(&self, docker: &mut Command) -> Result<(), Error> {
+        docker.args(["--env", "RUSTC_WRAPPER=sccache"]);
+        // Pass variables by name, so that credentials don't appear in the command line
+        for (name, _) in std::env::vars_os() {
+            if let Some(name) = name.to_str() {
+                if (name.starts_with("SCCACHE_") || name.starts_with("AWS_"))
+                    && name != "SCCACHE_DIR"
+                {
+                    docker.args(["--env", name]);
+                }
+            }
+        }
+        if let Some(cache_dir) = sccache_dir() {
+            self.bind(docker, &cache_dir, Path::new("/sccache"), false, true)?;
+            docker.args(["--env", "SCCACHE_DIR=/sccache"]);
+        }
+        Ok(())
+    }
+
+    /// Bind-mount `source` on the host at `target` in the container, read-only if `read_only`, and
+    /// relabeled for SELinux if `relabel`.
+    ///
+    /// `--volume` separates its fields with colons, so paths containing one are mounted with
+    /// `--mount`, a CSV record in which any path can be quoted. Otherwise `--volume` is used, since
+    /// Docker can only relabel mounts given by it.
+    ///
+    /// Paths which aren't UTF-8 are refused, since the engine would mangle them in parsing either
+    /// option rather than mount what was named.
+    fn bind(
+        &self,
+        docker: &mut Command,
+        source: &Path,
+        target: &Path,
+        read_only: bool,
+        relabel: bool,
+    ) -> Result<(), Error> {
+        let (source, target) = (mountable_path(source)?, mountable_path(target)?);
+
+        if !source.contains(':') && !target.contains(':') {
+            let mut volume = format!("{}:{}", source, target);
+            let options: Vec<&str> = [read_only.then_some("ro"), relabel.then_some("Z")]
+                .iter()
+                .flatten()
+                .copied()
+                .collect();
+            if !options.is_empty() {
+                volume += ":";
+                volume += &options.join(",");
+            }
+            docker.arg("--volume").arg(volume);
+            return Ok(());
+        }
+
+        let mut mount = format!(
+            "type=bind,{},{}",
+            csv_field("src", source),
+            csv_field("dst", target)
+        );
+        if read_only {
+            mount += ",readonly";
+        }
+        if relabel && self.container_engine == ContainerEngine::Podman {
+            mount += ",relabel=private";
+        }
+        docker.arg("--mount").arg(mount);
+        Ok(())
+    }
+
+    /// The platform the image must be run as, if not that of the container engine's host.
+    ///
+    /// Published images are only built for `linux/amd64`, which e.g. Docker Desktop on Apple
+    /// Silicon runs emulated when asked, but otherwise warns that the image's platform doesn't
+    /// match. This is `None` until `ensure_engine()` has found the engine's host's platform.
+    fn platform(&self) -> Option<&'static str> {
+        let host = self.platforms.host.lock().unwrap().clone()?;
+        if host.ends_with("/amd64") {
+            return None;
+        }
+        let mut images = self.platforms.images.lock().unwrap();
+        if let Some(platform) = images.get(&self.docker_image) {
+            return *platform;
+        }
+
+        // An image which isn't available locally yet can only be judged by where it's from
+        let image_platform = self.image_platform().or_else(|| {
+            self.docker_image
+                .starts_with(PUBLISHED_IMAGE)
+                .then(|| "linux/amd64".to_string())
+        })?;
+        let platform = (image_platform == "linux/amd64").then_some("linux/amd64");
+        if platform.is_some() {
+            status!(
+                "cargo-acap: note: Docker image {} is only built for linux/amd64, so this {} host \
+                 runs it emulated, which makes builds much slower",
+                self.docker_image,
+                host
+            );
+        }
+        images.insert(self.docker_image.clone(), platform);
+        platform
+    }
+
+    /// The `os/arch` of the image, if it's available locally.
+    fn image_platform(&self) -> Option<String> {
+        let output = self
+            .engine_command()
+            .args([
+                "image",
+                "inspect",
+                "--format",
+                "{{.Os}}/{{.Architecture}}",
+                &self.docker_image,
+            ])
+            .stderr(Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Construct a command which runs `program` directly on the host, configured like the
+    /// container that `docker_run_command()` would have started.
+    pub fn host_command(&self, program: &str) -> Command {
+        let mut command = Command::new(program);
+        command.current_dir(&self.package_root);
+        command.env("CARGO_TARGET_DIR", &self.acap_target);
+        if self.sccache {
+            command.env("RUSTC_WRAPPER", "sccache");
+        }
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+        command
+    }
+
+    /// Make sure `sccache` can run in this environment if it's to be used, creating the host's
+    /// local cache so that containers can mount it.
+    pub fn ensure_sccache(&self) -> Result<(), Error> {
+        if !self.sccache {
+            return Ok(());
+        }
+        if !self.no_docker {
+            if let Some(cache_dir) = sccache_dir() {
+                std::fs::create_dir_all(&cache_dir).with_path(&cache_dir)?;
+            }
+        }
+
+        let environment = self.clone().non_interactive();
+        let mut sccache = environment.command("sccache")?;
+        sccache.arg("--version").stderr(Stdio::null());
+        if environment.output(sccache, None).is_ok() {
+            return Ok(());
+        }
+
+        if self.no_docker {
+            return Err(Error::Usage(
+                "--sccache was given, but sccache is not installed: install it with \
+                 `cargo install sccache`"
+                    .to_string(),
+            ));
+        }
+        Err(Error::Image(format!(
+            "--sccache was given, but the Docker image {} does not include sccache: images \
+             built from the current cargo-acap-build do, so pull it again with `--pull always`, \
+             or select one with `--docker-image`",
+            self.docker_image
+        )))
+    }
+
+    /// Make sure the container engine can be reached, learning the platform of its host, once for
+    /// the whole invocation.
+    ///
+    /// An unreachable daemon would otherwise fail each command cryptically, e.g. when the user may
+    /// not connect to its socket.
+    pub fn ensure_engine(&self) -> Result<(), Error> {
+        let mut host = self.platforms.host.lock().unwrap();
+        if host.is_some() {
+            return Ok(());
+        }
+
+        let program = self.container_engine.program();
+        let format = match self.container_engine {
+            // `podman` runs containers itself, without a server
+            ContainerEngine::Podman => "{{.Client.OsArch}}",
+            ContainerEngine::Auto | ContainerEngine::Docker => "{{.Server.Os}}/{{.Server.Arch}}",
+        };
+        let mut command = self.engine_command();
+        command
+            .args(["version", "--format", format])
+            .stdin(Stdio::null());
+        if self.verbose > 1 {
+            eprintln!("+ {:?}", &command);
+        }
+        let output = command.output().map_err(|source| Error::Spawn {
+            program: program.to_string(),
+            source,
+        })?;
+        if output.status.success() {
+            *host = Some(String::from_utf8_lossy(&output.stdout).trim().to_string());
+            return Ok(());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Err(Error::Image(
+            if stderr.to_ascii_lowercase().contains("permission denied") {
+                format!(
+                    "permission denied connecting to the {} daemon: add your user to the \
+                     `docker` group, e.g. with `sudo usermod -aG docker $USER` and logging in \
+                     again, or build with --no-docker",
+                    program
+                )
+            } else {
+                format!(
+                    "the {} daemon can't be reached: start it, or build with --no-docker\n{}",
+                    program, stderr
+                )
+            },
+        ))
+    }
+
+    /// Make sure the `docker` image is available locally, pulling it as `pull` says.
+    ///
+    /// This catches a missing image up front, since `docker run` would otherwise try to pull it
+    /// itself and fail cryptically if, say, the host's `rustc` is newer than any published image.
+    pub fn ensure_image(&self, pull: Pull) -> Result<(), Error> {
+        if self.no_docker {
+            return Ok(());
+        }
+        self.ensure_engine()?;
+
+        match pull {
+            Pull::Missing | Pull::Never if self.image_exists()? => return Ok(()),
+            Pull::Never => {
+                return Err(self.image_unavailable(format!(
+                    "Docker image `{}` is not available locally, and --pull=never was given",
+                    self.docker_image
+                )))
+            }
+            Pull::Always | Pull::Missing => {}
+        }
+
+        status!("cargo-acap: pulling Docker image {}", self.docker_image);
+        let mut docker = self.engine_command();
+        docker.arg("pull");
+        if output::quiet() {
+            docker.arg("--quiet");
+        }
+        if let Some(platform) = self.platform() {
+            docker.args(["--platform", platform]);
+        }
+        docker.arg(&self.docker_image);
+        match self.run(docker, None) {
+            Ok(()) => Ok(()),
+            Err(Error::Command(_)) => Err(self.image_unavailable(format!(
+                "error pulling Docker image `{}`: the host's `rustc` may be newer than any published image",
+                self.docker_image
+            ))),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// What identifies the toolchain which builds in this environment: the Docker image's ID, or
+    /// with `--no-docker`, the host's `rustc -vV`.
+    pub fn toolchain_id(&self) -> Result<String, Error> {
+        let command = if self.no_docker {
+            let mut rustc = self.host_command("rustc");
+            rustc.arg("-vV");
+            rustc
+        } else {
+            let mut docker = self.engine_command();
+            docker.args([
+                "image",
+                "inspect",
+                "--format",
+                "{{.Id}}",
+                &self.docker_image,
+            ]);
+            docker
+        };
+        Ok(self.output(command, None)?.trim().to_string())
+    }
+
+    /// The `rustc --version` of the `docker` image, which needn't be the host's.
+    pub fn image_rustc_version(&self) -> Result<String, Error> {
+        let environment = self.clone().non_interactive();
+        let mut rustc = environment.command("rustc")?;
+        rustc.arg("--version");
+        Ok(environment.output(rustc, None)?.trim().to_string())
+    }
+
+    /// Whether the `docker` image is available locally.
+    fn image_exists(&self) -> Result<bool, Error> {
+        let mut docker = self.engine_command();
+        docker
+            .args(["image", "inspect", &self.docker_image])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        if self.verbose > 1 {
+            eprintln!("+ {:?}", &docker);
+        }
+        docker
+            .status()
+            .map(|status| status.success())
+            .map_err(|source| Error::Spawn {
+                program: self.container_engine.program().to_string(),
+                source,
+            })
+    }
+
+    /// Explain that the `docker` image is unavailable, suggesting the tags which are available
+    /// locally instead.
+    fn image_unavailable(&self, mut message: String) -> Error {
+        let repository = image_repository(&self.docker_image);
+        let tags = self
+            .engine_command()
+            .args(["image", "ls", repository, "--format", "{{.Tag}}"])
+            .stderr(Stdio::null())
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+            .unwrap_or_default();
+        let tags: Vec<&str> = tags
+            .lines()
+            .filter(|tag| !tag.is_empty() && *tag != "<none>")
+            .collect();
+
+        if tags.is_empty() {
+            message += &format!(
+                "\nuse --docker-image to select an image with an explicit tag, e.g. --docker-image {}:<tag>",
+                repository
+            );
+        } else {
+            message += "\nthese tags are available locally:";
+            for tag in &tags {
+                message += &format!("\n  * {}", tag);
+            }
+            message += &format!(
+                "\nuse --docker-image to select one, e.g. --docker-image {}:{}",
+                repository, tags[0]
+            );
+        }
+        Error::Image(message)
+    }
+
+    /// Download the package's dependencies into the host's CARGO_HOME, since builds inside
+    /// `docker` can only read it.
+    ///
+    /// `cargo_args`, e.g. `--locked`, are passed to `cargo fetch`.
+    pub fn fetch_dependencies(&self, cargo_args: &[&str]) -> Result<(), Error> {
+        if self.no_docker {
+            // Host builds can fetch for themselves
+            return Ok(());
+        }
+
+        for dir in ["registry", "git"] {
+            let path = self.cargo_home.join(dir);
+            std::fs::create_dir_all(&path).with_path(&path)?;
+        }
+
+        let mut cargo = self.host_command("cargo");
+        cargo.arg("fetch");
+        if output::quiet() {
+            cargo.arg("--quiet");
+        }
+        cargo.args(cargo_args);
+        self.run(cargo, None)
+    }
+
+    /// Run `command`, returning an error if it can't be started or fails.
+    ///
+    /// If `output_prefix` is given, each line the command writes to stdout or stderr is prefixed
+    /// with it, so that the output of concurrent commands remains readable.
+    pub fn run(&self, mut command: Command, output_prefix: Option<&str>) -> Result<(), Error> {
+        if self.verbose > 1 {
+            eprintln!("+ {:?}", &command);
+        }
+
+        let program = command.get_program().to_string_lossy().into_owned();
+        let spawn_failed = |source| Error::Spawn {
+            program: program.clone(),
+            source,
+        };
+        let exit_status = if output_prefix.is_none() {
+            if self.stdout_to_stderr {
+                command.stdout(std::io::stderr());
+            }
+            let mut child = command.spawn().map_err(spawn_failed)?;
+            let _registration = interrupt::register(&child, container(&command));
+            child.wait().map_err(spawn_failed)?
+        } else {
+            let mut child = command
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(spawn_failed)?;
+            let _registration = interrupt::register(&child, container(&command));
+            let stdout = child.stdout.take().unwrap();
+            let stderr = child.stderr.take().unwrap();
+            let stdout_to: Box<dyn Write + Send> = if self.stdout_to_stderr {
+                Box::new(std::io::stderr())
+            } else {
+                Box::new(std::io::stdout())
+            };
+
+            std::thread::scope(|scope| {
+                scope.spawn(|| copy_lines(stdout, stdout_to, output_prefix));
+                scope.spawn(|| copy_lines(stderr, std::io::stderr(), output_prefix));
+            });
+
+            child.wait().map_err(spawn_failed)?
+        };
+
+        self.check(&command, exit_status)
+    }
+
+    /// Run `command` like `run()`, but capture and return what it writes to stdout.
+    pub fn output(&self, command: Command, output_prefix: Option<&str>) -> Result<String, Error> {
+        self.output_lines(command, output_prefix, |_| {})
+    }
+
+    /// Run `command` like `output()`, also passing each line it writes to stdout to `on_line` as
+    /// soon as it's written.
+    pub fn output_lines(
+        &self,
+        mut command: Command,
+        output_prefix: Option<&str>,
+        mut on_line: impl FnMut(&str),
+    ) -> Result<String, Error> {
+        if self.verbose > 1 {
+            eprintln!("+ {:?}", &command);
+        }
+
+        let program = command.get_program().to_string_lossy().into_owned();
+        let spawn_failed = |source| Error::Spawn {
+            program: program.clone(),
+            source,
+        };
+        command.stdout(Stdio::piped());
+        if output_prefix.is_some() {
+            command.stderr(Stdio::piped());
+        }
+        let mut child = command.spawn().map_err(spawn_failed)?;
+        let _registration = interrupt::register(&child, container(&command));
+        let mut stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take();
+
+        let mut output = String::new();
+        std::thread::scope(|scope| {
+            if let Some(stderr) = stderr {
+                scope.spawn(|| copy_lines(stderr, std::io::stderr(), output_prefix));
+            }
+            for line in std::io::BufReader::new(&mut stdout).split(b'\n') {
+                let line = String::from_utf8_lossy(&line?).into_owned();
+                on_line(&line);
+                output += &line;
+                output.push('\n');
+            }
+            Ok(())
+        })
+        .map_err(spawn_failed)?;
+        let exit_status = child.wait().map_err(spawn_failed)?;
+
+        self.check(&command, exit_status)?;
+        Ok(output)
+    }
+
+    /// Fail unless `command` exited successfully.
+    fn check(&self, command: &Command, exit_status: ExitStatus) -> Result<(), Error> {
+        if exit_status.success() {
+            Ok(())
+        } else {
+            Err(Error::Command(CommandFailed {
+                package_name: self.package_name.clone(),
+                command: format!("{:?}", command),
+                code: exit_status.code(),
+            }))
+        }
+    }
+
+    /// The path at which commands in this environment see the host's `path`, which must be
+    /// absolute.
+    ///
+    /// Containers see the workspace at the same path as the host, and `target/acap` at `/target`,
+    /// but nothing else on the host.
+    pub fn container_path(&self, path: &Path) -> Result<PathBuf, Error> {
+        if self.no_docker {
+            return Ok(path.to_owned());
+        }
+        if let Ok(relative) = path.strip_prefix(&self.acap_target) {
+            return Ok(Path::new("/target").join(relative));
+        }
+        if path.starts_with(&self.workspace_root) {
+            return Ok(path.to_owned());
+        }
+        Err(Error::Usage(format!(
+            "{} is outside the workspace at {}, which is the only directory containers can see: \
+             move it within the workspace, or use --no-docker",
+            path.display(),
+            self.workspace_root.display()
+        )))
+    }
+
+    /// The host path of `path`, as named by a command in this environment.
+    pub fn host_path(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix("/target") {
+            Ok(relative) if !self.no_docker => self.acap_target.join(relative),
+            // Everything else is mounted where it is on the host
+            _ => path.to_owned(),
+        }
+    }
+}
+
+/// The platforms of the container engine's host and of the images it runs, learned once for the
+/// whole invocation, since that means asking the engine.
+#[derive(Debug, Default)]
+pub struct Platforms {
+    /// The `os/arch` of the engine's host, e.g. `linux/arm64`, once it's been reached
+    host: Mutex<Option<String>>,
+    /// The platform to run each image as, if not the host's, for each image judged so far
+    images: Mutex<HashMap<String, Option<&'static str>>>,
+}
+
+/// Containers which run in the background for the whole invocation, so that each build command
+/// runs in one with `docker exec` instead of paying to start a container of its own.
+///
+/// There's one for each distinct set of `docker run` options, e.g. for each image. They're removed
+/// when this is dropped, and stopped on Ctrl-C.
+#[derive(Debug, Default)]
+pub struct ReusedContainers {
+    /// The container started with each set of options, or `None` if starting it failed
+    started: Mutex<Vec<(Vec<OsString>, Option<Background>)>>,
+}
+
+/// A container running in the background, which Ctrl-C stops while this is alive
+type Background = (Container, interrupt::Registration);
+
+impl ReusedContainers {
+    /// The name of the container started with `options`, starting it if need be.
+    ///
+    /// Returns `None`, after warning the first time, if the container can't be started.
+    fn get(&self, environment: &BuildEnvironment, options: Vec<OsString>) -> Option<String> {
+        let mut started = self.started.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((_, container)) = started.iter().find(|(o, _)| *o == options) {
+            return container
+                .as_ref()
+                .map(|(container, _)| container.name.clone());
+        }
+
+        let container = Container {
+            program: environment.container_engine.program().to_string(),
+            name: interrupt::container_name(),
+        };
+        let mut docker = environment.engine_command();
+        docker.args([
+            "run",
+            "--detach",
+            "--rm",
+            "--init",
+            "--name",
+            &container.name,
+        ]);
+        docker.args(&options);
+        docker.args(["sleep", "infinity"]);
+        docker.stdout(Stdio::null());
+        if environment.verbose > 1 {
+            eprintln!("+ {:?}", &docker);
+        }
+        match docker.status() {
+            Ok(status) if status.success() => {}
+            result => {
+                let reason = match result {
+                    Ok(status) => status.to_string(),
+                    Err(e) => e.to_string(),
+                };
+                eprintln!(
+                    "cargo-acap: warning: not reusing a container, since starting one failed: {}",
+                    reason
+                );
+                started.push((options, None));
+                return None;
+            }
+        }
+
+        let registration = interrupt::register_container(container.clone());
+        let name = container.name.clone();
+        started.push((options, Some((container, registration))));
+        Some(name)
+    }
+}
+
+impl Drop for ReusedContainers {
+    fn drop(&mut self) {
+        let started = self.started.get_mut().unwrap_or_else(|e| e.into_inner());
+        for (container, _registration) in started.drain(..).filter_map(|(_, c)| c) {
+            let _ = Command::new(&container.program)
+                .args(["rm", "--force", &container.name])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+        }
+    }
+}
+
+/// The host's local `sccache` cache: SCCACHE_DIR, or else `sccache`'s default.
+fn sccache_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("SCCACHE_DIR").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    let home = || std::env::var_os("HOME").map(PathBuf::from);
+    if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(|dir| Path::new(&dir).join("Mozilla").join("sccache"))
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library/Caches/Mozilla.sccache"))
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home().map(|home| home.join(".cache")))
+            .map(|dir| dir.join("sccache"))
+    }
+}
+
+/// `image`, tagged with `tag` unless it names a tag or digest already.
+pub fn with_default_tag(image: &str, tag: &str) -> String {
+    if image.contains('@') || image_repository(image) != image {
+        image.to_string()
+    } else {
+        format!("{}:{}", image, tag)
+    }
+}
+
+/// The repository of `image`, i.e. `image` without its tag.
+pub fn image_repository(image: &str) -> &str {
+    match image.rsplit_once(':') {
+        // A `:` before the last `/` separates a registry's port, not a tag
+        Some((repository, tag)) if !tag.contains('/') => repository,
+        _ => image,
+    }
+}
+
+/// `path` as a string to mount, or an error if it isn't UTF-8.
+fn mountable_path(path: &Path) -> Result<&str, Error> {
+    path.to_str().ok_or_else(|| {
+        Error::Usage(format!(
+            "{} can't be mounted in a container, since its path isn't UTF-8: rename it, or use \
+             --no-docker",
+            path.display()
+        ))
+    })
+}
+
+/// `key=value` as a CSV field, quoted if `value` contains a comma, quote, or line break.
+fn csv_field(key: &str, value: &str) -> String {
+    let field = format!("{}={}", key, value.replace('"', "\"\""));
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field)
+    } else {
+        field
+    }
+}
+
+/// The container which `command` runs, if it's a `docker run` which named one.
+fn container(command: &Command) -> Option<Container> {
+    let program = command.get_program().to_str()?;
+    if program != "docker" && program != "podman" {
+        return None;
+    }
+    let args: Vec<_> = command.get_args().collect();
+    args.windows(2)
+        .find(|pair| pair[0] == "--name")
+        .map(|pair| Container {
+            program: program.to_string(),
+            name: pair[1].to_string_lossy().into_owned(),
+        })
+}
+
+/// The `cargo` configuration files in `.cargo/` directories above `workspace_root`, except in
+/// `cargo_home`, whose configuration is mounted in the container's CARGO_HOME instead.
+fn ancestor_cargo_configs(workspace_root: &Path, cargo_home: &Path) -> Vec<PathBuf> {
+    workspace_root
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join(".cargo"))
+        .filter(|dir| dir != cargo_home)
+        .flat_map(|dir| ["config", "config.toml"].map(|file| dir.join(file)))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Copy lines from `from` to `to`, prefixing each one with `[prefix]` if given.
+pub(super) fn copy_lines<R: std::io::Read, W: Write>(from: R, mut to: W, prefix: Option<&str>) {
+    for line in std::io::BufReader::new(from).split(b'\n') {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let mut output = match prefix {
+            Some(prefix) => format!("[{}] ", prefix).into_bytes(),
+            None => Vec::new(),
+        };
+        output.extend_from_slice(&line);
+        output.push(b'\n');
+        // Write the whole line at once so concurrent writers don't interleave within it
+        let _ = to.write_all(&output);
+    }
+}
+
+/// A command which ran unsuccessfully.
+#[derive(Debug, Clone)]
+pub struct CommandFailed {
+    package_name: String,
+    command: String,
+    code: Option<i32>,
+}
+
+impl std::error::Error for CommandFailed {}
+
+impl std::fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "building package `{}`: `{}` ",
+            &self.package_name, &self.command
+        )?;
+        match self.code {
+            Some(code) => write!(f, "returned exit code {}", code),
+            None => write!(f, "was terminated by a signal"),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// An environment for building the package at the root of `workspace_root` into
+    /// `acap_target` with `container_engine`, which is only ever described, not run.
+    pub(in crate::cli) fn environment(
+        workspace_root: &str,
+        acap_target: &str,
+        container_engine: ContainerEngine,
+    ) -> BuildEnvironment {
+        BuildEnvironment {
+            verbose: 0,
+            container_engine,
+            docker_image: "trunnion/cargo-acap:test".to_string(),
+            docker_args: vec![],
+            container_user: None,
+            env: vec![],
+            ssh_agent: false,
+            sccache: false,
+            no_docker: false,
+            interactive: false,
+            stdout_to_stderr: false,
+            cargo_home: PathBuf::from("/home/user/.cargo"),
+            workspace_root: PathBuf::from(workspace_root),
+            package_root: PathBuf::from(workspace_root),
+            package_name: "app".to_string(),
+            selected_package: None,
+            acap_target: PathBuf::from(acap_target),
+            reused_containers: None,
+            platforms: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn container_path_maps_an_out_of_tree_target_dir() {
+        let environment = environment(
+            "/work/app",
+            "/fast-ssd/targets/acap",
+            ContainerEngine::Docker,
+        );
+        assert_eq!(
+            environment
+                .container_path(Path::new(
+                    "/fast-ssd/targets/acap/mipsel-axis-linux-gnu/app"
+                ))
+                .unwrap(),
+            Path::new("/target/mipsel-axis-linux-gnu/app")
+        );
+        assert_eq!(
+            environment
+                .container_path(Path::new("/work/app/Cargo.toml"))
+                .unwrap(),
+            Path::new("/work/app/Cargo.toml")
+        );
+        assert!(matches!(
+            environment.container_path(Path::new("/fast-ssd/elsewhere")),
+            Err(Error::Usage(_))
+        ));
+    }
+
+    /// The arguments with which `environment` mounts `source` at `target`.
+    fn bind_args(
+        environment: &BuildEnvironment,
+        source: &Path,
+        target: &Path,
+        read_only: bool,
+        relabel: bool,
+    ) -> Result<Vec<String>, Error> {
+        let mut docker = Command::new("docker");
+        environment.bind(&mut docker, source, target, read_only, relabel)?;
+        Ok(docker
+            .get_args()
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .collect())
+    }
+
+    #[test]
+    fn bind_uses_volume_unless_a_path_has_a_colon() {
+        let environment = environment(
+            "/work/app",
+            "/work/app/target/acap",
+            ContainerEngine::Docker,
+        );
+        for (source, read_only, relabel, volume) in [
+            ("/work/my app", false, true, "/work/my app:/mnt:Z"),
+            ("/work/a,b", true, true, "/work/a,b:/mnt:ro,Z"),
+            ("/work/\"quoted\"", true, false, "/work/\"quoted\":/mnt:ro"),
+            ("/work/plain", false, false, "/work/plain:/mnt"),
+        ] {
+            assert_eq!(
+                bind_args(
+                    &environment,
+                    Path::new(source),
+                    Path::new("/mnt"),
+                    read_only,
+                    relabel
+                )
+                .unwrap(),
+                ["--volume", volume],
+            );
+        }
+    }
+
+    #[test]
+    fn bind_quotes_mount_fields_when_a_path_has_a_colon() {
+        for (engine, source, mount) in [
+            (
+                ContainerEngine::Docker,
+                "/work/a:b",
+                "type=bind,src=/work/a:b,dst=/mnt,readonly",
+            ),
+            (
+                ContainerEngine::Docker,
+                "/work/a:b, c",
+                "type=bind,\"src=/work/a:b, c\",dst=/mnt,readonly",
+            ),
+            (
+                ContainerEngine::Docker,
+                "/work/a:\"b\"",
+                "type=bind,\"src=/work/a:\"\"b\"\"\",dst=/mnt,readonly",
+            ),
+            (
+                ContainerEngine::Podman,
+                "/work/a:b c",
+                "type=bind,src=/work/a:b c,dst=/mnt,readonly,relabel=private",
+            ),
+        ] {
+            let environment = environment("/work", "/work/target/acap", engine);
+            assert_eq!(
+                bind_args(
+                    &environment,
+                    Path::new(source),
+                    Path::new("/mnt"),
+                    true,
+                    true
+                )
+                .unwrap(),
+                ["--mount", mount],
+                "{:?}",
+                engine
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn bind_refuses_paths_which_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let environment = environment("/work", "/work/target/acap", ContainerEngine::Docker);
+        let path = Path::new(OsStr::from_bytes(b"/work/caf\xe9"));
+        for (source, target) in [(path, Path::new("/mnt")), (Path::new("/work"), path)] {
+            match bind_args(&environment, source, target, false, true) {
+                Err(Error::Usage(message)) => assert!(message.contains("UTF-8"), "{}", message),
+                other => panic!("expected a usage error, got {:?}", other),
+            }
+        }
+
+        let mut environment = environment;
+        environment.workspace_root = path.to_owned();
+        assert!(matches!(environment.command("cargo"), Err(Error::Usage(_))));
+    }
+
+    #[test]
+    fn ancestor_cargo_configs_are_mounted_read_only_where_they_are() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let workspace_root = root.join("outer/workspace");
+        for config in [
+            ".cargo/config.toml",
+            "outer/.cargo/config",
+            "outer/.cargo/config.toml",
+            "outer/workspace/.cargo/config.toml",
+            "outer/workspace/crates/app/.cargo/config.toml",
+        ] {
+            let path = root.join(config);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        // The root's is CARGO_HOME's, and those within the workspace are mounted with it
+        let cargo_home = root.join(".cargo");
+        assert_eq!(
+            ancestor_cargo_configs(&workspace_root, &cargo_home),
+            [
+                root.join("outer/.cargo/config"),
+                root.join("outer/.cargo/config.toml"),
+            ]
+        );
+        // Nearest first, as `cargo` reads them
+        assert_eq!(
+            ancestor_cargo_configs(&workspace_root, Path::new("/nonexistent")),
+            [
+                root.join("outer/.cargo/config"),
+                root.join("outer/.cargo/config.toml"),
+                root.join(".cargo/config.toml"),
+            ]
+        );
+
+        let mut environment = environment(
+            workspace_root.to_str().unwrap(),
+            workspace_root.join("target/acap").to_str().unwrap(),
+            ContainerEngine::Docker,
+        );
+        environment.package_root = workspace_root.join("crates/app");
+        environment.cargo_home = cargo_home;
+        let mut docker = Command::new("docker");
+        environment.container_args(&mut docker).unwrap();
+        let args: Vec<String> = docker
+            .get_args()
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .collect();
+        let volumes: Vec<&str> = args
+            .windows(2)
+            .filter(|pair| pair[0] == "--volume")
+            .map(|pair| pair[1].as_str())
+            .collect();
+        for config in ["outer/.cargo/config", "outer/.cargo/config.toml"] {
+            let path = root.join(config).display().to_string();
+            let volume = format!("{}:{}:ro", path, path);
+            assert!(volumes.contains(&volume.as_str()), "{:?}", volumes);
+        }
+        let cargo_home_config = root.join(".cargo/config.toml").display().to_string();
+        assert!(
+            volumes.contains(&format!("{}:/.cargo/config.toml:ro,Z", cargo_home_config).as_str())
+        );
+        assert!(!volumes
+            .iter()
+            .any(|volume| volume
+                .starts_with(&format!("{}:{}", cargo_home_config, cargo_home_config))));
+        assert!(!volumes
+            .iter()
+            .any(|volume| volume.contains("workspace/.cargo")));
+        let workdir = args.iter().position(|arg| arg == "--workdir").unwrap();
+        assert_eq!(
+            Path::new(&args[workdir + 1]),
+            workspace_root.join("crates/app")
+        );
+    }
+
+    #[test]
+    fn cargo_in_the_container_resolves_env_from_the_projects_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace_root = dir.path().canonicalize().unwrap().join("workspace");
+        crate::testing::write(
+            &workspace_root,
+            ".cargo/config.toml",
+            "[env]\nACAP_CONFIG_ENV = \"from-config\"\n",
+        );
+        std::fs::create_dir_all(workspace_root.join("crates/app")).unwrap();
+
+        let mut environment = environment(
+            workspace_root.to_str().unwrap(),
+            workspace_root.join("target/acap").to_str().unwrap(),
+            ContainerEngine::Docker,
+        );
+        environment.package_root = workspace_root.join("crates/app");
+        let mut docker = Command::new("docker");
+        environment.container_args(&mut docker).unwrap();
+        let args: Vec<String> = docker
+            .get_args()
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .collect();
+
+        // The config is mounted where it is on the host
+        let config = workspace_root.join(".cargo/config.toml");
+        assert!(args
+            .windows(2)
+            .filter(|pair| pair[0] == "--volume")
+            .filter_map(|pair| pair[1].split(':').next())
+            .any(|source| config.starts_with(source)
+                && args.contains(&format!("{}:{}:Z", source, source))));
+
+        // ...so `cargo`, run in the container's working directory, sets its `[env]` for build
+        // scripts
+        let workdir = args.iter().position(|arg| arg == "--workdir").unwrap();
+        let mut cargo_config = cargo::Config::default().unwrap();
+        cargo_config.reload_rooted_at(&args[workdir + 1]).unwrap();
+        let env = cargo_config.env_config().unwrap();
+        assert_eq!(
+            env["ACAP_CONFIG_ENV"].resolve(&cargo_config),
+            OsStr::new("from-config")
+        );
+    }
+
+    #[test]
+    fn podman_keeps_the_invoking_users_id_unless_it_is_root() {
+        let user = |uid, username: Option<&str>| Whoami {
+            uid,
+            gid: uid,
+            username: username.map(String::from),
+        };
+        for (engine, whoami, expected) in [
+            (
+                ContainerEngine::Docker,
+                user(1000, Some("alice")),
+                &["--user", "1000:1000", "--env", "USER=alice"][..],
+            ),
+            (
+                ContainerEngine::Podman,
+                user(1000, Some("alice")),
+                &["--userns=keep-id", "--env", "USER=alice"][..],
+            ),
+            (
+                ContainerEngine::Podman,
+                user(0, Some("root")),
+                &["--user", "0:0", "--env", "USER=root"][..],
+            ),
+            (
+                ContainerEngine::Docker,
+                user(1000, None),
+                &["--user", "1000:1000"][..],
+            ),
+            (
+                ContainerEngine::Podman,
+                user(1000, None),
+                &["--userns=keep-id"][..],
+            ),
+        ] {
+            let environment = environment("/work", "/work/target/acap", engine);
+            assert_eq!(
+                environment.invoking_user_args(&whoami),
+                expected,
+                "{:?} {}",
+                engine,
+                whoami.uid
+            );
+        }
+    }
+
+    #[test]
+    fn container_args_relabel_the_workspace_and_target_for_either_engine() {
+        for engine in [ContainerEngine::Docker, ContainerEngine::Podman] {
+            let mut environment = environment("/work/app", "/fast-ssd/acap", engine);
+            environment.container_user = Some(ContainerUser::Id { uid: 42, gid: 43 });
+            let mut docker = Command::new("docker");
+            environment.container_args(&mut docker).unwrap();
+            let args: Vec<String> = docker
+                .get_args()
+                .map(|arg| arg.to_str().unwrap().to_string())
+                .collect();
+
+            assert!(args.windows(2).any(|pair| pair == ["--user", "42:43"]));
+            assert!(!args.iter().any(|arg| arg.starts_with("--userns")));
+            for volume in [
+                "/work/app:/work/app:Z",
+                "/fast-ssd/acap:/target:Z",
+                "/home/user/.cargo/registry:/.cargo/registry:ro,Z",
+                "/home/user/.cargo/git:/.cargo/git:ro,Z",
+            ] {
+                assert!(
+                    args.windows(2).any(|pair| pair == ["--volume", volume]),
+                    "{:?}: {} not in {:?}",
+                    engine,
+                    volume,
+                    args
+                );
+            }
+            assert_eq!(args.last().unwrap(), "trunnion/cargo-acap:test");
+        }
+    }
+
+    #[test]
+    fn rustflags_are_those_cargo_would_read_from_the_containers_environment() {
+        let mut environment = environment("/work", "/work/target/acap", ContainerEngine::Docker);
+        assert!(environment.rustflags().is_empty());
+
+        environment = environment.with_env("RUSTFLAGS", " -C  debuginfo=2 ".to_string());
+        assert_eq!(environment.rustflags(), ["-C", "debuginfo=2"]);
+
+        // As `cargo` does, the encoded form is read in preference to the other
+        environment = environment.with_env(
+            "CARGO_ENCODED_RUSTFLAGS",
+            "-C\x1fopt-level=z\x1f-Dwarnings".to_string(),
+        );
+        assert_eq!(environment.rustflags(), ["-C", "opt-level=z", "-Dwarnings"]);
+    }
+
+    #[test]
+    fn with_env_replaces_a_value_given_with_env() {
+        let mut environment = environment("/work", "/work/target/acap", ContainerEngine::Docker);
+        environment.env = vec![
+            (
+                "CARGO_ENCODED_RUSTFLAGS".to_string(),
+                "--cfg\x1fold".to_string(),
+            ),
+            (
+                "CARGO_ENCODED_RUSTFLAGS".to_string(),
+                "--cfg\x1fuser".to_string(),
+            ),
+            ("FOO".to_string(), "bar".to_string()),
+        ];
+        assert_eq!(environment.rustflags(), ["--cfg", "user"]);
+
+        // The user's rustflags are folded into those which replace them
+        let mut rustflags = environment.rustflags();
+        rustflags.push("-Dwarnings".to_string());
+        let environment = environment.with_env("CARGO_ENCODED_RUSTFLAGS", rustflags.join("\x1f"));
+        assert_eq!(environment.rustflags(), ["--cfg", "user", "-Dwarnings"]);
+
+        let mut docker = Command::new("docker");
+        environment.container_args(&mut docker).unwrap();
+        let rustflags: Vec<&str> = docker
+            .get_args()
+            .map(|arg| arg.to_str().unwrap())
+            .collect::<Vec<_>>()
+            .windows(2)
+            .filter(|pair| pair[0] == "--env" && pair[1].contains("RUSTFLAGS"))
+            .map(|pair| pair[1])
+            .collect();
+        assert_eq!(
+            rustflags,
+            ["CARGO_ENCODED_RUSTFLAGS=--cfg\x1fuser\x1f-Dwarnings"]
+        );
+    }
+}
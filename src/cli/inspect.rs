@@ -0,0 +1,139 @@
+use crate::cargo_config::CargoAcapMetadata;
+use crate::cli::build;
+use crate::cli::{package, Invocation};
+use crate::eap::{Entry, Source};
+use crate::error::Error;
+use crate::manifest_dot_json::ManifestDotJson;
+use crate::package_dot_conf::PackageDotConf;
+use crate::param_dot_conf::ParamDotConf;
+use crate::target::Target;
+use clap::Parser;
+use serde::Serialize;
+
+/// Show the package.conf and files which `cargo acap build` would package, without building
+#[derive(Parser)]
+pub struct Inspect {
+    /// The target to inspect (defaults to the first target `cargo acap build` would build)
+    #[clap(short, long)]
+    target: Option<Target>,
+
+    /// Inspect the package of the specified binary
+    #[clap(long, value_name = "NAME")]
+    bin: Option<String>,
+
+    /// The output format
+    #[clap(long, arg_enum, value_name = "FMT", default_value = "human")]
+    format: Format,
+
+    /// Fail, rather than warn, about likely mistakes in the package's configuration
+    #[clap(long)]
+    strict: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum Format {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    package: &'a str,
+    target: &'a str,
+    package_conf: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manifest_json: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    param_conf: Option<String>,
+    files: &'a [Entry],
+}
+
+impl Inspect {
+    pub fn invoke(self, invocation: Invocation) -> Result<(), Error> {
+        let package = &invocation.cargo_package;
+        let target = match self.target {
+            Some(target) => target,
+            None => CargoAcapMetadata::for_package(package)?
+                .targets
+                .and_then(|targets| targets.first().copied())
+                .unwrap_or(Target::all()[0]),
+        };
+        let binary_name = invocation.binary_name(self.bin.as_deref())?;
+
+        let package_conf = PackageDotConf::from_cargo_package(package, binary_name, None, target)?;
+        package_conf.warn_if_app_name_normalized();
+        let metadata = CargoAcapMetadata::for_package(package)?;
+        build::report_lints(&package_conf.lints(), &metadata, self.strict)?;
+        let param_conf = ParamDotConf::from_cargo_package(package)?;
+        let manifest =
+            ManifestDotJson::from_cargo_package(package, &package_conf, param_conf.as_ref())?;
+
+        // Name the executable where `cargo acap build` would put it
+        let executable = invocation
+            .acap_target()
+            .join(target.rust_target_triple())
+            .join("release")
+            .join(format!("{}.stripped", binary_name));
+
+        let contents = package::contents(
+            invocation.package_root(),
+            &package_conf,
+            param_conf.as_ref(),
+            manifest.as_ref(),
+            &executable,
+            // Built libraries can only be found by building, so they're not listed
+            &[],
+            // Build info describes a particular build, so neither is it
+            None,
+        )?;
+
+        let report = Report {
+            package: invocation.cargo_package_name(),
+            target: target.name(),
+            package_conf: contents
+                .package_conf()
+                .expect("every package has a package.conf")
+                .to_shell()?,
+            manifest_json: manifest.as_ref().map(|m| m.to_string()),
+            param_conf: param_conf.as_ref().map(|p| p.to_string()),
+            files: contents.entries(),
+        };
+
+        match self.format {
+            Format::Json => {
+                let json = serde_json::to_string(&report).expect("error serializing report");
+                println!("{}", json);
+            }
+            Format::Human => {
+                println!("package.conf for target {}:", report.target);
+                print!("{}", report.package_conf);
+                if let Some(manifest_json) = &report.manifest_json {
+                    println!();
+                    println!("manifest.json:");
+                    print!("{}", manifest_json);
+                }
+                if let Some(param_conf) = &report.param_conf {
+                    println!();
+                    println!("param.conf:");
+                    print!("{}", param_conf);
+                }
+                println!();
+                println!("files:");
+                for entry in report.files {
+                    let mode = format!("{:03o}", entry.mode());
+                    match &entry.source {
+                        Source::Path(path) => {
+                            println!("  {} {} <- {}", mode, entry.name, path.display())
+                        }
+                        Source::Generated(_) => println!("  {} {} (generated)", mode, entry.name),
+                        Source::Directory => println!("  {} {}/", mode, entry.name),
+                        Source::Symlink(target) => {
+                            println!("  {} {} -> {}", mode, entry.name, target)
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
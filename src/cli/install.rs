@@ -0,0 +1,122 @@
+use crate::cargo_config::CargoAcapMetadata;
+use crate::cli::build::{Build, BuildOptions};
+use crate::cli::device::DeviceOptions;
+use crate::cli::license;
+use crate::cli::Invocation;
+use crate::device::Device;
+use crate::error::Error;
+use crate::output::status;
+use crate::package_dot_conf::PackageDotConf;
+use crate::target::Target;
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Build an ACAP application and install it on an Axis product
+#[derive(Parser)]
+pub struct Install {
+    #[clap(flatten)]
+    device: DeviceOptions,
+
+    /// The target to install (defaults to the device's architecture)
+    #[clap(short, long)]
+    target: Option<Target>,
+
+    /// Install a previously-built `.eap` instead of building first
+    #[clap(long)]
+    no_build: bool,
+
+    #[clap(flatten)]
+    build: BuildOptions,
+}
+
+impl Install {
+    pub(crate) fn invoke(self, invocation: Invocation) -> Result<(), Error> {
+        let device = self.device(&invocation)?;
+        self.install(&invocation, &device)?;
+        Ok(())
+    }
+
+    /// The device selected by `--host` and friends.
+    pub(crate) fn device(&self, invocation: &Invocation) -> Result<Device, Error> {
+        self.device.device(invocation.global_options().verbose)
+    }
+
+    /// Build and install the invocation's current package on `device`.
+    ///
+    /// Returns the name of the installed application.
+    pub(crate) fn install(
+        &self,
+        invocation: &Invocation,
+        device: &Device,
+    ) -> Result<String, Error> {
+        let target = self.target(device)?;
+        let eap = self.eap(invocation, target)?;
+
+        status!(
+            "cargo-acap: installing {} on {}",
+            eap.display(),
+            device.base_url()
+        );
+        device.install(&eap)?;
+        status!("cargo-acap: installed {}", eap.display());
+
+        let app_name = PackageDotConf::from_cargo_package(
+            &invocation.cargo_package,
+            self.build.binary_name(invocation)?,
+            None,
+            target,
+        )?
+        .app_name;
+        let metadata = CargoAcapMetadata::for_package(&invocation.cargo_package)?;
+        if let Some(key) = metadata.license_key_file {
+            let key = invocation.package_root().join(key);
+            license::upload(device, &app_name, &key)?;
+        }
+        if let Some(web_server) = metadata.web_server {
+            status!(
+                "cargo-acap: its web server is at {}{}",
+                device.base_url(),
+                web_server.device_path(&app_name)
+            );
+        }
+        Ok(app_name)
+    }
+
+    /// The target to install: the one given by `--target`, or else the device's.
+    fn target(&self, device: &Device) -> Result<Target, Error> {
+        let detected = device.target();
+        match (self.target, detected) {
+            (Some(target), Ok(detected)) if target != detected => {
+                eprintln!(
+                    "cargo-acap: warning: installing target {} on a device which reports {}",
+                    target.name(),
+                    detected.name()
+                );
+                Ok(target)
+            }
+            (Some(target), _) => Ok(target),
+            (None, Ok(detected)) => Ok(detected),
+            (None, Err(e)) => {
+                eprintln!("cargo-acap: error detecting the device's architecture");
+                Err(e.into())
+            }
+        }
+    }
+
+    /// The `.eap` to install for `target`, building it unless `--no-build` was given.
+    fn eap(&self, invocation: &Invocation, target: Target) -> Result<PathBuf, Error> {
+        if !self.no_build {
+            let build = Build::for_targets(vec![target], self.build.clone());
+            return Ok(build.build_eaps(invocation)?.remove(0));
+        }
+
+        let eap = self.build.eap_path(invocation, target)?;
+        if !eap.is_file() {
+            return Err(Error::Usage(format!(
+                "{} does not exist: build it first, or omit --no-build",
+                eap.display()
+            )));
+        }
+        Ok(eap)
+    }
+}
@@ -0,0 +1,83 @@
+use crate::cargo_config::CargoAcapMetadata;
+use crate::cli::control;
+use crate::cli::device::DeviceOptions;
+use crate::cli::Invocation;
+use crate::device::Device;
+use crate::error::Error;
+use crate::output::status;
+use clap::Parser;
+use std::path::{Path, PathBuf};
+
+/// Upload a license key for an application installed on an Axis product
+#[derive(Parser)]
+pub struct License {
+    #[clap(flatten)]
+    device: DeviceOptions,
+
+    /// The license key file (defaults to `license_key_file` in `[package.metadata.acap]`)
+    #[clap(value_name = "KEY_FILE")]
+    key_file: Option<PathBuf>,
+
+    /// The application to license (defaults to the `app_name` of the current package)
+    #[clap(long, value_name = "NAME")]
+    app_name: Option<String>,
+
+    /// License the application of the specified binary
+    #[clap(long, value_name = "NAME", conflicts_with = "app-name")]
+    bin: Option<String>,
+}
+
+impl License {
+    pub(crate) fn invoke(self, invocation: Invocation) -> Result<(), Error> {
+        let metadata = CargoAcapMetadata::for_package(&invocation.cargo_package)?;
+        let app_name =
+            control::app_name(&invocation, self.app_name.as_deref(), self.bin.as_deref())?;
+        // Another application's metadata is unknown, so trust the device to check it
+        if self.app_name.is_none() && metadata.axis_application_id.is_none() {
+            return Err(Error::Usage(format!(
+                "{} doesn't use Axis licensing, so it can't be given a license key: set \
+                 axis_application_id in [package.metadata.acap]",
+                app_name
+            )));
+        }
+
+        let key = match (self.key_file, metadata.license_key_file) {
+            (Some(key), _) => key,
+            (None, Some(key)) => invocation.package_root().join(key),
+            (None, None) => {
+                return Err(Error::Usage(
+                    "no license key file given: name one, or set license_key_file in \
+                     [package.metadata.acap]"
+                        .to_string(),
+                ))
+            }
+        };
+
+        let device = self.device.device(invocation.global_options().verbose)?;
+        if device.application(&app_name)?.is_none() {
+            return Err(Error::Usage(format!(
+                "{} is not installed on {}: install it first",
+                app_name,
+                device.base_url()
+            )));
+        }
+        upload(&device, &app_name, &key)
+    }
+}
+
+/// Upload the license key file at `key` for `app_name`, which is installed on `device`.
+pub(crate) fn upload(device: &Device, app_name: &str, key: &Path) -> Result<(), Error> {
+    if !key.is_file() {
+        return Err(Error::Usage(format!(
+            "license key file {} does not exist",
+            key.display()
+        )));
+    }
+    device.upload_license(app_name, key)?;
+    status!(
+        "cargo-acap: uploaded license key {} for {}",
+        key.display(),
+        app_name
+    );
+    Ok(())
+}
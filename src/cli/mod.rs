@@ -1,11 +1,37 @@
-use crate::whoami::whoami;
+use crate::cargo_config::CargoAcapMetadata;
+use crate::error::{Error, IoResultExt};
+use crate::output::{self, Color, Style};
+use crate::target::Target;
+use cargo::util::toml::{TomlProfile, TomlProfiles};
 use clap::Parser;
+pub use environment::{
+    BuildEnvironment, CommandFailed, ContainerEngine, ContainerUser, Platforms, Pull,
+    ReusedContainers,
+};
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
-use std::path::PathBuf;
-use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 mod build;
+mod check;
+mod control;
+mod deploy;
+mod detect;
+mod device;
+mod devices;
+mod doctor;
+mod environment;
+mod inspect;
+mod install;
+mod license;
+mod new;
+mod package;
+mod run;
 mod targets;
+mod test;
+mod unpack;
+mod verify;
 
 #[derive(Parser)]
 #[clap(author, about)]
@@ -23,19 +49,122 @@ pub struct GlobalOptions {
     #[clap(short, long, parse(from_occurrences))]
     verbose: u8,
 
+    /// Print only warnings, errors, and requested output, not progress
+    #[clap(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// When to color output, including `cargo`'s: `auto`, `always`, or `never`
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "WHEN",
+        env = "CARGO_TERM_COLOR",
+        default_value = "auto"
+    )]
+    color: Color,
+
     /// Path to the application project's Cargo.toml
     #[clap(long, default_value = "Cargo.toml")]
     manifest_path: PathBuf,
 
+    /// The workspace member to build (defaults to the current package)
+    #[clap(short, long, value_name = "SPEC")]
+    package: Option<String>,
+
     /// `docker` image to use for cross-compiling
-    #[clap(long, default_value = "ghcr.io/trunnion/cargo-acap")]
+    #[clap(long, default_value = environment::PUBLISHED_IMAGE)]
     docker_image: String,
+
+    /// `docker` image to use for cross-compiling one target, and can be used multiple times
+    #[clap(long, value_name = "TARGET=IMAGE", parse(try_from_str = parse_target_image))]
+    docker_image_for: Vec<(Target, String)>,
+
+    /// The program which runs build containers
+    #[clap(long, arg_enum, value_name = "ENGINE", default_value = "auto")]
+    container_engine: ContainerEngine,
+
+    /// When to pull the `docker` image: `always`, only if `missing` locally, or `never`
+    #[clap(long, arg_enum, value_name = "WHEN", default_value = "missing")]
+    pull: Pull,
+
+    /// Whom to run containers as: `UID:GID`, or `none` for the image's default user (defaults to
+    /// the invoking user, except on macOS)
+    #[clap(long, value_name = "UID:GID|none")]
+    container_user: Option<ContainerUser>,
+
+    /// Run every build command in one long-lived container, rather than starting one for each
+    #[clap(long)]
+    reuse_container: bool,
+
+    /// An argument to pass to `docker run` verbatim, and can be used multiple times
+    #[clap(long = "docker-arg", value_name = "ARG", allow_hyphen_values = true)]
+    docker_args: Vec<String>,
+
+    /// An environment variable to set for the build, and can be used multiple times
+    #[clap(long, value_name = "KEY=VALUE", parse(try_from_str = parse_env_var))]
+    env: Vec<(String, String)>,
+
+    /// An environment variable to forward from the host to the build if it's set, and can be
+    /// used multiple times
+    #[clap(long, value_name = "NAME")]
+    forward_env: Vec<String>,
+
+    /// Give builds the host's SSH agent, git config, and known hosts, e.g. to fetch private git
+    /// dependencies
+    #[clap(long)]
+    ssh_agent: bool,
+
+    /// Compile with `sccache`, forwarding the host's SCCACHE_* and AWS_* variables and giving it
+    /// the host's local cache
+    #[clap(long)]
+    sccache: bool,
+}
+
+/// Parse a `TARGET=IMAGE` pair.
+fn parse_target_image(s: &str) -> Result<(Target, String), String> {
+    match s.split_once('=') {
+        Some((target, image)) if !image.is_empty() => {
+            let target = target.parse().map_err(|e| format!("{}", e))?;
+            Ok((target, image.to_string()))
+        }
+        _ => Err(format!("expected TARGET=IMAGE, found `{}`", s)),
+    }
+}
+
+/// Parse a `KEY=VALUE` pair, leaving the value as is.
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, found `{}`", s)),
+    }
 }
 
 #[derive(Parser)]
 enum Subcommand {
     Build(build::Build),
+    Check(check::Check),
+    /// Run clippy on an ACAP application for each target
+    Clippy(check::Check),
+    Deploy(deploy::Deploy),
+    Detect(detect::Detect),
+    Devices(devices::Devices),
+    Doctor(doctor::Doctor),
+    Inspect(inspect::Inspect),
+    Init(new::Init),
+    Install(install::Install),
+    License(license::License),
+    New(new::New),
+    /// Remove an application installed on an Axis product
+    Remove(control::Control),
+    Run(run::Run),
+    /// Start an application installed on an Axis product
+    Start(control::Control),
+    /// Stop an application installed on an Axis product
+    Stop(control::Control),
     Targets(targets::Targets),
+    Test(test::Test),
+    Unpack(unpack::Unpack),
+    Verify(verify::Verify),
 }
 
 #[derive(Debug)]
@@ -43,16 +172,26 @@ pub struct Invocation {
     global_options: GlobalOptions,
     cargo_home: PathBuf,
     workspace_root: PathBuf,
-    workspace_target: PathBuf,
+    manifest_path: PathBuf,
     cargo_package: cargo::core::Package,
-    acap_target: Mutex<Option<PathBuf>>,
+    workspace_members: Vec<cargo::core::Package>,
+    /// The `[profile]` tables of the workspace's root manifest
+    profiles: Option<TomlProfiles>,
+    /// The rustflags which `cargo`'s configuration gives each target, or why they can't be known
+    configured_rustflags: HashMap<Target, Result<Vec<String>, String>>,
+    rustc: cargo::util::Rustc,
+    /// The Docker images whose `rustc` has been checked against the host's so far
+    rustc_checked_images: Mutex<HashSet<String>>,
+    acap_target: PathBuf,
+    reused_containers: Option<Arc<ReusedContainers>>,
+    platforms: Arc<Platforms>,
 }
 
 /// Process arguments, where `cargo acap …` is treated as `cargo-acap …`
 fn cargo_acap_args() -> impl Iterator<Item = OsString> {
     let mut args: Vec<OsString> = std::env::args_os().collect();
 
-    match (args.get(0), args.get(1)) {
+    match (args.first(), args.get(1)) {
         (Some(cargo), Some(acap))
             if cargo.to_string_lossy().contains("cargo") && acap == "acap" =>
         {
@@ -68,67 +207,261 @@ fn cargo_acap_args() -> impl Iterator<Item = OsString> {
     args.into_iter()
 }
 
+impl Subcommand {
+    /// Whether this subcommand operates on every workspace member, rather than a single package.
+    fn spans_workspace(&self) -> bool {
+        match self {
+            Subcommand::Build(build) => build.workspace(),
+            Subcommand::Check(_)
+            | Subcommand::Clippy(_)
+            | Subcommand::Deploy(_)
+            | Subcommand::Detect(_)
+            | Subcommand::Devices(_)
+            | Subcommand::Doctor(_)
+            | Subcommand::Init(_)
+            | Subcommand::Inspect(_)
+            | Subcommand::Install(_)
+            | Subcommand::License(_)
+            | Subcommand::New(_)
+            | Subcommand::Remove(_)
+            | Subcommand::Run(_)
+            | Subcommand::Start(_)
+            | Subcommand::Stop(_)
+            | Subcommand::Targets(_)
+            | Subcommand::Test(_)
+            | Subcommand::Unpack(_)
+            | Subcommand::Verify(_) => false,
+        }
+    }
+}
+
+/// Construct `cargo`'s configuration, for loading the workspace at `manifest_path`.
+fn cargo_config(manifest_path: &Path) -> Result<cargo::Config, Error> {
+    cargo::Config::default().map_err(|e| Error::Workspace {
+        path: manifest_path.to_owned(),
+        message: format!("error constructing `cargo` config: {}", e),
+    })
+}
+
+/// The rustflags which `config` gives `target`: those of `[target.<triple>]`, or else of `build`.
+///
+/// Those of `[target.'cfg(…)']` tables would need `rustc` to tell which apply, so any such table
+/// with rustflags makes this an error.
+fn configured_rustflags(config: &cargo::Config, target: Target) -> Result<Vec<String>, String> {
+    fn error(e: impl std::fmt::Display) -> String {
+        format!("error reading `cargo` config: {:#}", e)
+    }
+    if let Some((cfg, _)) = config
+        .target_cfgs()
+        .map_err(error)?
+        .iter()
+        .find(|(_, table)| table.rustflags.is_some())
+    {
+        return Err(format!(
+            "the rustflags in `[target.'{}']` of `cargo`'s configuration can't be combined with \
+             `rustflags` metadata or --deny-warnings: move them to `[target.{}]` or `[build]`",
+            cfg,
+            target.rust_target_triple()
+        ));
+    }
+    let rustflags = match config
+        .target_cfg_triple(target.rust_target_triple())
+        .map_err(error)?
+        .rustflags
+    {
+        Some(rustflags) => Some(rustflags.val),
+        None => config.build_config().map_err(error)?.rustflags.clone(),
+    };
+    Ok(rustflags
+        .map(|rustflags| rustflags.as_slice().to_vec())
+        .unwrap_or_default())
+}
+
+/// The absolute path of the manifest at `manifest_path`, which may be relative to the current
+/// directory, with symlinks resolved as they are for the workspace's other paths.
+fn absolute_manifest_path(manifest_path: &Path) -> Result<PathBuf, Error> {
+    manifest_path.canonicalize().with_path(manifest_path)
+}
+
+/// Load the workspace containing the package at `manifest_path`, which must be absolute.
+fn load_workspace<'a>(
+    manifest_path: &Path,
+    cargo_config: &'a cargo::Config,
+) -> Result<cargo::core::Workspace<'a>, Error> {
+    cargo::core::Workspace::new(manifest_path, cargo_config).map_err(|e| Error::Workspace {
+        path: manifest_path.to_owned(),
+        message: format!("{:#}", e),
+    })
+}
+
+/// Select the workspace member matching `spec`, or the current package if no `spec` is given.
+///
+/// If `any_member` is set, any member is acceptable when there is no current package.
+///
+/// Fails with a list of workspace members if no package could be selected.
+fn select_package<'a>(
+    workspace: &'a cargo::core::Workspace,
+    spec: Option<&str>,
+    any_member: bool,
+) -> Result<&'a cargo::core::Package, Error> {
+    let problem = match spec {
+        Some(spec) => {
+            let id_spec = cargo::core::PackageIdSpec::parse(spec)
+                .map_err(|e| Error::Usage(format!("invalid package spec `{}`: {}", spec, e)))?;
+            match workspace
+                .members()
+                .find(|p| id_spec.matches(p.package_id()))
+            {
+                Some(package) => return Ok(package),
+                None => format!("package `{}` is not a member of the workspace", spec),
+            }
+        }
+        None => match workspace
+            .current_opt()
+            .or_else(|| workspace.members().find(|_| any_member))
+        {
+            Some(package) => return Ok(package),
+            None => {
+                "the manifest is a virtual workspace, so a package must be selected".to_string()
+            }
+        },
+    };
+
+    let mut message = format!("{}\nuse -p/--package to specify one of:", problem);
+    for member in workspace.members() {
+        message += &format!("\n  * {}", member.name());
+    }
+    Err(Error::Usage(message))
+}
+
 impl Invocation {
-    pub fn main() -> ! {
+    pub fn main() -> Result<(), Error> {
         let Args {
             mut global_options,
             subcommand,
             ..
         } = Args::parse_from(cargo_acap_args());
+        crate::output::set_quiet(global_options.quiet);
+        crate::output::set_color(global_options.color);
+
+        // Scaffolding creates a package, so it can't load one first
+        let subcommand = match subcommand {
+            Subcommand::New(sub) => return sub.invoke(),
+            Subcommand::Init(sub) => return sub.invoke(),
+            // Detecting a device's target needs only the device, and finding devices only the
+            // network
+            Subcommand::Detect(sub) => return sub.invoke(global_options.verbose),
+            Subcommand::Devices(sub) => return sub.invoke(global_options.verbose),
+            // Diagnosing problems can't depend on there being none
+            Subcommand::Doctor(sub) => return sub.invoke(global_options),
+            Subcommand::Unpack(sub) => return sub.invoke(),
+            Subcommand::Verify(sub) => return sub.invoke(),
+            subcommand => subcommand,
+        };
 
-        let cargo_config = cargo::Config::default().expect("error constructing `cargo` config");
-        let cargo_home = cargo_config.home().as_path_unlocked().to_owned();
-        let manifest_path = global_options
-            .manifest_path
-            .canonicalize()
-            .expect("error canonicalizing the manifest path");
+        // DOCKER_OPTS predates --docker-arg, so honor it, splitting it the way a shell would
+        if let Some(docker_opts) = std::env::var_os("DOCKER_OPTS") {
+            let docker_opts = docker_opts.to_string_lossy();
+            let args = shlex::split(&docker_opts).ok_or_else(|| {
+                Error::Usage(format!(
+                    "DOCKER_OPTS is not valid shell syntax, e.g. because of an unbalanced quote: {}",
+                    docker_opts
+                ))
+            })?;
+            global_options.docker_args.splice(0..0, args);
+        }
 
-        let cargo_workspace = cargo::core::Workspace::new(&manifest_path, &cargo_config)
-            .expect("error loading `cargo` workspace");
-        let workspace_root = cargo_workspace
-            .root()
-            .to_owned()
-            .canonicalize()
-            .expect("error canonicalizing workspace root");
+        let ssh_auth_sock = std::env::var_os("SSH_AUTH_SOCK").map(PathBuf::from);
+        if global_options.ssh_agent && ssh_auth_sock.is_none() {
+            return Err(Error::Usage(
+                "--ssh-agent requires an SSH agent, but SSH_AUTH_SOCK is not set".to_string(),
+            ));
+        }
+
+        let cargo_config = cargo_config(&global_options.manifest_path)?;
+        let cargo_home = cargo_config.home().as_path_unlocked().to_owned();
+        let manifest_path = absolute_manifest_path(&global_options.manifest_path)?;
+        let cargo_workspace = load_workspace(&manifest_path, &cargo_config)?;
+        let workspace_root = cargo_workspace.root().to_owned();
+        let workspace_root = workspace_root.canonicalize().with_path(&workspace_root)?;
+        // The target directory may be anywhere, e.g. in CARGO_TARGET_DIR, and it or `acap/` within
+        // it may be a symlink, so resolve both to the paths that are mounted into containers
         let workspace_target = {
             let fs = cargo_workspace.target_dir();
             let path = fs.as_path_unlocked();
-            std::fs::create_dir_all(path).expect("error creating target/");
-            path.canonicalize().expect("error canonicalizing target/")
+            std::fs::create_dir_all(path).with_path(path)?;
+            path.canonicalize().with_path(path)?
         };
+        let acap_target = {
+            let path = workspace_target.join("acap");
+            std::fs::create_dir_all(&path).with_path(&path)?;
+            path.canonicalize().with_path(&path)?
+        };
+
+        let cargo_package = select_package(
+            &cargo_workspace,
+            global_options.package.as_deref(),
+            subcommand.spans_workspace(),
+        )?
+        .clone();
+        let workspace_members = cargo_workspace.members().cloned().collect();
+        let profiles = cargo_workspace.profiles().cloned();
 
-        let cargo_package = cargo_workspace
-            .current()
-            .expect("error getting current `cargo` package")
-            .clone();
+        let configured_rustflags = Target::all()
+            .iter()
+            .map(|&target| (target, configured_rustflags(&cargo_config, target)))
+            .collect();
 
         let rustc = cargo_config
             .load_global_rustc(Some(&cargo_workspace))
-            .expect("error loading rustc");
+            .map_err(|e| Error::Workspace {
+                path: manifest_path.clone(),
+                message: format!("error loading rustc: {:#}", e),
+            })?;
 
-        if global_options.docker_image.contains(':') {
-            // use the provided tag
-        } else {
-            // use rustc's version as the tag
-            let image_with_tag = format!("{}:{}", &global_options.docker_image, &rustc.version);
-            global_options.docker_image = image_with_tag;
-        };
+        // Unless the image is tagged already, use the image built for our `rustc`
+        global_options.docker_image =
+            environment::with_default_tag(&global_options.docker_image, &rustc.version.to_string());
 
+        let reuse_container = global_options.reuse_container;
         let invocation = Invocation {
             global_options,
             cargo_home,
             workspace_root,
-            workspace_target,
+            manifest_path,
             cargo_package,
-            acap_target: Mutex::new(None),
+            workspace_members,
+            profiles,
+            configured_rustflags,
+            rustc,
+            rustc_checked_images: Mutex::default(),
+            acap_target,
+            reused_containers: reuse_container.then(Default::default),
+            platforms: Arc::default(),
         };
 
         match subcommand {
             Subcommand::Build(sub) => sub.invoke(invocation),
+            Subcommand::Check(sub) => sub.invoke(invocation, check::Checker::Check),
+            Subcommand::Clippy(sub) => sub.invoke(invocation, check::Checker::Clippy),
+            Subcommand::Deploy(sub) => sub.invoke(invocation),
+            Subcommand::Detect(_)
+            | Subcommand::Devices(_)
+            | Subcommand::Doctor(_)
+            | Subcommand::Init(_)
+            | Subcommand::New(_)
+            | Subcommand::Unpack(_)
+            | Subcommand::Verify(_) => unreachable!(),
+            Subcommand::Inspect(sub) => sub.invoke(invocation),
+            Subcommand::Install(sub) => sub.invoke(invocation),
+            Subcommand::License(sub) => sub.invoke(invocation),
+            Subcommand::Remove(sub) => sub.invoke(invocation, control::Action::Remove),
+            Subcommand::Run(sub) => sub.invoke(invocation),
+            Subcommand::Start(sub) => sub.invoke(invocation, control::Action::Start),
+            Subcommand::Stop(sub) => sub.invoke(invocation, control::Action::Stop),
             Subcommand::Targets(sub) => sub.invoke(invocation),
-        };
-
-        std::process::exit(0);
+            Subcommand::Test(sub) => sub.invoke(invocation),
+        }
     }
 
     pub fn global_options(&self) -> &GlobalOptions {
@@ -139,170 +472,391 @@ impl Invocation {
         self.cargo_package.name().as_str()
     }
 
-    pub fn acap_target(&self) -> PathBuf {
-        let mut lock = self.acap_target.lock().unwrap();
+    /// The names of the package's binary targets.
+    pub fn binary_names(&self) -> Vec<&str> {
+        self.cargo_package
+            .targets()
+            .iter()
+            .filter(|t| t.is_bin())
+            .map(|t| t.name())
+            .collect()
+    }
 
-        if let Some(dir) = lock.as_ref() {
-            return dir.clone();
+    /// The name of the binary selected by `bin`, or of the package's only binary.
+    ///
+    /// This is the name of the `[[bin]]` target, which need not match the package name.
+    ///
+    /// Fails with a list of binaries if the selection is ambiguous.
+    pub fn binary_name<'a>(&'a self, bin: Option<&'a str>) -> Result<&'a str, Error> {
+        let binaries = self.binary_names();
+
+        let mut message = match (bin, binaries.len()) {
+            (Some(bin), _) if binaries.contains(&bin) => return Ok(bin),
+            (None, 1) => return Ok(binaries[0]),
+            (_, 0) => format!("package `{}` has no binaries", self.cargo_package_name()),
+            (Some(bin), _) => format!("no binary named `{}`", bin),
+            (None, _) => "could not determine which binary to use".into(),
+        };
+
+        if !binaries.is_empty() {
+            message += "\nuse --bin to specify one of:";
+            for binary in binaries {
+                message += &format!("\n  * {}", binary);
+            }
         }
+        Err(Error::Usage(message))
+    }
 
-        let acap_target = self.workspace_target.join("acap");
-        std::fs::create_dir(&acap_target)
-            .or_else(|e| {
-                if e.kind() == std::io::ErrorKind::AlreadyExists {
-                    Ok(())
-                } else {
-                    Err(e)
-                }
-            })
-            .expect("error creating target/acap/");
+    /// The absolute path of the manifest selected by `--manifest-path`.
+    pub fn manifest_path(&self) -> &Path {
+        &self.manifest_path
+    }
 
-        lock.replace(acap_target.clone());
-        acap_target
+    /// The directory in which `cargo acap` builds, i.e. `target/acap/`.
+    pub fn acap_target(&self) -> PathBuf {
+        self.acap_target.clone()
     }
 
-    pub fn docker_run_command(&self) -> std::process::Command {
-        // Start constructing the command
-        let mut docker = std::process::Command::new("docker");
-        docker.args(&["run", "--rm"]);
-        if std::io::stdin().is_tty() {
-            docker.arg("--interactive");
-            if std::io::stdout().is_tty() {
-                docker.arg("--tty");
-            }
+    /// The `docker` image in which to build `target`: the one given by `--docker-image-for`,
+    /// or else by `metadata`, or else by `--docker-image`.
+    pub fn docker_image_for(&self, target: Target, metadata: &CargoAcapMetadata) -> String {
+        let image = self
+            .global_options
+            .docker_image_for
+            .iter()
+            .rev()
+            .find(|(t, _)| *t == target)
+            .map(|(_, image)| image.as_str())
+            .or_else(|| metadata.docker_image(target));
+        match image {
+            Some(image) => environment::with_default_tag(image, &self.rustc.version.to_string()),
+            None => self.global_options.docker_image.clone(),
         }
+    }
 
-        // Run with the right uid, gid, and USER env var
-        let whoami = whoami();
-        docker.args(&["--user", &format!("{}:{}", whoami.uid, whoami.gid)]);
-        if let Some(username) = whoami.username.as_ref() {
-            docker.args(&["--env", &format!("USER={}", username)]);
+    /// Snapshot the environment in which to build the current package.
+    pub fn build_environment(&self, no_docker: bool) -> BuildEnvironment {
+        BuildEnvironment {
+            verbose: self.global_options.verbose,
+            container_engine: self.global_options.container_engine.resolve(),
+            docker_image: self.global_options.docker_image.clone(),
+            docker_args: self.global_options.docker_args.clone(),
+            container_user: self.global_options.container_user,
+            env: self.global_options.env.clone(),
+            ssh_agent: self.global_options.ssh_agent,
+            sccache: self.global_options.sccache,
+            no_docker,
+            interactive: true,
+            stdout_to_stderr: false,
+            cargo_home: self.cargo_home.clone(),
+            workspace_root: self.workspace_root.clone(),
+            package_root: self.package_root().to_owned(),
+            package_name: self.cargo_package_name().to_string(),
+            selected_package: self.selected_package().map(String::from),
+            acap_target: self.acap_target(),
+            reused_containers: self.reused_containers.clone(),
+            platforms: self.platforms.clone(),
         }
+    }
 
-        // Mount the root_path at root_path path and use it as the current directory
-        docker.args(&[
-            "--volume",
-            &format!(
-                "{}:/{}:Z",
-                self.workspace_root.display(),
-                self.workspace_root.display()
-            ),
-            "--workdir",
-            &self.cargo_package.root().display().to_string(),
-        ]);
-
-        // Mount target_path at /target and tell `cargo` to use it
-        docker.args(&[
-            "--volume",
-            &format!("{}:/target:Z", self.acap_target().display()),
-        ]);
-        docker.args(&["--env", "CARGO_TARGET_DIR=/target"]);
-
-        // Mount the cargo home at /.cargo
-        docker.args(&[
-            "--volume",
-            &format!("{}:/.cargo:Z", self.cargo_home.display()),
-        ]);
-
-        // Pass through some env vars to docker container that cargo might need to fetch dependencies
-        let pass_through_env = [
-            "http_proxy",
-            "https_proxy",
-            "ftp_proxy",
-            "all_proxy",
-            "no_proxy",
-        ];
-        for var in pass_through_env {
-            if let Ok(val) = std::env::var(var) {
-                docker.args(&["--env", &format!("{}={}", var, val)]);
-            }
+    /// The host's `rustc --version`, which Docker images are chosen to match.
+    pub fn rustc_version(&self) -> &str {
+        self.rustc
+            .verbose_version
+            .lines()
+            .next()
+            .unwrap_or_default()
+    }
+
+    /// Warn, or fail if `strict`, if the `rustc` in `environment`'s Docker image isn't the same
+    /// version as the host's, e.g. because `--docker-image` names a tag for another version.
+    ///
+    /// Each image is only checked once, since that means starting a container.
+    pub fn check_image_rustc(
+        &self,
+        environment: &BuildEnvironment,
+        strict: bool,
+    ) -> Result<(), Error> {
+        if environment.no_docker {
+            return Ok(());
+        }
+        let image = environment.docker_image();
+        // One lock covers the check and the insert, so that targets built concurrently check it once
+        if !self
+            .rustc_checked_images
+            .lock()
+            .unwrap()
+            .insert(image.to_string())
+        {
+            return Ok(());
         }
 
-        if let Ok(value) = std::env::var("DOCKER_OPTS") {
-            let opts: Vec<&str> = value.split(' ').collect();
-            docker.args(&opts);
+        let image_rustc = environment.image_rustc_version()?;
+        if self.global_options.verbose > 0 {
+            eprintln!("cargo-acap: Docker image {} has {}", image, image_rustc);
         }
 
-        docker.arg(&self.global_options.docker_image);
-        docker
+        let host_version = self.rustc.version.to_string();
+        let image_version = image_rustc.split_whitespace().nth(1).unwrap_or_default();
+        if image_version == host_version {
+            return Ok(());
+        }
+        let repository = environment::image_repository(image);
+        let advice = if image == format!("{}:{}", repository, host_version) {
+            // The image is tagged for the host's `rustc`, but was built with another
+            "the local image may be stale, so pull it again with `--pull always`".to_string()
+        } else {
+            format!(
+                "select the image for the host's rustc with an explicit tag, e.g. \
+                 --docker-image {}:{}, or install rustc {} on the host",
+                repository, host_version, image_version
+            )
+        };
+        let message = format!(
+            "Docker image {} has rustc {}, but the host has rustc {}, so packages are built by a \
+             different compiler than the host's `cargo` uses: {}",
+            image, image_version, host_version, advice
+        );
+        if strict {
+            return Err(Error::Image(message));
+        }
+        eprintln!(
+            "cargo-acap: {} {}",
+            output::paint(Style::Warning, "warning:"),
+            message
+        );
+        Ok(())
     }
 
-    pub fn run_to_completion(&self, mut command: std::process::Command) {
-        if self.global_options.verbose > 1 {
-            println!("+ {:?}", &command);
+    /// The sysroot of the host's `rustc`, i.e. the one used by `--no-docker` builds.
+    pub fn host_rustc_sysroot(&self) -> Result<PathBuf, Error> {
+        let output = std::process::Command::new(&self.rustc.path)
+            .args(["--print", "sysroot"])
+            .output()
+            .map_err(|source| Error::Spawn {
+                program: self.rustc.path.display().to_string(),
+                source,
+            })?;
+        if !output.status.success() {
+            return Err(Error::Usage(format!(
+                "`{} --print sysroot` failed: {}",
+                self.rustc.path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
         }
+        Ok(PathBuf::from(
+            String::from_utf8_lossy(&output.stdout).trim(),
+        ))
+    }
 
-        let exit_status = command
-            .spawn()
-            .expect("error running command")
-            .wait()
-            .expect("command failed");
-        if !exit_status.success() {
-            let code = exit_status.code().expect("code() for failed exit status");
-            eprintln!(
-                "`cargo acap` failed: `{:?}` returned exit code {}",
-                &command, code
-            );
-            std::process::exit(code);
-        }
+    /// The manifest which configures the workspace's profiles, i.e. its root manifest.
+    pub fn root_manifest_path(&self) -> PathBuf {
+        self.workspace_root.join("Cargo.toml")
     }
 
-    pub fn package_source_path(&self) -> PathBuf {
-        self.cargo_package.root().join("src")
+    /// The rustflags which `cargo`'s configuration gives `target`, and which `cargo` uses unless
+    /// its environment gives some.
+    pub fn configured_rustflags(&self, target: Target) -> Result<Vec<String>, Error> {
+        self.configured_rustflags[&target]
+            .clone()
+            .map_err(Error::Usage)
     }
 
-    pub fn package_version(&self) -> String {
-        self.cargo_package.version().to_string()
+    /// The `[profile.<name>]` table of the workspace, if it has one.
+    pub fn profile(&self, name: &str) -> Option<&TomlProfile> {
+        self.profiles
+            .as_ref()
+            .and_then(|profiles| profiles.get(name))
     }
-}
 
-trait StdioExt {
-    fn is_tty(&self) -> bool;
-}
+    /// All the packages in the workspace.
+    pub fn workspace_members(&self) -> &[cargo::core::Package] {
+        &self.workspace_members
+    }
 
-#[cfg(unix)]
-impl StdioExt for std::io::Stdin {
-    fn is_tty(&self) -> bool {
-        use std::os::unix::io::AsRawFd;
-        // Safety: at worst isatty() just sets errno
-        unsafe { libc::isatty(self.as_raw_fd()) == 1 }
+    /// Make `package` the package on which this invocation operates, as if selected by `-p`.
+    pub fn select_member(&mut self, package: cargo::core::Package) {
+        self.global_options.package = Some(package.name().to_string());
+        self.cargo_package = package;
     }
-}
 
-#[cfg(unix)]
-impl StdioExt for std::io::Stdout {
-    fn is_tty(&self) -> bool {
-        use std::os::unix::io::AsRawFd;
-        // Safety: at worst isatty() just sets errno
-        unsafe { libc::isatty(self.as_raw_fd()) == 1 }
+    /// The package name to pass to the inner `cargo`, if one was selected explicitly.
+    pub fn selected_package(&self) -> Option<&str> {
+        self.global_options
+            .package
+            .as_ref()
+            .map(|_| self.cargo_package_name())
     }
-}
 
-#[cfg(windows)]
-impl StdioExt for std::io::Stdin {
-    fn is_tty(&self) -> bool {
-        // FIXME
-        false
+    pub fn package_root(&self) -> &Path {
+        self.cargo_package.root()
     }
-}
 
-#[cfg(windows)]
-impl StdioExt for std::io::Stdout {
-    fn is_tty(&self) -> bool {
-        // FIXME
-        false
+    /// The version of the application: `version` in `[package.metadata.acap]`, or else the
+    /// package's.
+    pub fn package_version(&self) -> Result<String, Error> {
+        let metadata = CargoAcapMetadata::for_package(&self.cargo_package)?;
+        Ok(metadata.version(&self.cargo_package).to_string())
     }
 }
 
-#[cfg(all(not(unix), not(windows)))]
-impl StdioExt for std::io::Stdin {
-    fn is_tty(&self) -> bool {
-        unimplemented!()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::build::cargo_command;
+    use crate::cli::environment::tests::environment;
+    use crate::cli::environment::ContainerEngine;
+    use crate::testing;
+
+    /// A workspace whose root manifest is virtual, with `app` nested in `crates/app` and `other`
+    /// beside the workspace's directory.
+    fn workspace() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        testing::write(
+            &root,
+            "workspace/Cargo.toml",
+            "[workspace]\nmembers = [\"crates/app\", \"../other\"]\n",
+        );
+        for (path, name, workspace) in [
+            ("workspace/crates/app", "app", None),
+            ("other", "other", Some("../workspace")),
+        ] {
+            let mut manifest = format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name);
+            if let Some(workspace) = workspace {
+                manifest += &format!("workspace = \"{}\"\n", workspace);
+            }
+            testing::write(&root, &format!("{}/Cargo.toml", path), &manifest);
+            testing::write(&root, &format!("{}/src/main.rs", path), "fn main() {}\n");
+        }
+        dir
     }
-}
-#[cfg(all(not(unix), not(windows)))]
-impl StdioExt for std::io::Stdout {
-    fn is_tty(&self) -> bool {
-        unimplemented!()
+
+    /// The arguments of the `cargo build` which builds `package` from the workspace selected by
+    /// `manifest_path`.
+    fn cargo_build_args(manifest_path: &Path, package: &str) -> Result<Vec<String>, Error> {
+        let manifest_path = absolute_manifest_path(manifest_path)?;
+        let config = cargo_config(&manifest_path)?;
+        let workspace = load_workspace(&manifest_path, &config)?;
+        let package = select_package(&workspace, Some(package), false)?;
+
+        let workspace_root = workspace.root().to_str().unwrap();
+        let mut environment = environment(
+            workspace_root,
+            &format!("{}/target/acap", workspace_root),
+            ContainerEngine::Docker,
+        );
+        environment.package_root = package.root().to_owned();
+        environment.selected_package = Some(package.name().to_string());
+        let cargo = cargo_command(
+            &environment,
+            "build",
+            Target::Mips,
+            "release",
+            vec![],
+            &manifest_path,
+        )?;
+        Ok(cargo
+            .get_args()
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .collect())
+    }
+
+    /// The value of `--manifest-path` in `args`, if any.
+    fn manifest_path_arg(args: &[String]) -> Option<&str> {
+        args.windows(2)
+            .find(|pair| pair[0] == "--manifest-path")
+            .map(|pair| pair[1].as_str())
+    }
+
+    #[test]
+    fn relative_manifest_paths_are_made_absolute() {
+        // Tests run in the package's directory
+        let expected = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("Cargo.toml")
+            .canonicalize()
+            .unwrap();
+        for path in ["Cargo.toml", "./src/../Cargo.toml"] {
+            assert_eq!(absolute_manifest_path(Path::new(path)).unwrap(), expected);
+        }
+        assert!(matches!(
+            absolute_manifest_path(Path::new("no/such/Cargo.toml")),
+            Err(Error::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn a_nested_package_is_built_with_an_absolute_manifest_path() {
+        let dir = workspace();
+        let root = dir.path().canonicalize().unwrap();
+
+        // Selected from the workspace's root, `cargo` must be pointed back at its manifest
+        let workspace_manifest = root.join("workspace/Cargo.toml");
+        let args = cargo_build_args(&workspace_manifest, "app").unwrap();
+        assert_eq!(
+            manifest_path_arg(&args),
+            Some(workspace_manifest.to_str().unwrap())
+        );
+        assert!(args.windows(2).any(|pair| pair == ["--package", "app"]));
+
+        // Selected by its own manifest, `cargo` finds it in the package's directory
+        let args = cargo_build_args(&root.join("workspace/crates/app/Cargo.toml"), "app").unwrap();
+        assert_eq!(manifest_path_arg(&args), None);
+    }
+
+    #[test]
+    fn a_package_outside_the_mounted_workspace_is_an_error() {
+        let dir = workspace();
+        let root = dir.path().canonicalize().unwrap();
+        for manifest_path in [
+            root.join("other/Cargo.toml"),
+            root.join("workspace/Cargo.toml"),
+        ] {
+            match cargo_build_args(&manifest_path, "other") {
+                Err(Error::Usage(message)) => {
+                    assert!(message.contains("outside the workspace"), "{}", message)
+                }
+                other => panic!("expected a usage error, got {:?}", other),
+            }
+        }
+    }
+
+    /// `cargo`'s configuration as read by `cargo` run in `dir`.
+    fn config_in(dir: &Path) -> cargo::Config {
+        let mut config = cargo::Config::default().unwrap();
+        config.reload_rooted_at(dir).unwrap();
+        config
+    }
+
+    #[test]
+    fn configured_rustflags_prefer_the_targets_own_to_builds() {
+        let dir = tempfile::tempdir().unwrap();
+        testing::write(
+            dir.path(),
+            ".cargo/config.toml",
+            "[build]\nrustflags = [\"--cfg\", \"build\"]\n\n\
+             [target.aarch64-axis-linux-gnu]\nrustflags = \"-Ctarget-cpu=cortex-a53\"\n",
+        );
+        let config = config_in(dir.path());
+        assert_eq!(
+            configured_rustflags(&config, Target::Aarch64).unwrap(),
+            ["-Ctarget-cpu=cortex-a53"]
+        );
+        assert_eq!(
+            configured_rustflags(&config, Target::Mips).unwrap(),
+            ["--cfg", "build"]
+        );
+
+        testing::write(
+            dir.path(),
+            ".cargo/config.toml",
+            "[target.'cfg(target_os = \"linux\")']\nrustflags = [\"--cfg\", \"linux\"]\n",
+        );
+        let message = configured_rustflags(&config_in(dir.path()), Target::Mips).unwrap_err();
+        assert!(
+            message.contains("cfg(target_os = \"linux\")"),
+            "{}",
+            message
+        );
     }
 }
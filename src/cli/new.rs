@@ -0,0 +1,227 @@
+use crate::cargo_config::app_name_problem;
+use crate::error::{Error, IoResultExt};
+use crate::output::status;
+use crate::package_dot_conf::{normalized_app_name, StartMode};
+use clap::{Parser, ValueEnum};
+use std::path::{Path, PathBuf};
+
+/// Create a new ACAP application package
+#[derive(Parser)]
+pub struct New {
+    /// The directory to create
+    path: PathBuf,
+
+    #[clap(flatten)]
+    options: ScaffoldOptions,
+}
+
+/// Create a new ACAP application package in an existing directory
+#[derive(Parser)]
+pub struct Init {
+    /// The directory in which to create the package
+    #[clap(default_value = ".")]
+    path: PathBuf,
+
+    #[clap(flatten)]
+    options: ScaffoldOptions,
+}
+
+#[derive(Debug, Parser)]
+struct ScaffoldOptions {
+    /// The package name (defaults to the directory name)
+    #[clap(long)]
+    name: Option<String>,
+
+    /// The vendor shown in the Axis product's web pages
+    #[clap(long)]
+    vendor: Option<String>,
+
+    /// The application's name on the device (defaults to the package name, with `-` as `_`)
+    #[clap(long, value_name = "NAME")]
+    app_name: Option<String>,
+
+    /// How the Axis product should start the application
+    #[clap(long, arg_enum, value_name = "MODE", default_value = "respawn")]
+    start_mode: StartMode,
+}
+
+impl New {
+    pub(crate) fn invoke(self) -> Result<(), Error> {
+        if self.path.exists() {
+            return Err(Error::Usage(format!(
+                "destination `{}` already exists: use `cargo acap init` instead",
+                self.path.display()
+            )));
+        }
+        scaffold(&self.path, &self.options)
+    }
+}
+
+impl Init {
+    pub(crate) fn invoke(self) -> Result<(), Error> {
+        if self.path.join("Cargo.toml").exists() {
+            return Err(Error::Usage(format!(
+                "`{}` already contains a Cargo.toml",
+                self.path.display()
+            )));
+        }
+        scaffold(&self.path, &self.options)
+    }
+}
+
+/// Write a minimal ACAP application package to `path`, leaving any existing files alone.
+fn scaffold(path: &Path, options: &ScaffoldOptions) -> Result<(), Error> {
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => std::env::current_dir()
+            .map(|cwd| cwd.join(path))
+            .ok()
+            .and_then(|path| path.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .unwrap_or_default(),
+    };
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error::Usage(format!(
+            "`{}` is not a valid package name: use --name",
+            name
+        )));
+    }
+
+    let app_name = options
+        .app_name
+        .clone()
+        .unwrap_or_else(|| normalized_app_name(&name));
+    if let Some(problem) = app_name_problem(&app_name) {
+        return Err(Error::Usage(format!(
+            "`{}` is not a valid app name: it {}",
+            app_name, problem
+        )));
+    }
+
+    let vendor = options
+        .vendor
+        .clone()
+        .unwrap_or_else(|| format!("{} authors", name));
+    let start_mode = options
+        .start_mode
+        .to_possible_value()
+        .expect("start mode has a name")
+        .get_name();
+
+    let files = [
+        (
+            "Cargo.toml",
+            cargo_toml(&name, &app_name, &vendor, start_mode),
+        ),
+        ("src/main.rs", main_rs(&app_name)),
+        ("html/index.html", index_html(&name)),
+        (".gitignore", "/target\n".to_string()),
+    ];
+
+    for (relative_path, contents) in &files {
+        let file_path = path.join(relative_path);
+        if file_path.exists() {
+            status!(
+                "cargo-acap: keeping existing {}",
+                Path::new(relative_path).display()
+            );
+            continue;
+        }
+        std::fs::create_dir_all(file_path.parent().unwrap())
+            .and_then(|_| std::fs::write(&file_path, contents))
+            .with_path(&file_path)?;
+    }
+
+    status!(
+        "cargo-acap: created ACAP application `{}` in {}",
+        name,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Quote `s` as a TOML string.
+fn toml_string(s: &str) -> String {
+    toml::Value::String(s.to_string()).to_string()
+}
+
+fn cargo_toml(name: &str, app_name: &str, vendor: &str, start_mode: &str) -> String {
+    format!(
+        r#"[package]
+name = {name}
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+libc = "0.2"
+
+[profile.release]
+# Unwinding adds size and isn't useful on the device: if the application panics, let it exit and
+# let the device restart it
+panic = "abort"
+
+[package.metadata.acap]
+app_name = {app_name}
+display_name = {name}
+vendor = {vendor}
+start_mode = {start_mode}
+settings_page_file = "index.html"
+"#,
+        name = toml_string(name),
+        app_name = toml_string(app_name),
+        vendor = toml_string(vendor),
+        start_mode = toml_string(start_mode),
+    )
+}
+
+fn main_rs(app_name: &str) -> String {
+    format!(
+        r#"use std::ffi::CString;
+use std::time::Duration;
+
+/// Write `message` to the system log, where the Axis product's web pages can show it.
+fn log(message: &str) {{
+    let message = CString::new(message).expect("message contains a NUL");
+    unsafe {{
+        libc::syslog(libc::LOG_INFO, b"%s\0".as_ptr() as *const _, message.as_ptr());
+    }}
+}}
+
+fn main() {{
+    // syslog keeps a pointer to the identifier, so it must live as long as the program
+    let ident = CString::new({app_name:?}).unwrap();
+    unsafe {{
+        libc::openlog(ident.as_ptr(), libc::LOG_PID, libc::LOG_USER);
+    }}
+
+    log("started");
+    loop {{
+        std::thread::sleep(Duration::from_secs(60));
+        log("still running");
+    }}
+}}
+"#,
+        app_name = app_name,
+    )
+}
+
+fn index_html(name: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>{name}</title>
+</head>
+<body>
+  <h1>{name}</h1>
+  <p>This page is packaged from <code>html/</code> and linked as the application's settings page.</p>
+</body>
+</html>
+"#,
+        name = name,
+    )
+}
@@ -0,0 +1,392 @@
+use crate::build_info::BuildInfo;
+use crate::cargo_config::CgiPath;
+use crate::eap::{EapBuilder, Entry, Source};
+use crate::error::{Error, IoResultExt};
+use crate::manifest_dot_json::ManifestDotJson;
+use crate::package_dot_conf::{archive_path, files_in, html_escape, MappedFile, PackageDotConf};
+use crate::param_dot_conf::ParamDotConf;
+use std::path::{Path, PathBuf};
+
+/// Plan the contents of the package for `package_conf`, whose executable is at `executable`.
+///
+/// `libraries` are built files to install alongside the executable, each named by its path within
+/// the package. `build_info`, if any, is installed as `build-info.json`, and noted in
+/// `package.conf`, which is adjusted for the files found.
+#[allow(clippy::too_many_arguments)]
+pub fn contents(
+    package_root: &Path,
+    package_conf: &PackageDotConf,
+    param_conf: Option<&ParamDotConf>,
+    manifest: Option<&ManifestDotJson>,
+    executable: &Path,
+    libraries: &[(String, PathBuf)],
+    build_info: Option<&BuildInfo>,
+) -> Result<EapBuilder, Error> {
+    let mut package_conf = package_conf.clone();
+    let mut eap = EapBuilder::new();
+
+    // files mapped by `files`, after the directories containing them
+    add_mapped_files(&mut eap, &package_conf.files);
+
+    // cgi.txt, generated from the metadata or else as found, if any
+    if !package_conf.cgi_paths.is_empty() {
+        let lines: Vec<String> = package_conf.cgi_paths.iter().map(CgiPath::line).collect();
+        eap.add_generated("cgi.txt", lines.join("\n") + "\n");
+        package_conf.http_cgi_paths = Some("cgi.txt".into());
+    } else if let Some(path) = &package_conf.http_cgi_paths_path {
+        eap.add_file("cgi.txt", path);
+        package_conf.http_cgi_paths = Some("cgi.txt".into());
+    }
+
+    // data/ and other_files, which package.conf lists as OTHERFILES
+    for name in &package_conf.other_files {
+        eap.add_file(name, package_root.join(name));
+    }
+    // which are followed in OTHERFILES by those mapped by `files`
+    package_conf
+        .other_files
+        .extend(package_conf.files.iter().map(|file| file.name.clone()));
+
+    // the D-Bus policy, as given or else generated, which is listed as OTHERFILES too
+    if let Some(dbus) = &package_conf.dbus {
+        match &package_conf.dbus_conf_path {
+            Some(path) => eap.add_file(dbus.archive_path(), path),
+            None => eap.add_generated(
+                dbus.archive_path(),
+                dbus_policy(&dbus.service, &package_conf.unix_user),
+            ),
+        };
+        package_conf.other_files.push(dbus.archive_path());
+    }
+
+    // built libraries, which must be listed as OTHERFILES too
+    for (name, path) in libraries {
+        eap.add_file(name, path);
+        package_conf.other_files.push(name.clone());
+    }
+
+    // html/, if any
+    let html_dir = package_root.join("html");
+    if html_dir.is_dir() {
+        for relative_path in files_in(&html_dir).with_path(&html_dir)? {
+            let name = Path::new("html").join(&relative_path);
+            eap.add_file(archive_path(&name), html_dir.join(&relative_path));
+        }
+    }
+
+    // the license text, if any, along with a page showing it if the package has web pages
+    if let Some(path) = &package_conf.license_file_path {
+        eap.add_file("LICENSE", path);
+        if package_conf.settings_page_file.is_some() && !html_dir.join("license.html").exists() {
+            let text = std::fs::read_to_string(path).with_path(path)?;
+            eap.add_generated(
+                "html/license.html",
+                license_html(&package_conf.display_name, &text),
+            );
+        }
+    }
+
+    let mut comments = String::new();
+    if let Some(build_info) = build_info {
+        eap.add_generated("build-info.json", build_info.to_json());
+        package_conf.other_files.push("build-info.json".into());
+        comments = build_info.package_conf_comments();
+    }
+    eap.set_package_conf(&package_conf, &comments)?;
+
+    if let Some(manifest) = manifest {
+        eap.add_generated("manifest.json", manifest.to_string());
+    }
+
+    if let Some(param_conf) = param_conf {
+        eap.add_generated("param.conf", param_conf.to_string());
+    }
+
+    // "otherfiles", if any
+    // TODO: the "otherfiles" folder is only intended for static files.
+    // Since it might be of interest to also copy built files such as
+    // .so files, we should also provide another folder to copy from
+    // for those files.
+    let otherfiles_dir = Path::new("otherfiles");
+    if otherfiles_dir.is_dir() {
+        for relative_path in files_in(otherfiles_dir).with_path(otherfiles_dir)? {
+            eap.add_file(
+                archive_path(&relative_path),
+                otherfiles_dir.join(&relative_path),
+            );
+        }
+    }
+
+    // the post-install script, if any
+    if let (Some(name), Some(path)) = (
+        &package_conf.post_install_script,
+        &package_conf.post_install_script_path,
+    ) {
+        eap.add_executable(name, path);
+    }
+
+    // the pre-uninstall script, if any
+    if let (Some(name), Some(path)) = (
+        &package_conf.pre_uninstall_script,
+        &package_conf.pre_uninstall_script_path,
+    ) {
+        eap.add_executable(name, path);
+    }
+
+    eap.add_executable(&package_conf.executable_name, executable);
+    if package_conf.executable_name != package_conf.app_name {
+        eap.add_symlink(&package_conf.app_name, &package_conf.executable_name);
+    }
+
+    Ok(eap)
+}
+
+/// A web page showing the license `text` of the application `display_name`.
+fn license_html(display_name: &str, text: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>{name} license</title>
+</head>
+<body>
+<pre>{text}</pre>
+</body>
+</html>
+"#,
+        name = html_escape(display_name),
+        text = html_escape(text),
+    )
+}
+
+/// Add `files` to `eap`, each preceded by any of its directories not already added.
+fn add_mapped_files(eap: &mut EapBuilder, files: &[MappedFile]) {
+    for file in files {
+        for (end, _) in file.name.match_indices('/') {
+            let directory = &file.name[..end];
+            if !eap.contains(directory) {
+                eap.add_directory(directory);
+            }
+        }
+        eap.add_entry(Entry {
+            name: file.name.clone(),
+            source: Source::Path(file.path.clone()),
+            executable: false,
+            mode: file.mode,
+        });
+    }
+}
+
+/// A D-Bus policy letting the application, running as `user`, own `service`, and anyone send to
+/// it.
+fn dbus_policy(service: &str, user: &str) -> String {
+    format!(
+        r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-BUS Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <policy user="{user}">
+    <allow own="{service}"/>
+  </policy>
+  <policy context="default">
+    <allow send_destination="{service}"/>
+  </policy>
+</busconfig>
+"#,
+        service = service,
+        user = html_escape(user),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eap::tests::{round_trip, ReadEntry};
+    use crate::shell_includes;
+    use crate::target::Target;
+    use crate::testing;
+
+    /// The `package.conf` and the entries of the package of `package`'s binary `binary_name`,
+    /// built as a dummy executable.
+    fn packaged(
+        package: &cargo::core::Package,
+        binary_name: &str,
+    ) -> (PackageDotConf, Vec<ReadEntry>) {
+        let executable = package.root().join("executable");
+        std::fs::write(&executable, "\x7fELF").unwrap();
+        let package_conf =
+            PackageDotConf::from_cargo_package(package, binary_name, None, Target::Mips).unwrap();
+        let eap = contents(
+            package.root(),
+            &package_conf,
+            None,
+            None,
+            &executable,
+            &[],
+            None,
+        )
+        .unwrap();
+        (package_conf, round_trip(&eap))
+    }
+
+    /// The value `package.conf` in `entries` assigns to `name`.
+    fn package_conf_value(entries: &[ReadEntry], name: &str) -> Option<String> {
+        let package_conf = entries
+            .iter()
+            .find(|entry| entry.0 == "package.conf")
+            .unwrap();
+        shell_includes::parse(std::str::from_utf8(&package_conf.4).unwrap())
+            .unwrap()
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value)
+    }
+
+    #[test]
+    fn data_is_packaged_and_listed_as_otherfiles() {
+        let (dir, package) = testing::package(
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n",
+            &[
+                ("data/config.json", "{}\n"),
+                ("data/nested/deep/.hidden", "hidden\n"),
+                ("data/nested/script.sh", "#!/bin/sh\n"),
+                ("README.md", "readme\n"),
+            ],
+        );
+        std::fs::create_dir(dir.path().join("data/empty")).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let script = dir.path().join("data/nested/script.sh");
+            std::fs::set_permissions(script, std::fs::Permissions::from_mode(0o750)).unwrap();
+        }
+        let (_, entries) = packaged(&package, "app");
+        let data: Vec<&str> = entries
+            .iter()
+            .map(|entry| entry.0.as_str())
+            .filter(|name| name.starts_with("data"))
+            .collect();
+        let expected = [
+            "data/config.json",
+            "data/nested/deep/.hidden",
+            "data/nested/script.sh",
+        ];
+        assert_eq!(data, expected);
+        let entry = |name: &str| entries.iter().find(|entry| entry.0 == name).unwrap();
+        assert_eq!(entry("data/nested/deep/.hidden").4, b"hidden\n");
+        #[cfg(unix)]
+        assert_eq!(entry("data/nested/script.sh").2 & 0o777, 0o750);
+        assert!(entries.iter().all(|entry| entry.0 != "README.md"));
+        assert_eq!(
+            package_conf_value(&entries, "OTHERFILES"),
+            Some(expected.join(" "))
+        );
+    }
+
+    /// The name, type, mode, and link target of each entry which isn't `package.conf`.
+    fn summary(entries: &[ReadEntry]) -> Vec<(&str, tar::EntryType, u32, Option<&str>)> {
+        entries
+            .iter()
+            .filter(|entry| entry.0 != "package.conf")
+            .map(|(name, kind, mode, link, _)| (name.as_str(), *kind, *mode, link.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn a_renamed_binary_names_the_application_and_its_executable() {
+        let manifest = "[package]\nname = \"my-camera-app\"\nversion = \"0.1.0\"\n\n\
+                        [[bin]]\nname = \"acapd\"\npath = \"src/main.rs\"\n";
+        let (_dir, package) = testing::package(manifest, &[]);
+        let binaries: Vec<&str> = package
+            .targets()
+            .iter()
+            .filter(|target| target.is_bin())
+            .map(|target| target.name())
+            .collect();
+        assert_eq!(binaries, ["acapd"]);
+
+        let (package_conf, entries) = packaged(&package, "acapd");
+        assert_eq!(package_conf.app_name, "acapd");
+        assert_eq!(package_conf.executable_name, "acapd");
+        assert_eq!(package_conf.display_name, "my-camera-app");
+        assert_eq!(
+            package_conf_value(&entries, "APPNAME").as_deref(),
+            Some("acapd")
+        );
+        assert_eq!(
+            summary(&entries),
+            [("acapd", tar::EntryType::Regular, 0o755, None)]
+        );
+    }
+
+    #[test]
+    fn a_binary_name_which_is_not_a_valid_app_name_is_normalized() {
+        let manifest = "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n\
+                        [[bin]]\nname = \"Camera-Daemon\"\npath = \"src/main.rs\"\n";
+        let (_dir, package) = testing::package(manifest, &[]);
+
+        let (package_conf, entries) = packaged(&package, "Camera-Daemon");
+        assert_eq!(package_conf.app_name, "camera_daemon");
+        assert_eq!(
+            package_conf.app_name_normalized_from.as_deref(),
+            Some("Camera-Daemon")
+        );
+        assert_eq!(
+            summary(&entries),
+            [("camera_daemon", tar::EntryType::Regular, 0o755, None)]
+        );
+    }
+
+    #[test]
+    fn an_executable_name_other_than_the_app_name_is_linked_from_it() {
+        let manifest = "[package]\nname = \"my-camera-app\"\nversion = \"0.1.0\"\n\n\
+                        [[bin]]\nname = \"acapd\"\npath = \"src/main.rs\"\n\n\
+                        [package.metadata.acap]\nexecutable_name = \"acapd_real\"\n";
+        let (_dir, package) = testing::package(manifest, &[]);
+
+        let (package_conf, entries) = packaged(&package, "acapd");
+        assert_eq!(package_conf.app_name, "acapd");
+        assert_eq!(package_conf.executable_name, "acapd_real");
+        assert_eq!(
+            summary(&entries),
+            [
+                ("acapd_real", tar::EntryType::Regular, 0o755, None),
+                ("acapd", tar::EntryType::Symlink, 0o777, Some("acapd_real")),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn the_executable_and_scripts_are_executable_whatever_their_modes_on_the_host() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (dir, package) = testing::package(
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n",
+            &[
+                ("executable", ""),
+                ("postinstall.sh", "#!/bin/sh\n"),
+                ("preuninstall.sh", "#!/bin/sh\n"),
+            ],
+        );
+        // `packaged()` writes the executable in place, keeping these modes
+        for (file, mode) in [
+            ("executable", 0o600),
+            ("postinstall.sh", 0o644),
+            ("preuninstall.sh", 0o444),
+        ] {
+            let path = dir.path().join(file);
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        let (_, entries) = packaged(&package, "app");
+        assert_eq!(
+            summary(&entries),
+            [
+                ("postinstall.sh", tar::EntryType::Regular, 0o755, None),
+                ("preuninstall.sh", tar::EntryType::Regular, 0o755, None),
+                ("app", tar::EntryType::Regular, 0o755, None),
+            ]
+        );
+    }
+}
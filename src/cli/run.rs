@@ -0,0 +1,119 @@
+use crate::cli::install::Install;
+use crate::cli::Invocation;
+use crate::device::{Device, DeviceError};
+use crate::error::Error;
+use crate::interrupt;
+use crate::output::status;
+use clap::Parser;
+use std::time::Duration;
+
+/// Build an ACAP application, install it on an Axis product, (re)start it, and show its log
+#[derive(Parser)]
+pub struct Run {
+    #[clap(flatten)]
+    install: Install,
+
+    /// Exit once the application is started, instead of showing its log until interrupted
+    #[clap(long)]
+    no_logs: bool,
+
+    /// Stop the application when interrupted with Ctrl-C
+    #[clap(long)]
+    stop_on_exit: bool,
+}
+
+/// How often to check the device for new log entries
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The longest to wait before trying to reach an unresponsive device again
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+impl Run {
+    pub(crate) fn invoke(self, invocation: Invocation) -> Result<(), Error> {
+        let device = self.install.device(&invocation)?;
+        let app_name = self.install.install(&invocation, &device)?;
+
+        // Remember where the log ended, so we only show what this run logs
+        let mut last_line = device
+            .log(&app_name)
+            .ok()
+            .and_then(|log| log.lines().rev().find(|l| !l.is_empty()).map(String::from));
+
+        // Stopping fails if the application isn't running, which is fine
+        match device.control("stop", &app_name) {
+            Ok(()) | Err(DeviceError::Rejected(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+        device.control("start", &app_name)?;
+        status!("cargo-acap: started {}", app_name);
+
+        if self.no_logs {
+            return Ok(());
+        }
+
+        let _handling = interrupt::handle();
+        stream_log(&device, &app_name, &mut last_line)?;
+
+        if self.stop_on_exit {
+            status!("cargo-acap: stopping {}", app_name);
+            device.control("stop", &app_name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Print the log of `app_name` as it grows, starting after `last_line`, until interrupted.
+///
+/// The device is polled, so dropped connections and reboots are survived by trying again.
+fn stream_log(
+    device: &Device,
+    app_name: &str,
+    last_line: &mut Option<String>,
+) -> Result<(), DeviceError> {
+    let mut retry_interval = POLL_INTERVAL;
+    while !interrupt::interrupted() {
+        match device.log(app_name) {
+            Ok(log) => {
+                if retry_interval != POLL_INTERVAL {
+                    status!("cargo-acap: reconnected to {}", device.base_url());
+                    retry_interval = POLL_INTERVAL;
+                }
+
+                let lines: Vec<&str> = log.lines().filter(|l| !l.is_empty()).collect();
+                // If the last line we showed is gone, the log was rotated: show it all
+                let start = last_line
+                    .as_deref()
+                    .and_then(|last| lines.iter().rposition(|l| *l == last))
+                    .map_or(0, |i| i + 1);
+                for line in &lines[start..] {
+                    println!("{}", line);
+                }
+                if let Some(line) = lines.last() {
+                    *last_line = Some(line.to_string());
+                }
+            }
+            Err(DeviceError::Unauthorized) => return Err(DeviceError::Unauthorized),
+            Err(e) => {
+                if retry_interval == POLL_INTERVAL {
+                    eprintln!("cargo-acap: lost connection to the device: {}", e);
+                    eprintln!("cargo-acap: reconnecting (press Ctrl-C to give up)");
+                }
+                retry_interval = (retry_interval * 2).min(MAX_RETRY_INTERVAL);
+            }
+        }
+
+        sleep_unless_interrupted(retry_interval);
+    }
+    Ok(())
+}
+
+/// Sleep for `duration`, waking early if interrupted.
+fn sleep_unless_interrupted(duration: Duration) {
+    let step = Duration::from_millis(100);
+    let mut remaining = duration;
+    while !remaining.is_zero() && !interrupt::interrupted() {
+        let nap = remaining.min(step);
+        std::thread::sleep(nap);
+        remaining -= nap;
+    }
+}
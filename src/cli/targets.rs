@@ -1,6 +1,9 @@
 use crate::cli::Invocation;
-use crate::target::{Target, SOC};
+use crate::error::Error;
+use crate::target::{Architecture, Target, SOC};
 use clap::Parser;
+use serde::Serialize;
+use std::io::Write;
 
 /// List which targets
 #[derive(Parser)]
@@ -8,6 +11,10 @@ pub struct Targets {
     /// Which mode to list
     #[clap(arg_enum)]
     mode: Option<Mode>,
+
+    /// The output format (defaults to the format of the selected mode)
+    #[clap(long, arg_enum, value_name = "FMT")]
+    format: Option<Format>,
 }
 
 #[derive(Debug, Clone, Parser, clap::ValueEnum)]
@@ -24,17 +31,64 @@ pub enum Mode {
     SocTable,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub(super) enum Format {
+    Plain,
+    Table,
+    Json,
+}
+
+/// A target, as listed by `--format json`.
+///
+/// The field names are relied upon by tools, so they must not change.
+#[derive(Debug, Serialize)]
+struct TargetEntry {
+    name: &'static str,
+    rust_triple: &'static str,
+    objcopy: &'static str,
+}
+
+impl From<Target> for TargetEntry {
+    fn from(target: Target) -> Self {
+        TargetEntry {
+            name: target.name(),
+            rust_triple: target.rust_target_triple(),
+            objcopy: target.docker_objcopy_command(),
+        }
+    }
+}
+
+/// An SoC, as listed by `soc_table --format json`.
+///
+/// The field names are relied upon by tools, so they must not change.
+#[derive(Debug, Serialize)]
+struct SocEntry {
+    soc: &'static str,
+    year: u32,
+    architecture: Architecture,
+    /// The target to build for this SoC, or `null` if it is unsupported
+    target: Option<TargetEntry>,
+}
+
 impl Targets {
-    pub fn invoke(self, _invocation: Invocation) {
-        match self.mode.unwrap_or(Mode::Plain) {
-            Mode::Plain => {
+    pub fn invoke(self, _invocation: Invocation) -> Result<(), Error> {
+        let mode = self.mode.unwrap_or(Mode::Plain);
+        let format = self.format.unwrap_or(match mode {
+            Mode::Plain => Format::Plain,
+            Mode::Table | Mode::SocTable => Format::Table,
+        });
+
+        match (mode, format) {
+            (Mode::SocTable, format) => Self::socs(format),
+            (_, Format::Plain) => {
                 for target in Target::all() {
                     println!("{}", target.name());
                 }
             }
-            Mode::Table => {
+            (_, Format::Table) => {
                 print_table(
-                    &["`cargo acap` `target`", "Rust `--target`"],
+                    std::io::stdout(),
+                    ["`cargo acap` `target`", "Rust `--target`"],
                     Target::all().iter().map(|t| {
                         vec![
                             format!("`{}`", t.name()),
@@ -43,12 +97,34 @@ impl Targets {
                     }),
                 );
             }
-            Mode::SocTable => {
-                let mut socs: Vec<&SOC> = SOC::all().iter().collect();
-                socs.sort_by_key(|soc| (soc.year(), soc.display_name()));
+            (_, Format::Json) => {
+                let entries: Vec<TargetEntry> = Target::all().iter().map(|&t| t.into()).collect();
+                print_json(&entries);
+            }
+        }
+        Ok(())
+    }
+
+    /// List every SoC and its target in `format`.
+    fn socs(format: Format) {
+        let mut socs: Vec<&SOC> = SOC::all().iter().collect();
+        socs.sort_by_key(|soc| (soc.year(), soc.display_name()));
 
+        match format {
+            Format::Plain => {
+                for soc in socs {
+                    let target = soc.architecture().ok();
+                    println!(
+                        "{}\t{}",
+                        soc.display_name(),
+                        target.map_or("(unsupported)", |t| t.name())
+                    );
+                }
+            }
+            Format::Table => {
                 print_table(
-                    &["SOC", "Year", "`cargo acap` `target`", "Rust `--target`"],
+                    std::io::stdout(),
+                    ["SOC", "Year", "`cargo acap` `target`", "Rust `--target`"],
                     socs.into_iter().map(|soc| {
                         let target = soc.architecture().ok();
                         vec![
@@ -64,12 +140,31 @@ impl Targets {
                     }),
                 );
             }
+            Format::Json => {
+                let entries: Vec<SocEntry> = socs
+                    .into_iter()
+                    .map(|soc| SocEntry {
+                        soc: soc.display_name(),
+                        year: soc.year(),
+                        architecture: soc.cpu_architecture(),
+                        target: soc.architecture().ok().map(TargetEntry::from),
+                    })
+                    .collect();
+                print_json(&entries);
+            }
         }
     }
 }
 
-fn print_table<H, HS, D, R, RS>(headers: H, data: D)
+pub(super) fn print_json<T: Serialize>(value: &T) {
+    let json = serde_json::to_string_pretty(value).expect("error serializing JSON");
+    println!("{}", json);
+}
+
+/// Print a Markdown-style table of `data` to `out`.
+pub(super) fn print_table<W, H, HS, D, R, RS>(mut out: W, headers: H, data: D)
 where
+    W: Write,
     H: IntoIterator<Item = HS>,
     HS: AsRef<str>,
     D: IntoIterator<Item = R>,
@@ -97,7 +192,11 @@ where
         })
         .collect();
 
-    fn print_row<R: IntoIterator<Item = T>, T: AsRef<str>>(row: R, column_lengths: &[usize]) {
+    fn print_row<R: IntoIterator<Item = T>, T: AsRef<str>>(
+        out: &mut dyn Write,
+        row: R,
+        column_lengths: &[usize],
+    ) {
         let total_length: usize = column_lengths.iter().map(|l| *l + 2).sum();
         let mut output = String::with_capacity(total_length + 2 + 1);
         output.push('|');
@@ -112,11 +211,12 @@ where
             output.push_str(" |");
         }
         output.push('\n');
-        print!("{}", output);
+        let _ = out.write_all(output.as_bytes());
     }
 
-    print_row(headers, &column_lengths);
+    print_row(&mut out, headers, &column_lengths);
     print_row(
+        &mut out,
         column_lengths.iter().map(|len| {
             let len = *len;
             let mut dashes = String::with_capacity(len);
@@ -128,6 +228,6 @@ where
         &column_lengths,
     );
     for row in data {
-        print_row(row, &column_lengths);
+        print_row(&mut out, row, &column_lengths);
     }
 }
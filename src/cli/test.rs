@@ -0,0 +1,127 @@
+use crate::cargo_config::CargoAcapMetadata;
+use crate::cli::build::{cargo_command, Build, BuildOptions};
+use crate::cli::{BuildEnvironment, Invocation};
+use crate::error::Error;
+use crate::output::status;
+use crate::target::Target;
+use clap::Parser;
+
+/// Build an ACAP application's tests and run them for each target under QEMU user emulation
+#[derive(Parser)]
+pub struct Test {
+    /// Which target(s) to test, by name, triple, or SoC (defaults to `targets` in
+    /// `[package.metadata.acap]`, or all)
+    #[clap(short, long, alias = "target")]
+    targets: Vec<Target>,
+
+    /// Target(s) not to test, even if otherwise selected
+    #[clap(long, value_name = "TARGET")]
+    exclude_target: Vec<Target>,
+
+    #[clap(flatten)]
+    options: BuildOptions,
+
+    /// Run only the tests whose names contain this
+    #[clap(value_name = "TESTNAME")]
+    filter: Option<String>,
+
+    /// Arguments for the test binaries, e.g. `-- --nocapture`
+    #[clap(last = true, value_name = "ARGS")]
+    args: Vec<String>,
+}
+
+impl Test {
+    pub(crate) fn invoke(self, invocation: Invocation) -> Result<(), Error> {
+        let targets = Build::for_targets(self.targets.clone(), self.options.clone())
+            .excluding(self.exclude_target.clone())
+            .targets(&invocation)?;
+
+        let metadata = CargoAcapMetadata::for_package(&invocation.cargo_package)?;
+        let environment = self.options.cargo_environment(&invocation, &metadata)?;
+
+        let mut tested = 0;
+        for &target in &targets {
+            let environment =
+                self.options
+                    .target_environment(&invocation, &metadata, &environment, target)?;
+            invocation.check_image_rustc(&environment, false)?;
+            let runner = match runner(&environment, target) {
+                Ok(runner) => runner,
+                Err(reason) => {
+                    eprintln!(
+                        "cargo-acap: warning: skipping target {}: {}",
+                        target.name(),
+                        reason
+                    );
+                    continue;
+                }
+            };
+
+            status!("cargo-acap: testing target {}", target.name());
+            self.cargo_test(&invocation, environment, target, runner)?;
+            tested += 1;
+        }
+
+        if tested == 0 {
+            return Err(Error::Usage(
+                "none of the selected targets can be tested: see the warnings above".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Run `cargo test` for `target` in `environment`, with each test executable run by `runner`.
+    fn cargo_test(
+        &self,
+        invocation: &Invocation,
+        environment: BuildEnvironment,
+        target: Target,
+        runner: &str,
+    ) -> Result<(), Error> {
+        let triple = target.rust_target_triple();
+        let runner_key = format!(
+            "CARGO_TARGET_{}_RUNNER",
+            triple.to_ascii_uppercase().replace('-', "_")
+        );
+        let environment = environment.with_env(&runner_key, runner.to_string());
+
+        let mut cargo = cargo_command(
+            &environment,
+            "test",
+            target,
+            self.options.profile(),
+            self.options.cargo_args(),
+            invocation.manifest_path(),
+        )?;
+        if let Some(filter) = &self.filter {
+            cargo.arg(filter);
+        }
+        if !self.args.is_empty() {
+            cargo.arg("--");
+            cargo.args(&self.args);
+        }
+
+        environment.run(cargo, None)
+    }
+}
+
+/// The runner for `target`'s test executables, or why there is none in `environment`.
+fn runner(environment: &BuildEnvironment, target: Target) -> Result<&'static str, String> {
+    let runner = target
+        .qemu_runner()
+        .ok_or_else(|| "QEMU can't emulate it with the toolchain's libraries".to_string())?;
+    let qemu = runner.split(' ').next().unwrap();
+
+    let mut command = environment.command("sh").map_err(|e| e.to_string())?;
+    command.args(["-c", &format!("command -v {}", qemu)]);
+    match environment.output(command, None) {
+        Ok(_) => Ok(runner),
+        Err(_) if environment.no_docker => {
+            Err(format!("`{}` was not found: install qemu-user", qemu))
+        }
+        Err(_) => Err(format!(
+            "the Docker image has no `{}`: use an image built with qemu-user",
+            qemu
+        )),
+    }
+}
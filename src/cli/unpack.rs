@@ -0,0 +1,111 @@
+use crate::cli::targets::{print_json, print_table, Format};
+use crate::eap::{entry_name, read_eap};
+use crate::error::{Error, IoResultExt};
+use crate::output::status;
+use crate::shell_includes;
+use clap::Parser;
+use serde::ser::{Serialize, Serializer};
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Extract an `.eap`, or show its package.conf
+#[derive(Parser)]
+pub struct Unpack {
+    /// The package to unpack
+    eap: PathBuf,
+
+    /// The directory into which to extract the package
+    #[clap(long, value_name = "DIR")]
+    out: Option<PathBuf>,
+
+    /// Print the package's package.conf, parsed
+    #[clap(long)]
+    show_conf: bool,
+
+    /// The format in which to print package.conf
+    #[clap(long, arg_enum, value_name = "FMT", default_value = "table")]
+    format: Format,
+}
+
+/// package.conf assignments, which serialize as a JSON object in their original order.
+struct Assignments<'a>(&'a [(String, String)]);
+
+impl Serialize for Assignments<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+impl Unpack {
+    pub(crate) fn invoke(self) -> Result<(), Error> {
+        if self.out.is_none() && !self.show_conf {
+            return Err(Error::Usage(
+                "nothing to do: use --out to extract the package, or --show-conf".to_string(),
+            ));
+        }
+        if let Some(out) = &self.out {
+            std::fs::create_dir_all(out).with_path(out)?;
+        }
+
+        let mut package_conf = None;
+        read_eap(&self.eap, |entry| {
+            let name = entry_name(entry)?;
+            if name == "package.conf" && self.show_conf {
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents)?;
+                package_conf = Some(contents);
+            }
+            if let Some(out) = &self.out {
+                // `unpack_in` refuses to write outside `out`, reporting `false`
+                if !entry.unpack_in(out)? {
+                    eprintln!(
+                        "cargo-acap: warning: skipping `{}`, which is outside the package",
+                        name
+                    );
+                }
+            }
+            Ok(())
+        })?;
+
+        if let Some(out) = &self.out {
+            status!(
+                "cargo-acap: extracted {} into {}",
+                self.eap.display(),
+                out.display()
+            );
+        }
+        if self.show_conf {
+            let package_conf = package_conf.ok_or_else(|| {
+                Error::Package(format!("{} has no package.conf", self.eap.display()))
+            })?;
+            let assignments = shell_includes::parse(&package_conf).map_err(|e| {
+                Error::Package(format!(
+                    "the package.conf in {} can't be parsed: {}",
+                    self.eap.display(),
+                    e
+                ))
+            })?;
+            self.print(&assignments);
+        }
+        Ok(())
+    }
+
+    /// Print `assignments` from package.conf in the selected format.
+    fn print(&self, assignments: &[(String, String)]) {
+        match self.format {
+            Format::Plain => {
+                for (key, value) in assignments {
+                    println!("{}\t{}", key, value);
+                }
+            }
+            Format::Table => print_table(
+                std::io::stdout(),
+                ["Key", "Value"],
+                assignments
+                    .iter()
+                    .map(|(key, value)| vec![key.clone(), value.replace('\n', "\\n")]),
+            ),
+            Format::Json => print_json(&Assignments(assignments)),
+        }
+    }
+}
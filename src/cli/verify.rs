@@ -0,0 +1,228 @@
+use crate::eap::{entry_name, read_eap};
+use crate::error::Error;
+use crate::output::status;
+use crate::shell_includes;
+use crate::target::Target;
+use clap::Parser;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Check an `.eap` for mistakes which would otherwise only be found when installing it
+#[derive(Parser)]
+pub struct Verify {
+    /// The package to check
+    eap: PathBuf,
+
+    /// The target the package must be for (defaults to its APPTYPE)
+    #[clap(short, long)]
+    target: Option<Target>,
+}
+
+impl Verify {
+    pub(crate) fn invoke(self) -> Result<(), Error> {
+        verify(&self.eap, self.target)?;
+        status!("cargo-acap: {} looks valid", self.eap.display());
+        Ok(())
+    }
+}
+
+/// A file or directory in an `.eap`.
+struct Entry {
+    is_file: bool,
+    executable: bool,
+    /// Where the entry points, if it's a symbolic link
+    link: Option<String>,
+    /// The start of the file, enough for an ELF header
+    head: Vec<u8>,
+}
+
+/// How much of each file to keep, which covers the 64-bit ELF header
+const HEAD_LEN: u64 = 64;
+
+/// Check that `eap` is complete and consistent, and that its executable was built for `target`,
+/// or the target its `package.conf` names.
+pub(crate) fn verify(eap: &Path, target: Option<Target>) -> Result<(), Error> {
+    let (entries, package_conf) = read(eap)?;
+    let problems = match package_conf {
+        Some(package_conf) => problems(&entries, &package_conf, target),
+        None => vec!["package.conf is missing".to_string()],
+    };
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        let mut message = format!("{} is invalid:", eap.display());
+        for problem in problems {
+            message += "\n  * ";
+            message += &problem;
+        }
+        Err(Error::Package(message))
+    }
+}
+
+/// Read the entries of `eap`, along with the contents of its `package.conf`, if any.
+fn read(eap: &Path) -> Result<(HashMap<String, Entry>, Option<String>), Error> {
+    let mut entries = HashMap::new();
+    let mut package_conf = None;
+    read_eap(eap, |entry| {
+        let name = entry_name(entry)?;
+        let header = entry.header();
+        let is_file = header.entry_type().is_file();
+        let executable = header.mode()? & 0o111 != 0;
+        let link = match header.entry_type() {
+            tar::EntryType::Symlink => entry
+                .link_name()?
+                .map(|link| link.to_string_lossy().into_owned()),
+            _ => None,
+        };
+
+        if name == "package.conf" {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            package_conf = Some(contents);
+        }
+        let mut head = Vec::new();
+        if is_file {
+            entry.take(HEAD_LEN).read_to_end(&mut head)?;
+        }
+
+        entries.insert(
+            name,
+            Entry {
+                is_file,
+                executable,
+                link,
+                head,
+            },
+        );
+        Ok(())
+    })?;
+    Ok((entries, package_conf))
+}
+
+/// Everything wrong with a package containing `entries` described by `package_conf`.
+fn problems(
+    entries: &HashMap<String, Entry>,
+    package_conf: &str,
+    target: Option<Target>,
+) -> Vec<String> {
+    let package_conf: HashMap<String, String> = match shell_includes::parse(package_conf) {
+        Ok(assignments) => assignments.into_iter().collect(),
+        Err(e) => return vec![format!("package.conf can't be parsed: {}", e)],
+    };
+    let value = |key: &str| package_conf.get(key).filter(|v| !v.is_empty());
+    let mut problems = Vec::new();
+
+    let app_type = value("APPTYPE");
+    let target = match (target, app_type) {
+        (Some(target), Some(app_type)) if app_type != target.name() => {
+            problems.push(format!(
+                "APPTYPE is {:?}, but the package should be for target {}",
+                app_type, target
+            ));
+            Some(target)
+        }
+        (Some(target), _) => Some(target),
+        (None, Some(app_type)) => match app_type.parse::<Target>() {
+            Ok(target) => Some(target),
+            Err(_) => {
+                problems.push(format!("APPTYPE {:?} is not a known target", app_type));
+                None
+            }
+        },
+        (None, None) => {
+            problems.push("package.conf has no APPTYPE".to_string());
+            None
+        }
+    };
+
+    match value("APPNAME") {
+        // APPNAME may be a symlink to the executable, within the package
+        Some(app_name) => match entries
+            .get(app_name.as_str())
+            .and_then(|entry| match &entry.link {
+                Some(link) => entries.get(link.trim_start_matches("./")),
+                None => Some(entry),
+            }) {
+            Some(entry) if entry.is_file => {
+                if !entry.executable {
+                    problems.push(format!("the executable `{}` is not executable", app_name));
+                }
+                if let Some(problem) = target.and_then(|t| elf_problem(&entry.head, t)) {
+                    problems.push(format!("the executable `{}` {}", app_name, problem));
+                }
+            }
+            _ => problems.push(format!(
+                "the executable `{}` named by APPNAME is missing",
+                app_name
+            )),
+        },
+        None => problems.push("package.conf has no APPNAME".to_string()),
+    }
+
+    let mut referenced: Vec<(&str, String)> = Vec::new();
+    if let Some(other_files) = value("OTHERFILES") {
+        referenced.extend(
+            other_files
+                .split_whitespace()
+                .map(|name| ("OTHERFILES", name.to_string())),
+        );
+    }
+    if let Some(script) = value("POSTINSTALLSCRIPT") {
+        referenced.push(("POSTINSTALLSCRIPT", script.clone()));
+    }
+    if let Some(script) = value("PREUNINSTALLSCRIPT") {
+        referenced.push(("PREUNINSTALLSCRIPT", script.clone()));
+    }
+    if let Some(page) = value("SETTINGSPAGEFILE") {
+        referenced.push(("SETTINGSPAGEFILE", format!("html/{}", page)));
+    }
+    if let Some(paths) = value("HTTPCGIPATHS") {
+        referenced.push(("HTTPCGIPATHS", paths.clone()));
+    }
+    for (key, name) in referenced {
+        if !entries.contains_key(name.trim_start_matches("./")) {
+            problems.push(format!("`{}` named by {} is missing", name, key));
+        }
+    }
+
+    problems
+}
+
+/// What's wrong with the ELF file starting with `head` as an executable for `target`, if anything.
+fn elf_problem(head: &[u8], target: Target) -> Option<String> {
+    if head.len() < 20 || &head[..4] != b"\x7fELF" {
+        return Some("is not an ELF file".to_string());
+    }
+
+    let is_64_bit = head[4] == 2;
+    let machine_bytes = [head[18], head[19]];
+    let machine = match head[5] {
+        2 => u16::from_be_bytes(machine_bytes),
+        _ => u16::from_le_bytes(machine_bytes),
+    };
+    if machine == target.elf_machine() && is_64_bit == target.elf_is_64_bit() {
+        return None;
+    }
+
+    let architecture = match machine {
+        3 => "x86",
+        8 => "MIPS",
+        40 => "ARM",
+        62 => "x86-64",
+        183 => "AArch64",
+        _ => {
+            return Some(format!(
+                "is for ELF machine {}, not target {}",
+                machine, target
+            ))
+        }
+    };
+    Some(format!(
+        "is a {}-bit {} executable, not one for target {}: was a stale or host build packaged?",
+        if is_64_bit { 64 } else { 32 },
+        architecture,
+        target
+    ))
+}
@@ -0,0 +1,318 @@
+use crate::target::{Architecture, Target};
+use curl::easy::{Auth, Easy, Form};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+
+/// An Axis product, controlled over its VAPIX HTTP API.
+#[derive(Debug, Clone)]
+pub struct Device {
+    base_url: String,
+    user: String,
+    pass: String,
+    insecure: bool,
+    verbose: u8,
+}
+
+impl Device {
+    /// Connect to the device at `host`, which may be a hostname, an address, or a URL.
+    ///
+    /// If `insecure` is set, TLS certificates are not verified, e.g. because the device uses a
+    /// self-signed certificate.
+    pub fn new(host: &str, user: &str, pass: &str, insecure: bool, verbose: u8) -> Self {
+        let base_url = if host.contains("://") {
+            host.trim_end_matches('/').to_string()
+        } else {
+            format!("http://{}", host)
+        };
+
+        Device {
+            base_url,
+            user: user.to_string(),
+            pass: pass.to_string(),
+            insecure,
+            verbose,
+        }
+    }
+
+    /// The URL of the device, e.g. `http://192.168.0.90`.
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Construct a request for `path`, e.g. `/axis-cgi/param.cgi?action=list`.
+    fn easy(&self, path: &str) -> Result<Easy, DeviceError> {
+        let url = format!("{}{}", self.base_url, path);
+        if self.verbose > 1 {
+            eprintln!("+ {}", url);
+        }
+
+        let mut easy = Easy::new();
+        easy.url(&url)?;
+        easy.username(&self.user)?;
+        easy.password(&self.pass)?;
+        let mut auth = Auth::new();
+        auth.digest(true).basic(true);
+        easy.http_auth(&auth)?;
+        easy.connect_timeout(Duration::from_secs(10))?;
+        easy.timeout(Duration::from_secs(60))?;
+        if self.insecure {
+            easy.ssl_verify_peer(false)?;
+            easy.ssl_verify_host(false)?;
+        }
+        Ok(easy)
+    }
+
+    /// Perform `easy`, returning the response body if the device responded successfully.
+    fn perform(&self, mut easy: Easy) -> Result<String, DeviceError> {
+        let mut body = Vec::new();
+        {
+            let mut transfer = easy.transfer();
+            transfer.write_function(|data| {
+                body.extend_from_slice(data);
+                Ok(data.len())
+            })?;
+            transfer.perform()?;
+        }
+
+        let body = String::from_utf8_lossy(&body).into_owned();
+        match easy.response_code()? {
+            200..=299 => Ok(body),
+            401 | 403 => Err(DeviceError::Unauthorized),
+            code => Err(DeviceError::Status(code, body)),
+        }
+    }
+
+    /// GET `path`, returning the response body.
+    pub fn get(&self, path: &str) -> Result<String, DeviceError> {
+        let easy = self.easy(path)?;
+        self.perform(easy)
+    }
+
+    /// The value of the parameter `name`, e.g. `root.Properties.System.Architecture`.
+    pub fn parameter(&self, name: &str) -> Result<String, DeviceError> {
+        let body = self.get(&format!("/axis-cgi/param.cgi?action=list&group={}", name))?;
+        body.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+            .map(|value| value.trim().to_string())
+            .ok_or(DeviceError::Response(body))
+    }
+
+    /// Every parameter in `group`, e.g. `root.Properties.System`, by name.
+    pub fn parameters(&self, group: &str) -> Result<Vec<(String, String)>, DeviceError> {
+        let body = self.get(&format!("/axis-cgi/param.cgi?action=list&group={}", group))?;
+        parse_parameters(body)
+    }
+
+    /// The target whose packages the device can run.
+    pub fn target(&self) -> Result<Target, DeviceError> {
+        let architecture = self.parameter("root.Properties.System.Architecture")?;
+        target_for_architecture(&architecture)
+    }
+
+    /// Upload and install the package at `eap`.
+    pub fn install(&self, eap: &Path) -> Result<(), DeviceError> {
+        let mut form = Form::new();
+        form.part("packfil").file(eap).add()?;
+
+        let mut easy = self.easy("/axis-cgi/applications/upload.cgi")?;
+        easy.httppost(form)?;
+        // Installation can take a while on slower devices
+        easy.timeout(Duration::from_secs(300))?;
+
+        let body = self.perform(easy)?;
+        if body.trim() == "OK" {
+            Ok(())
+        } else {
+            Err(DeviceError::Rejected(body.trim().to_string()))
+        }
+    }
+
+    /// Upload the license key at `key` for the installed application `app_name`, which must use
+    /// Axis licensing.
+    pub fn upload_license(&self, app_name: &str, key: &Path) -> Result<(), DeviceError> {
+        let mut form = Form::new();
+        form.part("fileData").file(key).add()?;
+
+        let mut easy = self.easy(&format!(
+            "/axis-cgi/applications/license.cgi?action=uploadlicensekey&package={}",
+            app_name
+        ))?;
+        easy.httppost(form)?;
+
+        let body = self.perform(easy)?;
+        if body.trim() == "OK" {
+            Ok(())
+        } else {
+            Err(DeviceError::Rejected(body.trim().to_string()))
+        }
+    }
+
+    /// Perform `action` on the installed application `app_name`, e.g. `start` or `stop`.
+    pub fn control(&self, action: &str, app_name: &str) -> Result<(), DeviceError> {
+        let body = self.get(&format!(
+            "/axis-cgi/applications/control.cgi?action={}&package={}",
+            action, app_name
+        ))?;
+        if body.trim() == "OK" {
+            Ok(())
+        } else {
+            Err(DeviceError::Rejected(body.trim().to_string()))
+        }
+    }
+
+    /// The applications installed on the device.
+    pub fn applications(&self) -> Result<Vec<Application>, DeviceError> {
+        let body = self.get("/axis-cgi/applications/list.cgi")?;
+        if !body.contains("<reply result=\"ok\"") {
+            return Err(DeviceError::Response(body));
+        }
+
+        // Each application is an empty element, e.g. `<application Name="foo" Status="Running" />`
+        Ok(body
+            .split("<application ")
+            .skip(1)
+            .filter_map(|element| {
+                let element = format!(" {}", &element[..element.find('>')?]);
+                Some(Application {
+                    name: xml_attribute(&element, "Name")?,
+                    status: xml_attribute(&element, "Status").unwrap_or_default(),
+                })
+            })
+            .collect())
+    }
+
+    /// The installed application `app_name`, if any.
+    pub fn application(&self, app_name: &str) -> Result<Option<Application>, DeviceError> {
+        Ok(self
+            .applications()?
+            .into_iter()
+            .find(|app| app.name == app_name))
+    }
+
+    /// The system log entries of the installed application `app_name`.
+    pub fn log(&self, app_name: &str) -> Result<String, DeviceError> {
+        self.get(&format!(
+            "/axis-cgi/admin/systemlog.cgi?appname={}",
+            app_name
+        ))
+    }
+}
+
+/// An application installed on a device.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Application {
+    pub name: String,
+    /// The application's state, e.g. `Running` or `Stopped`
+    pub status: String,
+}
+
+impl Application {
+    pub fn is_running(&self) -> bool {
+        self.status == "Running"
+    }
+}
+
+/// The value of the attribute `name` within the XML element `element`.
+fn xml_attribute(element: &str, name: &str) -> Option<String> {
+    let start = element.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = element[start..].find('"')?;
+    Some(
+        element[start..start + len]
+            .replace("&quot;", "\"")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&"),
+    )
+}
+
+/// The parameters listed by `param.cgi?action=list` in `body`, as `name=value` lines.
+pub fn parse_parameters(body: String) -> Result<Vec<(String, String)>, DeviceError> {
+    let parameters: Vec<(String, String)> = body
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+        .collect();
+    if parameters.is_empty() {
+        return Err(DeviceError::Response(body));
+    }
+    Ok(parameters)
+}
+
+/// The target for a device reporting `architecture`.
+pub fn target_for_architecture(architecture: &str) -> Result<Target, DeviceError> {
+    Architecture::from_vapix(architecture)
+        .and_then(|a| Target::try_from(a).ok())
+        .ok_or_else(|| DeviceError::UnsupportedArchitecture(architecture.to_string()))
+}
+
+/// A problem communicating with a device.
+#[derive(Debug)]
+pub enum DeviceError {
+    /// The request could not be made
+    Http(curl::Error),
+    /// The device did not accept the credentials
+    Unauthorized,
+    /// The device responded with an unexpected HTTP status
+    Status(u32, String),
+    /// The device responded with something other than what was expected
+    Response(String),
+    /// The device reported an architecture for which `cargo acap` can't build
+    UnsupportedArchitecture(String),
+    /// The device refused the request, e.g. because a package was invalid
+    Rejected(String),
+    /// A package could not be prepared for uploading
+    Form(curl::FormError),
+    /// The local network could not be searched for devices
+    Discovery(std::io::Error),
+    /// A command run on the device over SSH failed, as explained
+    Ssh(String),
+}
+
+impl From<curl::Error> for DeviceError {
+    fn from(e: curl::Error) -> Self {
+        DeviceError::Http(e)
+    }
+}
+
+impl From<curl::FormError> for DeviceError {
+    fn from(e: curl::FormError) -> Self {
+        DeviceError::Form(e)
+    }
+}
+
+impl Error for DeviceError {}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeviceError::Http(e) => write!(f, "error communicating with the device: {}", e),
+            DeviceError::Unauthorized => {
+                f.write_str("the device rejected the credentials: check --user and --pass")
+            }
+            DeviceError::Status(code, body) => {
+                write!(
+                    f,
+                    "the device responded with HTTP {}: {}",
+                    code,
+                    body.trim()
+                )
+            }
+            DeviceError::Response(body) => {
+                write!(f, "unexpected response from the device: {}", body.trim())
+            }
+            DeviceError::UnsupportedArchitecture(architecture) => write!(
+                f,
+                "the device's architecture `{}` is not supported by `cargo acap`",
+                architecture
+            ),
+            DeviceError::Rejected(response) => write!(f, "the device refused: {}", response),
+            DeviceError::Form(e) => write!(f, "error preparing the upload: {}", e),
+            DeviceError::Discovery(e) => {
+                write!(f, "error searching the local network for devices: {}", e)
+            }
+            DeviceError::Ssh(message) => f.write_str(message),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::error::{Error, IoResultExt};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// A device named in a `devices.toml`, selected by `--device NAME`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceProfile {
+    pub host: Option<String>,
+    pub user: Option<String>,
+    /// The password, which should only be stored in a file no one else can read
+    pub pass: Option<String>,
+    /// An environment variable containing the password, used in preference to `pass`
+    pub pass_env: Option<String>,
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+impl DeviceProfile {
+    /// The password from `pass_env` if it's set, or else `pass`.
+    pub fn password(&self) -> Option<String> {
+        self.pass_env
+            .as_deref()
+            .and_then(|name| std::env::var(name).ok())
+            .or_else(|| self.pass.clone())
+    }
+}
+
+/// The contents of a `devices.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DevicesDotToml {
+    #[serde(default)]
+    device: BTreeMap<String, DeviceProfile>,
+}
+
+/// The user's `devices.toml`, e.g. `~/.config/cargo-acap/devices.toml`.
+fn user_path() -> Option<PathBuf> {
+    let config_dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    config_dir.map(|dir| dir.join("cargo-acap").join("devices.toml"))
+}
+
+/// The project's `.cargo-acap/devices.toml`, in the current directory or the nearest ancestor.
+fn project_path() -> Option<PathBuf> {
+    let current_dir = std::env::current_dir().ok()?;
+    current_dir
+        .ancestors()
+        .map(|dir| dir.join(".cargo-acap").join("devices.toml"))
+        .find(|path| path.is_file())
+}
+
+/// The devices in `path`, or none if it doesn't exist.
+fn read(path: &Path) -> Result<BTreeMap<String, DeviceProfile>, Error> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e).with_path(path),
+    };
+    let devices: DevicesDotToml = toml::from_str(&contents)
+        .map_err(|e| Error::Usage(format!("invalid {}: {}", path.display(), e)))?;
+
+    if devices.device.values().any(|device| device.pass.is_some()) {
+        warn_if_readable_by_others(path);
+    }
+    Ok(devices.device)
+}
+
+/// The device `name`, from the project's `devices.toml` or else the user's.
+pub fn device(name: &str) -> Result<DeviceProfile, Error> {
+    let paths: Vec<PathBuf> = user_path().into_iter().chain(project_path()).collect();
+    let mut devices = BTreeMap::new();
+    for path in &paths {
+        devices.append(&mut read(path)?);
+    }
+
+    if let Some(device) = devices.remove(name) {
+        return Ok(device);
+    }
+    let suggestion = devices
+        .keys()
+        .map(|candidate| (strsim::jaro_winkler(name, candidate), candidate))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, candidate)| format!(" (did you mean `{}`?)", candidate))
+        .unwrap_or_default();
+    let searched: Vec<String> = user_path()
+        .into_iter()
+        .map(|path| path.display().to_string())
+        .chain(Some(".cargo-acap/devices.toml".to_string()))
+        .collect();
+    Err(Error::Usage(format!(
+        "no device named `{}`{}: define it as [device.{}] in {}",
+        name,
+        suggestion,
+        name,
+        searched.join(" or ")
+    )))
+}
+
+/// Warn if the file at `path`, which contains passwords, can be read by users besides its owner.
+#[cfg(unix)]
+pub fn warn_if_readable_by_others(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        if metadata.permissions().mode() & 0o077 != 0 {
+            eprintln!(
+                "cargo-acap: warning: {} contains passwords, but others can access it: run \
+                 `chmod 600 {}`",
+                path.display(),
+                path.display()
+            );
+        }
+    }
+}
+
+/// Warn if the file at `path`, which contains passwords, can be read by users besides its owner.
+#[cfg(not(unix))]
+pub fn warn_if_readable_by_others(_path: &Path) {}
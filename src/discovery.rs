@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// An Axis product which announced itself on the local network.
+#[derive(Debug, Clone, Default)]
+pub struct Discovered {
+    /// The product's model, e.g. `M3045-V`
+    pub model: Option<String>,
+    /// The product's serial number, which is also its MAC address
+    pub serial: Option<String>,
+}
+
+/// The service type Axis products register with mDNS
+const MDNS_SERVICE: &str = "_axis-video._tcp.local";
+const MDNS_ADDRESS: (Ipv4Addr, u16) = (Ipv4Addr::new(224, 0, 0, 251), 5353);
+const WS_DISCOVERY_ADDRESS: (Ipv4Addr, u16) = (Ipv4Addr::new(239, 255, 255, 250), 3702);
+
+/// Browse for Axis products by mDNS and WS-Discovery for `timeout`, returning them by address.
+pub fn discover(timeout: Duration) -> io::Result<BTreeMap<IpAddr, Discovered>> {
+    let deadline = Instant::now() + timeout;
+    let (mdns, ws_discovery) = std::thread::scope(|scope| {
+        let mdns = scope.spawn(|| browse(mdns_query(), MDNS_ADDRESS, deadline, parse_mdns));
+        let ws_discovery = scope.spawn(|| {
+            browse(
+                ws_discovery_probe().into_bytes(),
+                WS_DISCOVERY_ADDRESS,
+                deadline,
+                parse_ws_discovery,
+            )
+        });
+        (
+            mdns.join().expect("mDNS thread panicked"),
+            ws_discovery.join().expect("WS-Discovery thread panicked"),
+        )
+    });
+
+    let mut devices: BTreeMap<IpAddr, Discovered> = BTreeMap::new();
+    for (address, found) in mdns?.into_iter().chain(ws_discovery?) {
+        let device = devices.entry(address).or_default();
+        device.model = device.model.take().or(found.model);
+        device.serial = device.serial.take().or(found.serial);
+    }
+    Ok(devices)
+}
+
+/// Send `query` to the multicast group at `group`, and `parse` each response until `deadline`.
+///
+/// Sending from an ephemeral port asks responders to reply to it directly, so there's no need to
+/// join the group.
+fn browse(
+    query: Vec<u8>,
+    group: (Ipv4Addr, u16),
+    deadline: Instant,
+    parse: fn(&[u8]) -> Option<Discovered>,
+) -> io::Result<Vec<(IpAddr, Discovered)>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.send_to(&query, SocketAddr::from(group))?;
+
+    let mut found = Vec::new();
+    let mut buffer = [0; 9000];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(found);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        match socket.recv_from(&mut buffer) {
+            Ok((len, from)) => {
+                if let Some(device) = parse(&buffer[..len]) {
+                    found.push((from.ip(), device));
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(found)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// An mDNS query for instances of `MDNS_SERVICE`.
+fn mdns_query() -> Vec<u8> {
+    // ID 0, standard query, one question
+    let mut query = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in MDNS_SERVICE.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    // The root label, then QTYPE PTR, QCLASS IN
+    query.extend_from_slice(&[0, 0, 12, 0, 1]);
+    query
+}
+
+/// The Axis product described by the mDNS response `message`, if it describes one.
+///
+/// Axis products name their instances like `AXIS M3045-V - ACCC8E123456`, and publish their serial
+/// number in a `macaddress` TXT record.
+fn parse_mdns(message: &[u8]) -> Option<Discovered> {
+    let u16_at = |pos: usize| {
+        Some(u16::from_be_bytes([
+            *message.get(pos)?,
+            *message.get(pos + 1)?,
+        ]))
+    };
+    if u16_at(2)? & 0x8000 == 0 {
+        // A query, not a response
+        return None;
+    }
+
+    let questions = u16_at(4)?;
+    let records = u16_at(6)? as usize + u16_at(8)? as usize + u16_at(10)? as usize;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(message, pos)?.1 + 4;
+    }
+
+    let mut instance = None;
+    let mut txt = Vec::new();
+    for _ in 0..records {
+        let (owner, next) = read_name(message, pos)?;
+        let record_type = u16_at(next)?;
+        let data_start = next + 10;
+        let data = message.get(data_start..data_start + u16_at(next + 8)? as usize)?;
+        pos = data_start + data.len();
+
+        match record_type {
+            12 if owner.eq_ignore_ascii_case(MDNS_SERVICE) => {
+                let (name, _) = read_name(message, data_start)?;
+                instance = name.split('.').next().map(str::to_string);
+            }
+            16 if owner
+                .to_ascii_lowercase()
+                .ends_with(&format!(".{}", MDNS_SERVICE)) =>
+            {
+                let mut strings = data;
+                while let Some((&len, rest)) = strings.split_first() {
+                    let (string, rest) = rest.split_at((len as usize).min(rest.len()));
+                    if let Some((key, value)) = String::from_utf8_lossy(string).split_once('=') {
+                        txt.push((key.to_ascii_lowercase(), value.to_string()));
+                    }
+                    strings = rest;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let instance = instance?;
+    let (model, suffix) = match instance.rsplit_once(" - ") {
+        Some((model, suffix)) => (model, Some(suffix)),
+        None => (instance.as_str(), None),
+    };
+    let txt_value = |key: &str| {
+        txt.iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.to_string())
+    };
+    Some(Discovered {
+        model: txt_value("model").or_else(|| Some(strip_brand(model).to_string())),
+        serial: txt_value("macaddress").or_else(|| suffix.map(str::to_string)),
+    })
+}
+
+/// Read the possibly compressed DNS name at `pos` in `message`, returning it in dotted form and the
+/// position after it.
+fn read_name(message: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bound the number of labels, in case pointers form a loop
+    for _ in 0..128 {
+        let len = *message.get(pos)? as usize;
+        if len == 0 {
+            return Some((labels.join("."), end.unwrap_or(pos + 1)));
+        } else if len & 0xc0 == 0xc0 {
+            end.get_or_insert(pos + 2);
+            pos = (len & 0x3f) << 8 | *message.get(pos + 1)? as usize;
+        } else {
+            let label = message.get(pos + 1..pos + 1 + len)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            pos += 1 + len;
+        }
+    }
+    None
+}
+
+/// A WS-Discovery probe for ONVIF network video transmitters.
+fn ws_discovery_probe() -> String {
+    let id = rand::random::<u128>().to_be_bytes();
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    let message_id = format!(
+        "{}-{}-{}-{}-{}",
+        hex(&id[..4]),
+        hex(&id[4..6]),
+        hex(&id[6..8]),
+        hex(&id[8..10]),
+        hex(&id[10..])
+    );
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<e:Envelope xmlns:e="http://www.w3.org/2003/05/soap-envelope" xmlns:w="http://schemas.xmlsoap.org/ws/2004/08/addressing" xmlns:d="http://schemas.xmlsoap.org/ws/2005/04/discovery" xmlns:dn="http://www.onvif.org/ver10/network/wsdl">
+<e:Header>
+<w:MessageID>uuid:{}</w:MessageID>
+<w:To e:mustUnderstand="true">urn:schemas-xmlsoap-org:ws:2005:04:discovery</w:To>
+<w:Action e:mustUnderstand="true">http://schemas.xmlsoap.org/ws/2005/04/discovery/Probe</w:Action>
+</e:Header>
+<e:Body><d:Probe><d:Types>dn:NetworkVideoTransmitter</d:Types></d:Probe></e:Body>
+</e:Envelope>
+"#,
+        message_id
+    )
+}
+
+/// The Axis product described by the WS-Discovery `ProbeMatches` in `message`, if it describes one.
+///
+/// Other ONVIF devices answer probes too, but only Axis products name Axis in their scopes, e.g.
+/// `onvif://www.onvif.org/name/AXIS%20M3045-V`.
+fn parse_ws_discovery(message: &[u8]) -> Option<Discovered> {
+    let message = std::str::from_utf8(message).ok()?;
+    let scopes = xml_text(message, "Scopes")?;
+    if !scopes.to_ascii_lowercase().contains("axis") {
+        return None;
+    }
+
+    let scope = |kind: &str| {
+        scopes
+            .split_whitespace()
+            .find_map(|scope| {
+                scope
+                    .strip_prefix("onvif://www.onvif.org/")?
+                    .strip_prefix(kind)
+            })
+            .map(percent_decode)
+    };
+    let model = scope("hardware/").or_else(|| scope("name/").map(|n| strip_brand(&n).to_string()));
+    Some(Discovered {
+        model,
+        serial: None,
+    })
+}
+
+/// The text of the first element named `local_name` in any namespace within `xml`.
+fn xml_text<'a>(xml: &'a str, local_name: &str) -> Option<&'a str> {
+    let open = format!(":{}>", local_name);
+    let start = xml.find(&open)? + open.len();
+    let len = xml[start..].find('<')?;
+    Some(xml[start..start + len].trim())
+}
+
+/// Decode `%XX` escapes in `s`.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// `name` without a leading `AXIS `.
+fn strip_brand(name: &str) -> &str {
+    name.strip_prefix("AXIS ").unwrap_or(name).trim()
+}
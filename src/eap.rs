@@ -0,0 +1,526 @@
+use crate::error::{Error, IoResultExt};
+use crate::package_dot_conf::PackageDotConf;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+/// Assembles an `.eap` from entries added in archive order, and writes it.
+///
+/// Deciding what goes into a package is left to the caller; this only says how each entry is
+/// archived, so that the contents can be planned and inspected without writing anything.
+#[derive(Debug, Clone, Default)]
+pub struct EapBuilder {
+    entries: Vec<Entry>,
+    /// The `package.conf` written as the `package.conf` entry, once set
+    package_conf: Option<PackageDotConf>,
+}
+
+impl EapBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the file at `path` on the host as `name`, with the mode of a regular file.
+    pub fn add_file(&mut self, name: impl Into<String>, path: impl Into<PathBuf>) -> &mut Self {
+        self.add(name.into(), Source::Path(path.into()), false)
+    }
+
+    /// Add the file at `path` on the host as `name`, marked executable.
+    pub fn add_executable(
+        &mut self,
+        name: impl Into<String>,
+        path: impl Into<PathBuf>,
+    ) -> &mut Self {
+        self.add(name.into(), Source::Path(path.into()), true)
+    }
+
+    /// Add `bytes`, generated rather than read from the host, as `name` with `mode`.
+    pub fn add_bytes(
+        &mut self,
+        name: impl Into<String>,
+        bytes: impl Into<Vec<u8>>,
+        mode: u32,
+    ) -> &mut Self {
+        self.add_entry(Entry {
+            name: name.into(),
+            source: Source::Generated(bytes.into()),
+            executable: false,
+            mode: Some(mode),
+        })
+    }
+
+    /// Add `text`, generated rather than read from the host, as `name`.
+    pub fn add_generated(&mut self, name: impl Into<String>, text: impl Into<String>) -> &mut Self {
+        self.add_bytes(name, text.into(), 0o644)
+    }
+
+    /// Add the directory `name`, for the entries which follow it.
+    pub fn add_directory(&mut self, name: impl Into<String>) -> &mut Self {
+        self.add(name.into(), Source::Directory, false)
+    }
+
+    /// Add a symbolic link `name` to `target`, relative to the link's directory.
+    pub fn add_symlink(&mut self, name: impl Into<String>, target: impl Into<String>) -> &mut Self {
+        self.add(name.into(), Source::Symlink(target.into()), false)
+    }
+
+    /// Add `entry` as it is, e.g. with an explicit mode.
+    pub fn add_entry(&mut self, entry: Entry) -> &mut Self {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Add `package_conf` as `package.conf`, followed by `comments`, replacing any added before.
+    ///
+    /// `package_conf` is generated now, so it must already list every file it should.
+    pub fn set_package_conf(
+        &mut self,
+        package_conf: &PackageDotConf,
+        comments: &str,
+    ) -> Result<&mut Self, Error> {
+        let text = package_conf.to_shell()? + comments;
+        self.entries.retain(|entry| entry.name != "package.conf");
+        self.package_conf = Some(package_conf.clone());
+        Ok(self.add_generated("package.conf", text))
+    }
+
+    /// The `package.conf` given to `set_package_conf()`, if any.
+    pub fn package_conf(&self) -> Option<&PackageDotConf> {
+        self.package_conf.as_ref()
+    }
+
+    /// Everything added so far, in archive order.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Whether an entry named `name` has been added.
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.iter().any(|entry| entry.name == name)
+    }
+
+    /// Write the package to `writer`, gzipped with `compression`, returning `writer` once the
+    /// package is complete.
+    ///
+    /// Every package needs a `package.conf`, so this fails if none was set.
+    pub fn finish<W: Write>(&self, writer: W, compression: Compression) -> std::io::Result<W> {
+        if self.package_conf.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a package must have a package.conf",
+            ));
+        }
+
+        let gz = flate2::write::GzEncoder::new(writer, compression.level());
+        let mut tar = tar::Builder::new(gz);
+        for entry in &self.entries {
+            entry.append_to(&mut tar)?;
+        }
+        tar.into_inner()?.finish()
+    }
+
+    fn add(&mut self, name: String, source: Source, executable: bool) -> &mut Self {
+        self.add_entry(Entry {
+            name,
+            source,
+            executable,
+            mode: None,
+        })
+    }
+}
+
+/// A file within an `.eap`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    /// The path within the archive
+    pub name: String,
+    pub source: Source,
+    /// Whether the file is marked executable, regardless of its mode on the host
+    pub executable: bool,
+    /// The file's mode, if given explicitly, which overrides `executable`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+}
+
+impl Entry {
+    /// The mode with which the entry is packaged.
+    pub fn mode(&self) -> u32 {
+        match (self.mode, &self.source, self.executable) {
+            (Some(mode), _, _) => mode,
+            (None, Source::Symlink(_), _) => 0o777,
+            (None, Source::Directory, _) | (None, _, true) => 0o755,
+            (None, _, false) => 0o644,
+        }
+    }
+
+    /// Append the entry to `tar`.
+    fn append_to<W: Write>(&self, tar: &mut tar::Builder<W>) -> Result<(), std::io::Error> {
+        match &self.source {
+            Source::Path(path) if self.executable || self.mode.is_some() => {
+                append_with_mode(tar, &self.name, &mut File::open(path)?, self.mode())
+            }
+            Source::Path(path) => tar.append_path_with_name(path, &self.name),
+            Source::Generated(bytes) => {
+                let mut header = tar_header(bytes.len() as _, Some(SystemTime::now()));
+                header.set_mode(self.mode());
+                tar.append_data(&mut header, &self.name, bytes.as_slice())
+            }
+            Source::Directory => {
+                let mut header = tar_header(0, Some(SystemTime::now()));
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(self.mode());
+                tar.append_data(&mut header, format!("{}/", self.name), std::io::empty())
+            }
+            Source::Symlink(target) => {
+                let mut header = tar_header(0, Some(SystemTime::now()));
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_mode(self.mode());
+                tar.append_link(&mut header, &self.name, target)
+            }
+        }
+    }
+}
+
+/// Where the contents of an `Entry` come from.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// A file on the host
+    Path(PathBuf),
+    /// Data generated by `cargo acap`, usually text, which it's shown as
+    #[serde(serialize_with = "serialize_lossy")]
+    Generated(Vec<u8>),
+    /// A directory, for the files which follow it
+    Directory,
+    /// A symbolic link to this path, relative to the link's directory
+    Symlink(String),
+}
+
+/// Serialize `bytes` as a string, replacing anything which isn't UTF-8.
+fn serialize_lossy<S: serde::Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&String::from_utf8_lossy(bytes))
+}
+
+/// How hard to gzip an `.eap`: a level from 0 (store only) to 9 (smallest).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Compression(u32);
+
+impl Compression {
+    /// The level for this compression, as used by `flate2`.
+    pub fn level(self) -> flate2::Compression {
+        flate2::Compression::new(self.0)
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression(6)
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    /// Parse a level from 0 to 9, or `fast`, `default`, or `best`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast" => Ok(Compression(1)),
+            "default" => Ok(Compression::default()),
+            "best" => Ok(Compression(9)),
+            _ => match s.parse() {
+                Ok(level) if level <= 9 => Ok(Compression(level)),
+                _ => Err(format!(
+                    "invalid compression {:?}: expected a level from 0 to 9, `fast`, `default`, or `best`",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Compression {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Level(u32),
+            Name(String),
+        }
+
+        let s = match Value::deserialize(deserializer)? {
+            Value::Level(level) => level.to_string(),
+            Value::Name(name) => name,
+        };
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A reader which counts the bytes read through it, to say where a problem was found.
+pub struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// An entry of an `.eap` being read from an `R` by `read_eap()` or `read_eap_from()`.
+pub type EapEntry<'a, R = File> = tar::Entry<'a, flate2::read::GzDecoder<CountingReader<R>>>;
+
+/// Call `f` with each entry of the `.eap` at `path`, in archive order.
+///
+/// Corrupt or truncated archives are reported along with how far into the file the problem was
+/// found, as are errors returned by `f`.
+pub fn read_eap<F>(path: &Path, f: F) -> Result<(), Error>
+where
+    F: FnMut(&mut EapEntry) -> Result<(), std::io::Error>,
+{
+    let file = File::open(path).with_path(path)?;
+    read_eap_from(file, path, f)
+}
+
+/// Call `f` with each entry of the `.eap` read from `reader`, in archive order, as for
+/// `read_eap()`, naming it `path` in errors.
+pub fn read_eap_from<R, F>(reader: R, path: &Path, mut f: F) -> Result<(), Error>
+where
+    R: Read,
+    F: FnMut(&mut EapEntry<R>) -> Result<(), std::io::Error>,
+{
+    let count = Rc::new(Cell::new(0));
+    let reader = CountingReader {
+        inner: reader,
+        count: count.clone(),
+    };
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(reader));
+
+    let failed = |e: std::io::Error| {
+        Error::Package(format!(
+            "error reading {} near byte {}: {}",
+            path.display(),
+            count.get(),
+            e
+        ))
+    };
+    for entry in archive.entries().map_err(failed)? {
+        let mut entry = entry.map_err(failed)?;
+        f(&mut entry).map_err(failed)?;
+    }
+    Ok(())
+}
+
+/// The path of `entry` within its archive, without any leading `./` or trailing `/`.
+pub fn entry_name<R: Read>(entry: &EapEntry<R>) -> Result<String, std::io::Error> {
+    let name = entry.path()?.to_string_lossy().into_owned();
+    Ok(name
+        .trim_start_matches("./")
+        .trim_end_matches('/')
+        .to_string())
+}
+
+/// Append `file` to `tar` as `name` with `mode`, regardless of its mode on the host.
+fn append_with_mode<W: Write>(
+    tar: &mut tar::Builder<W>,
+    name: &str,
+    file: &mut File,
+    mode: u32,
+) -> Result<(), std::io::Error> {
+    let metadata = file.metadata()?;
+    let mut header = tar_header(metadata.len(), metadata.modified().ok());
+    header.set_mode(mode);
+    tar.append_data(&mut header, name, file)
+}
+
+/// A header for a regular file of `size` bytes, to be named by `tar::Builder::append_data()`,
+/// which writes GNU long name entries for paths too long for the header itself.
+fn tar_header(size: u64, mtime: Option<SystemTime>) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(
+        mtime
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    );
+    header.set_size(size);
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_cksum();
+    header
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::target::Target;
+    use crate::testing;
+
+    /// An entry read back from a package: its name, type, mode, link target, and contents.
+    pub(crate) type ReadEntry = (String, tar::EntryType, u32, Option<String>, Vec<u8>);
+
+    fn package_conf() -> PackageDotConf {
+        let (_dir, package) =
+            testing::package("[package]\nname = \"app\"\nversion = \"0.1.0\"\n", &[]);
+        PackageDotConf::from_cargo_package(&package, "app", None, Target::Mips).unwrap()
+    }
+
+    /// Write `eap` to memory and read back each of its entries.
+    pub(crate) fn round_trip(eap: &EapBuilder) -> Vec<ReadEntry> {
+        let bytes = eap.finish(Vec::new(), Compression::default()).unwrap();
+        let mut entries = vec![];
+        read_eap_from(bytes.as_slice(), Path::new("test.eap"), |entry| {
+            let name = entry_name(entry)?;
+            let header = entry.header();
+            let kind = header.entry_type();
+            let mode = header.mode()?;
+            let link = entry
+                .link_name()?
+                .map(|link| link.to_string_lossy().into_owned());
+            let mut contents = vec![];
+            entry.read_to_end(&mut contents)?;
+            entries.push((name, kind, mode, link, contents));
+            Ok(())
+        })
+        .unwrap();
+        entries
+    }
+
+    #[test]
+    fn entries_are_written_in_order_with_their_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let model = dir.path().join("model.bin");
+        std::fs::write(&model, "model").unwrap();
+        let executable = dir.path().join("app");
+        std::fs::write(&executable, "\x7fELF").unwrap();
+
+        let mut eap = EapBuilder::new();
+        eap.add_directory("models")
+            .add_entry(Entry {
+                name: "models/model.bin".into(),
+                source: Source::Path(model),
+                executable: false,
+                mode: Some(0o600),
+            })
+            .add_generated("cgi.txt", "GET /app.cgi\n");
+        eap.set_package_conf(&package_conf(), "").unwrap();
+        eap.add_bytes("blob", vec![0, 1, 2], 0o640)
+            .add_executable("app", executable)
+            .add_symlink("renamed", "app");
+
+        let entries = round_trip(&eap);
+        let summary: Vec<(&str, tar::EntryType, u32, Option<&str>)> = entries
+            .iter()
+            .map(|(name, kind, mode, link, _)| (name.as_str(), *kind, *mode, link.as_deref()))
+            .collect();
+        use tar::EntryType::{Directory, Regular, Symlink};
+        assert_eq!(
+            summary,
+            [
+                ("models", Directory, 0o755, None),
+                ("models/model.bin", Regular, 0o600, None),
+                ("cgi.txt", Regular, 0o644, None),
+                ("package.conf", Regular, 0o644, None),
+                ("blob", Regular, 0o640, None),
+                ("app", Regular, 0o755, None),
+                ("renamed", Symlink, 0o777, Some("app")),
+            ]
+        );
+        assert_eq!(entries[1].4, b"model");
+        assert_eq!(entries[2].4, b"GET /app.cgi\n");
+        assert_eq!(entries[4].4, [0, 1, 2]);
+        assert_eq!(entries[5].4, b"\x7fELF");
+    }
+
+    #[test]
+    fn long_names_are_written_as_gnu_long_names() {
+        let name = format!("html/{}.html", "a".repeat(120));
+        let mut eap = EapBuilder::new();
+        eap.set_package_conf(&package_conf(), "").unwrap();
+        eap.add_generated(name.clone(), "<html></html>\n");
+
+        let entries = round_trip(&eap);
+        assert_eq!(entries[1].0, name);
+        assert_eq!(entries[1].4, b"<html></html>\n");
+    }
+
+    #[test]
+    fn every_kind_of_entry_keeps_a_long_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, "file").unwrap();
+
+        // Longer than a header's 100 bytes, and than the 255 bytes of a ustar prefix and name
+        let long_dir = format!("{}/{}", "d".repeat(99), "e".repeat(120));
+        let name = |n: &str| format!("{}/{}{}", long_dir, "f".repeat(150), n);
+        let mut eap = EapBuilder::new();
+        eap.set_package_conf(&package_conf(), "").unwrap();
+        eap.add_directory(long_dir.clone())
+            .add_file(name("host"), file.clone())
+            .add_executable(name("executable"), file.clone())
+            .add_entry(Entry {
+                name: name("mode"),
+                source: Source::Path(file),
+                executable: false,
+                mode: Some(0o600),
+            })
+            .add_generated(name("generated"), "generated")
+            .add_symlink(name("symlink"), name("host"));
+
+        let entries = round_trip(&eap);
+        let names: Vec<&str> = entries[1..].iter().map(|e| e.0.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                long_dir.clone(),
+                name("host"),
+                name("executable"),
+                name("mode"),
+                name("generated"),
+                name("symlink"),
+            ]
+        );
+        assert!(names.iter().skip(1).all(|name| name.len() > 255));
+        assert_eq!(entries[2].4, b"file");
+        assert_eq!(entries[3].4, b"file");
+        assert_eq!(entries[4].4, b"file");
+        assert_eq!(entries[5].4, b"generated");
+        assert_eq!(entries[6].3, Some(name("host")));
+    }
+
+    #[test]
+    fn set_package_conf_replaces_any_earlier_package_conf() {
+        let package_conf = package_conf();
+        let mut eap = EapBuilder::new();
+        eap.set_package_conf(&package_conf, "# first\n").unwrap();
+        eap.add_generated("manifest.json", "{}\n");
+        eap.set_package_conf(&package_conf, "# second\n").unwrap();
+
+        let entries = round_trip(&eap);
+        let names: Vec<&str> = entries.iter().map(|entry| entry.0.as_str()).collect();
+        assert_eq!(names, ["manifest.json", "package.conf"]);
+        let text = String::from_utf8(entries[1].4.clone()).unwrap();
+        assert!(text.starts_with(&package_conf.to_shell().unwrap()));
+        assert!(text.ends_with("# second\n"));
+        assert!(!text.contains("# first"));
+    }
+
+    #[test]
+    fn finish_fails_without_a_package_conf() {
+        let mut eap = EapBuilder::new();
+        eap.add_generated("manifest.json", "{}\n");
+        let e = eap.finish(Vec::new(), Compression::default()).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::cargo_config::MetadataError;
+use crate::cli::CommandFailed;
+use crate::device::DeviceError;
+use std::path::{Path, PathBuf};
+
+/// Why `cargo acap` failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The workspace at `path` could not be loaded by `cargo`
+    Workspace { path: PathBuf, message: String },
+    /// The `[package.metadata.acap]` table in the manifest at `path` is invalid
+    Metadata {
+        path: PathBuf,
+        source: MetadataError,
+    },
+    /// The package can't be packaged as it is, e.g. because a file it names is missing
+    Package(String),
+    /// A command run while building returned unsuccessfully
+    Command(CommandFailed),
+    /// Some targets failed to build, each of which has already been reported
+    TargetsFailed { failed: usize, total: usize },
+    /// `program` could not be started
+    Spawn {
+        program: String,
+        source: std::io::Error,
+    },
+    /// Reading or writing `path` failed
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A device could not be reached or refused a request
+    Device(DeviceError),
+    /// The `docker` image is not available, or `docker` can't be reached to run it
+    Image(String),
+    /// The invocation can't be carried out as given, e.g. because an option is missing
+    Usage(String),
+}
+
+impl Error {
+    /// The code with which `cargo acap` exits because of this error.
+    ///
+    /// Scripts can rely on these:
+    ///
+    /// * 101: a target failed to build
+    /// * 102: `docker` (or `podman`) is not installed or can't be reached, or its image is not
+    ///   available
+    /// * 103: the package or its `[package.metadata.acap]` is invalid
+    /// * 104: a device could not be reached or refused a request
+    /// * 1: anything else
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Command(_) | Error::TargetsFailed { .. } => 101,
+            Error::Spawn { program, source } if is_container_engine_missing(program, source) => 102,
+            Error::Image(_) => 102,
+            Error::Workspace { .. } | Error::Metadata { .. } | Error::Package(_) => 103,
+            Error::Device(_) => 104,
+            Error::Spawn { .. } | Error::Io { .. } | Error::Usage(_) => 1,
+        }
+    }
+}
+
+/// Whether `program` failed to start because it's a container engine which isn't installed.
+fn is_container_engine_missing(program: &str, error: &std::io::Error) -> bool {
+    (program == "docker" || program == "podman") && error.kind() == std::io::ErrorKind::NotFound
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Metadata { source, .. } => Some(source),
+            Error::Command(e) => Some(e),
+            Error::Spawn { source, .. } | Error::Io { source, .. } => Some(source),
+            Error::Device(e) => Some(e),
+            Error::Workspace { .. }
+            | Error::Package(_)
+            | Error::TargetsFailed { .. }
+            | Error::Image(_)
+            | Error::Usage(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Workspace { path, message } => {
+                write!(f, "error loading {}: {}", path.display(), message)
+            }
+            Error::Metadata { path, source } => write!(
+                f,
+                "invalid [package.metadata.acap] in {}:\n{}",
+                path.display(),
+                source
+            ),
+            Error::Package(message) | Error::Image(message) | Error::Usage(message) => {
+                f.write_str(message)
+            }
+            Error::Command(e) => write!(f, "{}", e),
+            Error::TargetsFailed { failed, total } => {
+                write!(f, "{} of {} targets failed to build", failed, total)
+            }
+            Error::Spawn { program, source } if is_container_engine_missing(program, source) => {
+                write!(
+                    f,
+                    "`{}` was not found: install it, select another --container-engine, or pass --no-docker",
+                    program
+                )
+            }
+            Error::Spawn { program, source } => {
+                write!(f, "error running `{}`: {}", program, source)
+            }
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Device(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<CommandFailed> for Error {
+    fn from(e: CommandFailed) -> Self {
+        Error::Command(e)
+    }
+}
+
+impl From<DeviceError> for Error {
+    fn from(e: DeviceError) -> Self {
+        Error::Device(e)
+    }
+}
+
+/// Attach the path of the file concerned to an I/O error.
+pub trait IoResultExt<T> {
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T, Error>;
+}
+
+impl<T> IoResultExt<T> for Result<T, std::io::Error> {
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T, Error> {
+        self.map_err(|source| Error::Io {
+            path: path.as_ref().to_owned(),
+            source,
+        })
+    }
+}
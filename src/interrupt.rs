@@ -0,0 +1,136 @@
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// Whether Ctrl-C has been pressed
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// What's running which Ctrl-C must stop
+static RUNNING: Mutex<Running> = Mutex::new(Running {
+    next_id: 0,
+    children: Vec::new(),
+    handlers: 0,
+});
+
+struct Running {
+    next_id: u64,
+    /// Each running child's registration ID, process ID, and container, if it runs one, or a
+    /// container left running between commands, which has no process ID
+    children: Vec<(u64, Option<u32>, Option<Container>)>,
+    /// How many `Handling` guards are alive
+    handlers: usize,
+}
+
+/// A container, by the program which runs it and its name.
+#[derive(Debug, Clone)]
+pub struct Container {
+    pub program: String,
+    pub name: String,
+}
+
+fn running() -> MutexGuard<'static, Running> {
+    // The state remains consistent even if a holder panicked
+    RUNNING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Handle Ctrl-C by stopping whatever is running, so that `cargo acap` can clean up and exit.
+///
+/// If no child is running and nothing is handling interruptions, or Ctrl-C is pressed a second
+/// time, `cargo acap` exits immediately with status 130, after stopping any containers left
+/// running between commands.
+pub fn install_handler() {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+
+        let running = running();
+        for (_, pid, container) in &running.children {
+            match (pid, container) {
+                // The engine may not forward signals to the container, e.g. with --tty
+                (_, Some(container)) => {
+                    let _ = Command::new(&container.program)
+                        .args(["kill", "--signal", "INT", &container.name])
+                        .stdout(Stdio::null())
+                        .stderr(Stdio::null())
+                        .status();
+                }
+                (Some(pid), None) => interrupt_process(*pid),
+                (None, None) => {}
+            }
+        }
+        if running.children.iter().all(|(_, pid, _)| pid.is_none()) && running.handlers == 0 {
+            std::process::exit(130);
+        }
+    })
+    .expect("error installing Ctrl-C handler");
+}
+
+/// Whether Ctrl-C has been pressed.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[cfg(unix)]
+fn interrupt_process(pid: u32) {
+    // Safety: at worst kill() fails because the process has already exited
+    unsafe { libc::kill(pid as libc::pid_t, libc::SIGINT) };
+}
+
+#[cfg(not(unix))]
+fn interrupt_process(_pid: u32) {
+    // Console processes all receive Ctrl-C themselves
+}
+
+/// A unique name for a container started by this process.
+pub fn container_name() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "cargo-acap-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// A running child or container, which Ctrl-C interrupts until this is dropped.
+#[derive(Debug)]
+pub struct Registration(u64);
+
+/// Interrupt `child`, or the container it runs, when Ctrl-C is pressed.
+pub fn register(child: &Child, container: Option<Container>) -> Registration {
+    add(Some(child.id()), container)
+}
+
+/// Stop `container`, which runs in the background between commands, when Ctrl-C is pressed.
+pub fn register_container(container: Container) -> Registration {
+    add(None, Some(container))
+}
+
+fn add(pid: Option<u32>, container: Option<Container>) -> Registration {
+    let mut running = running();
+    let id = running.next_id;
+    running.next_id += 1;
+    running.children.push((id, pid, container));
+    Registration(id)
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        running().children.retain(|(id, _, _)| *id != self.0);
+    }
+}
+
+/// A section which checks `interrupted()` itself, so that Ctrl-C doesn't exit immediately.
+pub struct Handling(());
+
+/// Handle Ctrl-C by checking `interrupted()` until the result is dropped.
+pub fn handle() -> Handling {
+    running().handlers += 1;
+    Handling(())
+}
+
+impl Drop for Handling {
+    fn drop(&mut self) {
+        running().handlers -= 1;
+    }
+}
@@ -1,10 +1,37 @@
+mod bloat;
+mod build_info;
 mod cargo_config;
 mod cli;
+mod device;
+mod devices_dot_toml;
+mod discovery;
+mod eap;
+mod error;
+mod interrupt;
+mod manifest_dot_json;
+mod output;
 mod package_dot_conf;
+mod param_dot_conf;
 mod shell_includes;
 mod target;
+#[cfg(test)]
+mod testing;
 mod whoami;
 
+use output::Style;
+
 fn main() {
-    cli::Invocation::main()
+    interrupt::install_handler();
+    if let Err(e) = cli::Invocation::main() {
+        if interrupt::interrupted() {
+            eprintln!("cargo-acap: {}", output::paint(Style::Error, "interrupted"));
+            std::process::exit(130);
+        }
+        eprintln!(
+            "{} {}",
+            output::paint(Style::Error, "`cargo acap` failed:"),
+            e
+        );
+        std::process::exit(e.exit_code());
+    }
 }
@@ -0,0 +1,233 @@
+use crate::cargo_config::CargoAcapMetadata;
+use crate::error::Error;
+use crate::package_dot_conf::{LicensePage, PackageDotConf, StartMode};
+use crate::param_dot_conf::ParamDotConf;
+use serde::Serialize;
+use std::fmt;
+
+/// The `manifest.json` schema versions which `cargo acap` can generate.
+pub const SCHEMA_VERSIONS: &[&str] = &["1.0", "1.1", "1.2", "1.3", "1.5"];
+
+/// The first schema version with `reverseProxy`, for `web_server`.
+const REVERSE_PROXY_SCHEMA_VERSION: &str = "1.5";
+
+/// The `manifest.json` used by the ACAP Native SDK and firmware 10.7 and later in place of
+/// `package.conf`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ManifestDotJson {
+    schema_version: String,
+    acap_package_conf: AcapPackageConf,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AcapPackageConf {
+    setup: Setup,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    installation: Option<Installation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uninstallation: Option<Uninstallation>,
+    #[serde(skip_serializing_if = "Configuration::is_empty")]
+    configuration: Configuration,
+    copy_protection: CopyProtection,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Setup {
+    app_name: String,
+    friendly_name: String,
+    vendor: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vendor_url: Option<String>,
+    embedded_sdk_version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    app_id: Option<String>,
+    run_mode: StartMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run_options: Option<String>,
+    user: User,
+    version: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+struct User {
+    username: String,
+    group: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Installation {
+    post_install_script: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Uninstallation {
+    pre_uninstall_script: String,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Configuration {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    setting_page: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    param_config: Vec<ParamConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reverse_proxy: Vec<ReverseProxy>,
+}
+
+impl Configuration {
+    fn is_empty(&self) -> bool {
+        self == &Configuration::default()
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+struct ParamConfig {
+    name: String,
+    default: String,
+    #[serde(rename = "type")]
+    param_type: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReverseProxy {
+    api_path: String,
+    target: String,
+    access: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+struct CopyProtection {
+    method: LicensePage,
+}
+
+impl ManifestDotJson {
+    /// Resolve the `manifest.json` for `package`, if `manifest_version` is set in its metadata.
+    ///
+    /// Fails if the manifest would not conform to the selected schema version.
+    pub fn from_cargo_package(
+        package: &cargo::core::Package,
+        package_conf: &PackageDotConf,
+        param_conf: Option<&ParamDotConf>,
+    ) -> Result<Option<Self>, Error> {
+        let metadata = CargoAcapMetadata::for_package(package)?;
+        let schema_version = match metadata.manifest_version {
+            Some(schema_version) => schema_version,
+            None => return Ok(None),
+        };
+        if !SCHEMA_VERSIONS.contains(&schema_version.as_str()) {
+            return Err(Error::Package(format!(
+                "manifest_version = {:?} is not supported: expected one of {}",
+                schema_version,
+                SCHEMA_VERSIONS.join(", ")
+            )));
+        }
+
+        let version = &package_conf.version;
+        if !version.pre.is_empty() || !version.build.is_empty() {
+            return Err(Error::Package(format!(
+                "version {} can't be used in manifest.json, which requires MAJOR.MINOR.PATCH",
+                version
+            )));
+        }
+
+        // The manifest.json format was introduced with SDK 3.0
+        let embedded_sdk_version = match package_conf
+            .required_embedded_development_version
+            .split('.')
+            .next()
+            .and_then(|major| major.parse::<u32>().ok())
+        {
+            Some(major) if major >= 3 => package_conf.required_embedded_development_version.clone(),
+            _ => "3.0".to_string(),
+        };
+
+        let param_config = match param_conf {
+            Some(ParamDotConf::Generated(parameters)) => parameters
+                .iter()
+                .map(|p| ParamConfig {
+                    name: p.name.clone(),
+                    default: p.default.clone(),
+                    param_type: p.param_type.clone().unwrap_or_else(|| "string".to_string()),
+                })
+                .collect(),
+            Some(ParamDotConf::Verbatim(_)) => return Err(Error::Package(
+                "param.conf can't be converted to manifest.json: declare parameters in [package.metadata.acap] instead".to_string()
+            )),
+            None => vec![],
+        };
+
+        let reverse_proxy = match metadata.web_server {
+            Some(web_server) => {
+                // SCHEMA_VERSIONS is in order
+                let position = |v: &str| SCHEMA_VERSIONS.iter().position(|s| *s == v);
+                if position(&schema_version) < position(REVERSE_PROXY_SCHEMA_VERSION) {
+                    return Err(Error::Package(format!(
+                        "web_server needs manifest_version = \"{}\" or later, for reverseProxy",
+                        REVERSE_PROXY_SCHEMA_VERSION
+                    )));
+                }
+                vec![ReverseProxy {
+                    api_path: web_server.path.clone(),
+                    target: format!("http://localhost:{}", web_server.port),
+                    access: web_server.access.name().to_string(),
+                }]
+            }
+            None => vec![],
+        };
+
+        Ok(Some(ManifestDotJson {
+            schema_version,
+            acap_package_conf: AcapPackageConf {
+                setup: Setup {
+                    app_name: package_conf.app_name.clone(),
+                    friendly_name: package_conf.display_name.clone(),
+                    vendor: package_conf.vendor.clone(),
+                    vendor_url: package_conf.vendor_homepage_url.clone(),
+                    embedded_sdk_version,
+                    app_id: Some(package_conf.axis_application_id.clone())
+                        .filter(|id| !id.is_empty()),
+                    run_mode: package_conf.start_mode,
+                    run_options: package_conf.launch_arguments.clone(),
+                    user: User {
+                        username: package_conf.unix_user.clone(),
+                        group: package_conf.unix_group.clone(),
+                    },
+                    version: version.to_string(),
+                },
+                installation: package_conf
+                    .post_install_script
+                    .clone()
+                    .map(|post_install_script| Installation {
+                        post_install_script,
+                    }),
+                uninstallation: package_conf.pre_uninstall_script.clone().map(
+                    |pre_uninstall_script| Uninstallation {
+                        pre_uninstall_script,
+                    },
+                ),
+                configuration: Configuration {
+                    setting_page: package_conf.settings_page_file.clone(),
+                    param_config,
+                    reverse_proxy,
+                },
+                copy_protection: CopyProtection {
+                    method: package_conf.license_page,
+                },
+            },
+        }))
+    }
+}
+
+impl fmt::Display for ManifestDotJson {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let json = serde_json::to_string_pretty(self).map_err(|_| fmt::Error)?;
+        writeln!(f, "{}", json)
+    }
+}
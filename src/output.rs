@@ -0,0 +1,78 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether `--quiet` was given
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Whether to color output, as resolved from `--color`
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// When to color output.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum Color {
+    /// Color output if stderr is a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+/// How to color part of a message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Style {
+    /// What's happening, e.g. `building`, in bold green like `cargo`'s `Compiling`
+    Progress,
+    /// Why `cargo acap` failed, in bold red
+    Error,
+    /// A warning which mustn't be missed, in bold yellow
+    Warning,
+}
+
+/// Suppress messages about progress, leaving only warnings, errors, and requested output.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::SeqCst);
+}
+
+/// Whether `--quiet` was given.
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::SeqCst)
+}
+
+/// Color output as `color` says, resolving `auto` now.
+pub fn set_color(color: Color) {
+    let color = match color {
+        Color::Auto => std::io::stderr().is_terminal(),
+        Color::Always => true,
+        Color::Never => false,
+    };
+    COLOR.store(color, Ordering::SeqCst);
+}
+
+/// Whether to color output.
+pub fn color() -> bool {
+    COLOR.load(Ordering::SeqCst)
+}
+
+/// `text` in `style`, if output is colored.
+pub fn paint(style: Style, text: &str) -> String {
+    if !color() {
+        return text.to_string();
+    }
+    let sgr = match style {
+        Style::Progress => "1;32",
+        Style::Error => "1;31",
+        Style::Warning => "1;33",
+    };
+    format!("\x1b[{}m{}\x1b[0m", sgr, text)
+}
+
+/// Print a message about `cargo acap`'s progress to stderr, unless `--quiet` was given.
+///
+/// Stdout is reserved for output that was asked for, e.g. `--message-format json`.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::output::quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+pub(crate) use status;
@@ -1,15 +1,24 @@
-use crate::cargo_config::CargoAcapMetadata;
+use crate::cargo_config::{app_name_problem, CargoAcapMetadata, CgiPath, Dbus, FileMapping};
+use crate::error::{Error, IoResultExt};
 use crate::shell_includes;
 use crate::target::Target;
 use serde::{Deserialize, Serialize};
 use std::convert::TryInto;
-use std::fmt;
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub(crate) struct PackageDotConf {
     #[serde(rename = "APPNAME")]
     pub app_name: String,
 
+    /// The executable's name within the package, which is `app_name` unless that's a symlink to it
+    #[serde(skip)]
+    pub executable_name: String,
+
+    /// The binary's name, if `app_name` was derived from it by `normalized_app_name()`, and differs
+    #[serde(skip)]
+    pub app_name_normalized_from: Option<String>,
+
     #[serde(rename = "PACKAGENAME")]
     pub display_name: String,
 
@@ -57,6 +66,10 @@ pub(crate) struct PackageDotConf {
     #[serde(rename = "LICENSE_CHECK_ARGS", skip_serializing_if = "Option::is_none")]
     pub license_check_arguments: Option<String>,
 
+    /// The path on the host of the license text to package as `LICENSE`, which is UTF-8.
+    #[serde(skip)]
+    pub license_file_path: Option<PathBuf>,
+
     /// Specifies the file to use for a custom Settings page. The file (settings.html in this
     /// example) must be located in the html/ directory. If SETTINGSPAGEFILE is specified, a link
     /// from from Applications > [application name] > Settings page will direct users to the custom
@@ -76,16 +89,39 @@ pub(crate) struct PackageDotConf {
     #[serde(rename = "VENDORHOMEPAGELINK", skip_serializing_if = "Option::is_none")]
     pub vendor_homepage_link: Option<String>,
 
+    /// The URL within `vendor_homepage_link`.
+    #[serde(skip)]
+    pub vendor_homepage_url: Option<String>,
+
     /// A filename containing a list of CGI's that an http-enabled application will use. See example
     /// application ax_http_serve_request.c for more information.
     #[serde(rename = "HTTPCGIPATHS", skip_serializing_if = "Option::is_none")]
     pub http_cgi_paths: Option<String>,
 
+    /// The path on the host of the file to package as `cgi.txt`, unless it's generated from
+    /// `cgi_paths`.
+    #[serde(skip)]
+    pub http_cgi_paths_path: Option<PathBuf>,
+
     /// A script that will be executed on the Axis product when the installation is completed. The
     /// script must be a shell script located in the same directory as the package.conf file. The
     /// script will be executed from the application directory in the Axis product.
-    #[serde(rename = "POSTINSTALLSCRIPT")]
-    pub post_install_script: String,
+    #[serde(rename = "POSTINSTALLSCRIPT", skip_serializing_if = "Option::is_none")]
+    pub post_install_script: Option<String>,
+
+    /// The path on the host of the script named by `post_install_script`.
+    #[serde(skip)]
+    pub post_install_script_path: Option<PathBuf>,
+
+    /// A script that will be executed on the Axis product before the application is uninstalled,
+    /// from the application directory. Like the post-install script, it must be located in the
+    /// same directory as the package.conf file.
+    #[serde(rename = "PREUNINSTALLSCRIPT", skip_serializing_if = "Option::is_none")]
+    pub pre_uninstall_script: Option<String>,
+
+    /// The path on the host of the script named by `pre_uninstall_script`.
+    #[serde(skip)]
+    pub pre_uninstall_script_path: Option<PathBuf>,
 
     /// Specifies the minimum required SDK version that the product running the application must
     /// support. Firmware version 5.60 correspond to REQEMBDEVVERSION="2.0"
@@ -103,6 +139,27 @@ pub(crate) struct PackageDotConf {
     /// Defines how the application is started.
     #[serde(rename = "STARTMODE")]
     pub start_mode: StartMode,
+
+    /// The CGIs declared in `[package.metadata.acap]`, from which to generate `cgi.txt`.
+    #[serde(skip)]
+    pub cgi_paths: Vec<CgiPath>,
+
+    /// The files mapped by `files` in `[package.metadata.acap]`, which are listed in OTHERFILES
+    /// once they're packaged.
+    #[serde(skip)]
+    pub files: Vec<MappedFile>,
+
+    /// The D-Bus service declared in `[package.metadata.acap]`, whose policy to package.
+    #[serde(skip)]
+    pub dbus: Option<Dbus>,
+
+    /// The D-Bus policy to package, if not generated from `dbus`.
+    #[serde(skip)]
+    pub dbus_conf_path: Option<PathBuf>,
+
+    /// The application's full version, from which the fields above were derived.
+    #[serde(skip)]
+    pub version: semver::Version,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -113,7 +170,7 @@ pub enum LicensePage {
     None,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
 #[serde(rename_all = "snake_case")]
 pub enum StartMode {
     Respawn,
@@ -121,91 +178,389 @@ pub enum StartMode {
     Never,
 }
 
+/// The likely mistakes found by `PackageDotConf::lints()`, by the IDs which `allow` accepts.
+pub const LINT_IDS: &[&str] = &[
+    "acap::launch-arguments-unused",
+    "acap::license-check-arguments-unused",
+    "acap::settings-page-missing",
+];
+
+/// A likely mistake in a package's configuration, which is valid but doesn't do what was meant.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct Lint {
+    /// One of `LINT_IDS`
+    pub id: &'static str,
+    pub message: String,
+}
+
+/// What of the version to put in `APPMICROVERSION`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MicroVersionStyle {
+    /// The patch version, pre-release, and build metadata, e.g. `3-rc.1+build5`
+    Full,
+    /// Only the patch version, e.g. `3`
+    PatchOnly,
+    /// Like `full`, but with every character other than ASCII letters, digits, and `.` replaced
+    /// by `_`, e.g. `3_rc.1_build5`, since some firmware rejects `-` and `+`
+    Sanitized,
+}
+
+impl MicroVersionStyle {
+    /// The `APPMICROVERSION` for `version`.
+    fn micro_version(self, version: &semver::Version) -> String {
+        let mut s = version.patch.to_string();
+        if self == MicroVersionStyle::PatchOnly {
+            return s;
+        }
+        if !version.pre.is_empty() {
+            s += &format!("-{}", version.pre);
+        }
+        if !version.build.is_empty() {
+            s += &format!("+{}", version.build);
+        }
+        if self == MicroVersionStyle::Sanitized {
+            s = s
+                .chars()
+                .map(|c| match c {
+                    c if c.is_ascii_alphanumeric() || c == '.' => c,
+                    _ => '_',
+                })
+                .collect();
+        }
+        s
+    }
+}
+
 impl PackageDotConf {
-    pub fn from_cargo_package(package: &cargo::core::Package, target: Target) -> Self {
-        let acap_metadata_toml = package
-            .manifest()
-            .custom_metadata()
-            .and_then(|v| v.as_table())
-            .and_then(|t| t.get("acap"));
-
-        let acap_metadata = match acap_metadata_toml {
-            Some(m) => {
-                let acap_metadata_str = m.to_string();
-                let acap_metadata: CargoAcapMetadata = toml::de::from_str(&acap_metadata_str)
-                    .expect("error parsing [package.metadata.acap] table");
-                acap_metadata
-            }
-            None => CargoAcapMetadata::default(),
+    /// The likely mistakes in this configuration, e.g. settings which have no effect.
+    pub fn lints(&self) -> Vec<Lint> {
+        let mut lints = vec![];
+        let mut lint = |id: &'static str, message: String| {
+            debug_assert!(LINT_IDS.contains(&id));
+            lints.push(Lint { id, message })
         };
 
+        if self.start_mode == StartMode::Never && self.launch_arguments.is_some() {
+            lint(
+                "acap::launch-arguments-unused",
+                "launch_arguments are set, but start_mode = \"never\", so the application is \
+                 never launched with them"
+                    .to_string(),
+            );
+        }
+        if self.license_check_arguments.is_some() && self.license_page != LicensePage::Custom {
+            lint(
+                "acap::license-check-arguments-unused",
+                format!(
+                    "license_check_arguments are set, but the license page is {:?}, not custom, \
+                     so they're never used",
+                    self.license_page
+                ),
+            );
+        }
+        if self.settings_page_text.is_some() && self.settings_page_file.is_none() {
+            lint(
+                "acap::settings-page-missing",
+                "settings_page_text is set, but there's no settings_page_file for it to link to"
+                    .to_string(),
+            );
+        }
+        lints
+    }
+
+    /// Warn that `app_name` isn't the binary's name, if it was normalized from it.
+    pub fn warn_if_app_name_normalized(&self) {
+        if let Some(binary_name) = &self.app_name_normalized_from {
+            eprintln!(
+                "cargo-acap: warning: using app_name {:?} for the binary {:?}, whose name isn't \
+                 a valid APPNAME: set app_name in [package.metadata.acap] to choose another",
+                self.app_name, binary_name
+            );
+        }
+    }
+
+    /// Resolve the `package.conf` for building `binary_name` from `package` for `target`.
+    ///
+    /// `app_name` overrides the metadata's, which defaults to the name of the binary, normalized
+    /// by `normalized_app_name()`.
+    pub fn from_cargo_package(
+        package: &cargo::core::Package,
+        binary_name: &str,
+        app_name: Option<&str>,
+        target: Target,
+    ) -> Result<Self, Error> {
+        let app_name_override = app_name;
+        let acap_metadata = CargoAcapMetadata::for_package(package)?.resolved_for(target);
+        let version = acap_metadata.version(package);
+        let launch_arguments = acap_metadata
+            .launch_arguments(target)
+            .map(|(key, args)| (key, args.to_string()));
+        let required_embedded_development_version = acap_metadata
+            .required_embedded_development_version(target)
+            .unwrap_or("2.0")
+            .to_string();
+
         let CargoAcapMetadata {
             app_name,
+            executable_name,
             display_name,
             menu_name,
             vendor,
             axis_application_id,
             vendor_homepage_url,
-            launch_arguments,
+            launch_arguments: _,
             license_check_arguments,
+            settings_page_file,
+            settings_page_text,
+            post_install_script,
+            pre_uninstall_script,
+            other_files,
+            files,
+            parameters: _,
             start_mode,
             targets: _,
-            required_embedded_development_version,
+            manifest_version: _,
+            required_embedded_development_version: _,
+            forward_env: _,
+            rustflags: _,
+            strip_args: _,
+            libraries: _,
+            target: _,
+            unix_user,
+            unix_group,
+            compression: _,
+            license_file,
+            embed_build_info: _,
+            artifact_name: _,
+            artifact_per_soc: _,
+            version: _,
+            micro_version_style,
+            suppress_profile_warnings: _,
+            max_eap_size: _,
+            max_binary_size: _,
+            license_key_file: _,
+            cgi_paths,
+            http_cgi_paths_file,
+            allow: _,
+            dbus,
+            web_server: _,
         } = acap_metadata;
 
-        let app_name = app_name.unwrap_or_else(|| package.name().to_string());
-        // ACAP framework only support names that contain alphanumeric ASCII and _
-        for c in app_name.chars() {
-            if !c.is_ascii_alphanumeric() && c != '_' {
-                panic!("Character '{}' is not allowed in acap APPNAME: {}", c, app_name);
+        // A name set explicitly is used as it is, but one derived from the binary's is normalized
+        let (app_name, app_name_normalized_from) =
+            match app_name_override.map(String::from).or(app_name) {
+                Some(app_name) => (app_name, None),
+                None => {
+                    let app_name = normalized_app_name(binary_name);
+                    let normalized_from = Some(binary_name.to_string()).filter(|b| *b != app_name);
+                    (app_name, normalized_from)
+                }
+            };
+        let executable_name = executable_name.unwrap_or_else(|| app_name.clone());
+        for (what, key, name) in [
+            ("APPNAME", "app_name", &app_name),
+            ("the executable's name", "executable_name", &executable_name),
+        ] {
+            if let Some(problem) = app_name_problem(name) {
+                return Err(Error::Package(format!(
+                    "{} {:?} {}: set {} in [package.metadata.acap]",
+                    what, name, problem, key
+                )));
+            }
+        }
+
+        if let Some(settings_page_file) = settings_page_file.as_ref() {
+            let path = package.root().join("html").join(settings_page_file);
+            if !path.is_file() {
+                return Err(Error::Package(format!(
+                    "settings_page_file = {:?} does not exist: expected to find {}",
+                    settings_page_file,
+                    path.display()
+                )));
             }
         }
 
+        let post_install_script_path = resolve_script(
+            package.root(),
+            "post_install_script",
+            post_install_script,
+            "postinstall.sh",
+        )?;
+        let pre_uninstall_script_path = resolve_script(
+            package.root(),
+            "pre_uninstall_script",
+            pre_uninstall_script,
+            "preuninstall.sh",
+        )?;
+        // The scripts must be next to package.conf
+        let file_name = |path: &Option<PathBuf>| {
+            path.as_ref()
+                .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        };
+        let post_install_script = file_name(&post_install_script_path);
+        let pre_uninstall_script = file_name(&pre_uninstall_script_path);
+        if post_install_script.is_some() && post_install_script == pre_uninstall_script {
+            return Err(Error::Package(format!(
+                "the post-install and pre-uninstall scripts are both named {:?}, but both must be \
+                 packaged next to package.conf: rename one",
+                post_install_script.unwrap_or_default()
+            )));
+        }
+
+        let dbus_conf_path = match dbus.as_ref().and_then(|dbus| dbus.conf_file.as_ref()) {
+            Some(file) => {
+                let path = package.root().join(file);
+                if !path.is_file() {
+                    return Err(Error::Package(format!(
+                        "dbus.conf_file = {:?} does not exist: expected to find {}",
+                        file,
+                        path.display()
+                    )));
+                }
+                Some(path)
+            }
+            None => None,
+        };
+
+        let cgi_paths = cgi_paths.unwrap_or_default();
+        let http_cgi_paths_path = match http_cgi_paths_file {
+            Some(file) => {
+                let path = package.root().join(&file);
+                if !path.is_file() {
+                    return Err(Error::Package(format!(
+                        "http_cgi_paths_file = {:?} does not exist: expected to find {}",
+                        file,
+                        path.display()
+                    )));
+                }
+                Some(path)
+            }
+            None if cgi_paths.is_empty() => [
+                package.root().join("cgi.txt"),
+                package.root().join("src").join("cgi.txt"),
+            ]
+            .iter()
+            .find(|path| path.is_file())
+            .cloned(),
+            None => None,
+        };
+        if let Some(path) = http_cgi_paths_path.as_ref() {
+            let text = std::fs::read(path).with_path(path)?;
+            if text.iter().all(u8::is_ascii_whitespace) || !text.ends_with(b"\n") {
+                return Err(Error::Package(format!(
+                    "{} must list at least one CGI, and end with a newline, for the device to \
+                     read it",
+                    path.display()
+                )));
+            }
+        }
+
+        let license_file = match license_file {
+            Some(license_file) => Some(("license_file", license_file)),
+            None => package
+                .manifest()
+                .metadata()
+                .license_file
+                .clone()
+                .map(|license_file| ("license-file", license_file)),
+        };
+        let license_file_path = match license_file {
+            Some((key, license_file)) => {
+                let path = package.root().join(&license_file);
+                if !path.is_file() {
+                    return Err(Error::Package(format!(
+                        "{} = {:?} does not exist: expected to find {}",
+                        key,
+                        license_file,
+                        path.display()
+                    )));
+                }
+                let text = std::fs::read(&path).with_path(&path)?;
+                if std::str::from_utf8(&text).is_err() {
+                    return Err(Error::Package(format!(
+                        "license file {} is not UTF-8, which the device can't display: convert it",
+                        path.display()
+                    )));
+                }
+                Some(path)
+            }
+            None => None,
+        };
+
+        let other_files = resolve_other_files(package.root(), other_files.unwrap_or_default())?;
+        let files = resolve_files(package.root(), files.as_deref().unwrap_or_default())?;
+
         let display_name = display_name.unwrap_or_else(|| package.name().to_string());
         let menu_name = menu_name.unwrap_or_else(|| display_name.clone());
-        let required_embedded_development_version = required_embedded_development_version.unwrap_or_else(|| "2.0".to_string());
 
         let vendor = vendor.unwrap_or_else(|| format!("{} authors", &display_name));
 
         let license_page = if axis_application_id.is_some() {
             LicensePage::Axis
-        } else if license_check_arguments.is_some() {
+        } else if license_check_arguments.is_some() || license_file_path.is_some() {
             LicensePage::Custom
         } else {
             LicensePage::None
         };
 
-        // TODO: HTML escaping
-        let vendor_homepage_link =
-            vendor_homepage_url.map(|url| format!("<a href=\"{}\">{}</a>", url, &vendor));
+        let vendor_homepage_link = vendor_homepage_url.as_ref().map(|url| {
+            format!(
+                "<a href=\"{}\">{}</a>",
+                html_escape(url),
+                html_escape(&vendor)
+            )
+        });
 
         let start_mode = start_mode.unwrap_or(StartMode::Respawn);
 
-        let version = package.version();
+        let launch_arguments = match launch_arguments {
+            Some((key, args)) => Some(
+                interpolate(&args, |name| match name {
+                    "version" => Some(version.to_string()),
+                    "target" => Some(target.name().to_string()),
+                    "app_name" => Some(app_name.clone()),
+                    _ => None,
+                })
+                .map_err(|placeholder| {
+                    Error::Package(format!(
+                        "unknown placeholder `{}` in {}: expected ${{version}}, ${{target}}, or \
+                         ${{app_name}}",
+                        placeholder, key
+                    ))
+                })?,
+            ),
+            None => None,
+        };
+
+        let out_of_range = |part: &str, field: &str| {
+            Error::Package(format!(
+                "version {} can't be packaged: its {} version is larger than {} allows (at most \
+                 {}): set version in [package.metadata.acap]",
+                version,
+                part,
+                field,
+                i32::MAX
+            ))
+        };
         let app_major_version = version
             .major
             .try_into()
-            .unwrap_or_else(|_| panic!("version {:?} out of range", version));
+            .map_err(|_| out_of_range("major", "APPMAJORVERSION"))?;
         let app_minor_version = version
             .minor
             .try_into()
-            .unwrap_or_else(|_| panic!("version {:?} out of range", version));
-
-        let app_micro_version = {
-            let mut s = version.patch.to_string();
-            for pre in version.pre.as_str().chars() {
-                s += "-";
-                s += &pre.to_string();
-            }
-            for build in version.build.as_str().chars() {
-                s += "+";
-                s += &build.to_string();
-            }
-            Some(s)
-        };
+            .map_err(|_| out_of_range("minor", "APPMINORVERSION"))?;
+        let app_micro_version = Some(
+            micro_version_style
+                .unwrap_or(MicroVersionStyle::Full)
+                .micro_version(&version),
+        );
 
-        PackageDotConf {
+        Ok(PackageDotConf {
             app_name,
+            executable_name,
+            app_name_normalized_from,
             display_name,
             menu_name,
             axis_application_id: axis_application_id.unwrap_or_default(),
@@ -215,26 +570,73 @@ impl PackageDotConf {
             app_major_version,
             app_minor_version,
             app_micro_version,
-            other_files: vec![],
+            other_files,
             license_page,
             license_check_arguments,
-            settings_page_file: None,
-            settings_page_text: None,
+            license_file_path,
+            settings_page_file,
+            settings_page_text,
             vendor_homepage_link,
+            vendor_homepage_url,
             http_cgi_paths: None,
-            post_install_script: "postinstall.sh".to_string(),
+            http_cgi_paths_path,
+            post_install_script,
+            post_install_script_path,
+            pre_uninstall_script,
+            pre_uninstall_script_path,
             required_embedded_development_version,
-            unix_user: "sdk".to_string(),
-            unix_group: "sdk".to_string(),
+            unix_user: unix_user.unwrap_or_else(|| "sdk".to_string()),
+            unix_group: unix_group.unwrap_or_else(|| "sdk".to_string()),
             start_mode,
+            cgi_paths,
+            files,
+            dbus,
+            dbus_conf_path,
+            version,
+        })
+    }
+}
+
+impl PackageDotConf {
+    /// The contents of `package.conf`, which the device sources as a shell script.
+    pub fn to_shell(&self) -> Result<String, Error> {
+        shell_includes::to_string(self)
+            .map_err(|e| Error::Package(format!("package.conf can't be generated: {}", e)))
+    }
+}
+
+/// Escape `s` for use as HTML text or a quoted attribute value.
+pub(crate) fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&#39;",
+            c => escaped.push(c),
         }
     }
+    escaped
 }
 
-impl fmt::Display for PackageDotConf {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(&shell_includes::to_string(self))
+/// Replace each `${name}` in `s` with `value(name)`, failing with the first unknown placeholder.
+fn interpolate(s: &str, value: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        result += &rest[..start];
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| rest[start..].to_string())?;
+        let name = &rest[start + 2..end];
+        result += &value(name).ok_or_else(|| rest[start..=end].to_string())?;
+        rest = &rest[end + 1..];
     }
+    result += rest;
+    Ok(result)
 }
 
 fn serialize_other_files<S>(other_files: &[String], ser: S) -> Result<S::Ok, S::Error>
@@ -242,11 +644,382 @@ where
     S: serde::Serializer,
 {
     if let Some(bad) = other_files.iter().find(|f| f.contains(' ')) {
-        panic!(
+        return Err(serde::ser::Error::custom(format!(
             "unable to serialize other_files=[{:?}] since it contains a space",
             bad
-        );
+        )));
     }
 
     ser.serialize_str(&other_files.join(" "))
 }
+
+/// List the files to ship as OTHERFILES: everything in `data/`, plus the files and directories
+/// named by `other_files`, all relative to `root`.
+///
+/// Fails if an entry doesn't exist or can't be listed in package.conf.
+fn resolve_other_files(root: &Path, other_files: Vec<String>) -> Result<Vec<String>, Error> {
+    let mut entries = Vec::new();
+    if root.join("data").is_dir() {
+        entries.push("data".to_string());
+    }
+    entries.extend(other_files);
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let relative = Path::new(&entry);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(Error::Package(format!(
+                "other_files entry {:?} must be a path within the package root",
+                entry
+            )));
+        }
+
+        let path = root.join(relative);
+        let names = if path.is_dir() {
+            files_in(&path)
+                .with_path(&path)?
+                .into_iter()
+                .map(|file| archive_path(&relative.join(file)))
+                .collect()
+        } else if path.is_file() {
+            vec![archive_path(relative)]
+        } else {
+            return Err(Error::Package(format!(
+                "other_files entry {:?} does not exist: expected to find {}",
+                entry,
+                path.display()
+            )));
+        };
+
+        for name in names {
+            if name.contains(' ') {
+                return Err(Error::Package(format!(
+                    "{:?} can't be packaged since OTHERFILES doesn't permit spaces in file names",
+                    name
+                )));
+            }
+            if !files.contains(&name) {
+                files.push(name);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Find the script configured by `key`, relative to `root`, or else `default_name` if `root`
+/// contains it, and check that the device's /bin/sh can run it.
+fn resolve_script(
+    root: &Path,
+    key: &str,
+    configured: Option<String>,
+    default_name: &str,
+) -> Result<Option<PathBuf>, Error> {
+    let path = match configured {
+        Some(configured) => {
+            let path = root.join(&configured);
+            if !path.is_file() {
+                return Err(Error::Package(format!(
+                    "{} = {:?} does not exist: expected to find {}",
+                    key,
+                    configured,
+                    path.display()
+                )));
+            }
+            path
+        }
+        None => match Some(root.join(default_name)).filter(|path| path.is_file()) {
+            Some(path) => path,
+            None => return Ok(None),
+        },
+    };
+
+    let script = std::fs::read(&path).with_path(&path)?;
+    if script.windows(2).any(|w| w == b"\r\n") {
+        return Err(Error::Package(format!(
+            "{} {} has CRLF line endings, which the device's /bin/sh can't run",
+            key,
+            path.display()
+        )));
+    }
+    if !script.starts_with(b"#!") {
+        return Err(Error::Package(format!(
+            "{} {} has no shebang line, e.g. `#!/bin/sh`, so the device can't run it",
+            key,
+            path.display()
+        )));
+    }
+    Ok(Some(path))
+}
+
+/// A file to package at a path of its own, as mapped by `files` in `[package.metadata.acap]`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MappedFile {
+    /// The path within the package
+    pub name: String,
+    pub path: PathBuf,
+    /// The mode to give it, regardless of its mode on the host
+    pub mode: Option<u32>,
+}
+
+/// Resolve `mappings` relative to `root`, expanding globs and directories into the files they
+/// contain.
+///
+/// Fails if a source doesn't exist, a glob matches nothing, or two files would be packaged at the
+/// same path.
+fn resolve_files(root: &Path, mappings: &[FileMapping]) -> Result<Vec<MappedFile>, Error> {
+    let mut files: Vec<MappedFile> = Vec::new();
+    for (i, mapping) in mappings.iter().enumerate() {
+        let dst = mapping.dst.trim_end_matches('/');
+        let sources = if mapping.is_glob() {
+            let pattern = format!(
+                "{}/{}",
+                glob::Pattern::escape(&root.to_string_lossy()),
+                mapping.src
+            );
+            let paths = glob::glob(&pattern).map_err(|e| {
+                Error::Package(format!(
+                    "files[{}].src = {:?} is not a valid glob: {}",
+                    i, mapping.src, e.msg
+                ))
+            })?;
+            let mut sources = Vec::new();
+            for path in paths {
+                let path = path.map_err(|e| Error::Io {
+                    path: e.path().to_owned(),
+                    source: e.into_error(),
+                })?;
+                let name = format!("{}/{}", dst, path.file_name().unwrap().to_string_lossy());
+                sources.push((path, name));
+            }
+            if sources.is_empty() {
+                return Err(Error::Package(format!(
+                    "files[{}].src = {:?} matches no files in {}",
+                    i,
+                    mapping.src,
+                    root.display()
+                )));
+            }
+            sources
+        } else {
+            let path = root.join(&mapping.src);
+            if !path.exists() {
+                return Err(Error::Package(format!(
+                    "files[{}].src = {:?} does not exist: expected to find {}",
+                    i,
+                    mapping.src,
+                    path.display()
+                )));
+            }
+            vec![(path, dst.to_string())]
+        };
+
+        for (path, name) in sources {
+            let found = if path.is_dir() {
+                files_in(&path)
+                    .with_path(&path)?
+                    .into_iter()
+                    .map(|file| {
+                        (
+                            path.join(&file),
+                            format!("{}/{}", name, archive_path(&file)),
+                        )
+                    })
+                    .collect()
+            } else {
+                vec![(path, name)]
+            };
+            for (path, name) in found {
+                if name.contains(' ') {
+                    return Err(Error::Package(format!(
+                        "{:?} can't be packaged since OTHERFILES doesn't permit spaces in file names",
+                        name
+                    )));
+                }
+                if files.iter().any(|file| file.name == name) {
+                    return Err(Error::Package(format!(
+                        "files would package two files as {:?}: map them to different paths",
+                        name
+                    )));
+                }
+                files.push(MappedFile {
+                    name,
+                    path,
+                    mode: mapping.mode,
+                });
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// List the files within `dir` recursively, relative to `dir` and in a stable order.
+///
+/// Hidden files are included. Directories are not listed themselves, so empty directories are
+/// effectively skipped.
+pub(crate) fn files_in(dir: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    fn visit(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<(), std::io::Error> {
+        let mut entries = std::fs::read_dir(root.join(relative))?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+
+        for name in entries {
+            let relative = relative.join(name);
+            if root.join(&relative).is_dir() {
+                visit(root, &relative, files)?;
+            } else {
+                files.push(relative);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    visit(dir, Path::new(""), &mut files)?;
+    Ok(files)
+}
+
+/// Format `path` the way it's named inside the archive, i.e. with `/` separators.
+pub(crate) fn archive_path(path: &Path) -> String {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// `name` made into a valid `app_name` as far as possible, by lowercasing it and replacing `-`
+/// with `_`, as for a name derived from the package's.
+pub(crate) fn normalized_app_name(name: &str) -> String {
+    name.to_ascii_lowercase().replace('-', "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `package.conf` of a package with `version`, and `[package.metadata.acap]` `metadata`.
+    fn package_dot_conf(version: &str, metadata: &str) -> Result<PackageDotConf, Error> {
+        let (_dir, package) = crate::testing::package(
+            &format!(
+                "[package]\nname = \"app\"\nversion = \"{}\"\n\n[package.metadata.acap]\n{}",
+                version, metadata
+            ),
+            &[],
+        );
+        PackageDotConf::from_cargo_package(&package, "app", None, Target::Mips)
+    }
+
+    /// The variables `conf` assigns in `package.conf`, as the device reads them.
+    fn assignments(conf: &PackageDotConf) -> Vec<(String, String)> {
+        shell_includes::parse(&conf.to_shell().unwrap()).unwrap()
+    }
+
+    fn value<'a>(assignments: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        assignments
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn versions(conf: &PackageDotConf) -> (i32, i32, Option<&str>) {
+        (
+            conf.app_major_version,
+            conf.app_minor_version,
+            conf.app_micro_version.as_deref(),
+        )
+    }
+
+    #[test]
+    fn versions_larger_than_i32_are_errors() {
+        for (version, part, field) in [
+            ("2147483648.0.0", "major", "APPMAJORVERSION"),
+            ("1.2147483648.0", "minor", "APPMINORVERSION"),
+            ("18446744073709551615.0.0", "major", "APPMAJORVERSION"),
+        ] {
+            match package_dot_conf(version, "") {
+                Err(Error::Package(message)) => {
+                    assert!(message.contains(part), "{}: {}", version, message);
+                    assert!(message.contains(field), "{}: {}", version, message);
+                }
+                other => panic!("{}: expected a package error, got {:?}", version, other),
+            }
+        }
+
+        let conf = package_dot_conf("2147483647.2147483647.0", "").unwrap();
+        assert_eq!(versions(&conf), (i32::MAX, i32::MAX, Some("0")));
+    }
+
+    #[test]
+    fn pre_release_versions_follow_micro_version_style() {
+        for (style, expected) in [
+            ("", "3-rc.1+build5"),
+            ("micro_version_style = \"full\"", "3-rc.1+build5"),
+            ("micro_version_style = \"patch-only\"", "3"),
+            ("micro_version_style = \"sanitized\"", "3_rc.1_build5"),
+        ] {
+            let conf = package_dot_conf("1.2.3-rc.1+build5", style).unwrap();
+            assert_eq!(versions(&conf), (1, 2, Some(expected)), "{}", style);
+        }
+
+        let conf = package_dot_conf("0.4.1", "micro_version_style = \"sanitized\"").unwrap();
+        assert_eq!(versions(&conf), (0, 4, Some("1")));
+    }
+
+    #[test]
+    fn metadata_version_overrides_the_package_version() {
+        let conf = package_dot_conf("3000000000.0.0", "version = \"4.5.6-beta\"").unwrap();
+        assert_eq!(versions(&conf), (4, 5, Some("6-beta")));
+    }
+
+    #[test]
+    fn html_escape_escapes_markup_and_quotes() {
+        assert_eq!(
+            html_escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+        assert_eq!(html_escape("&amp;"), "&amp;amp;");
+        assert_eq!(html_escape("plain ünïcode"), "plain ünïcode");
+    }
+
+    #[test]
+    fn vendor_homepage_link_escapes_the_vendor_and_url() {
+        let conf = package_dot_conf(
+            "1.0.0",
+            r#"vendor = "<Acme & \"Sons\">"
+vendor_homepage_url = "https://example.com/?a=1&b=\"<2>\""
+"#,
+        )
+        .unwrap();
+        let link = "<a href=\"https://example.com/?a=1&amp;b=&quot;&lt;2&gt;&quot;\">\
+                    &lt;Acme &amp; &quot;Sons&quot;&gt;</a>";
+        assert_eq!(conf.vendor_homepage_link.as_deref(), Some(link));
+
+        let assignments = assignments(&conf);
+        let value = |name| value(&assignments, name);
+        assert_eq!(value("VENDORHOMEPAGELINK"), Some(link));
+        assert_eq!(value("VENDOR"), Some("<Acme & \"Sons\">"));
+    }
+
+    #[test]
+    fn unix_user_and_group_are_written_as_appusr_and_appgrp() {
+        for (metadata, user, group) in [
+            ("", "sdk", "sdk"),
+            ("unix_user = \"root\"\n", "root", "sdk"),
+            (
+                "unix_user = \"root\"\nunix_group = \"root\"\n",
+                "root",
+                "root",
+            ),
+        ] {
+            let conf = package_dot_conf("1.0.0", metadata).unwrap();
+            let assignments = assignments(&conf);
+            let value = |name| value(&assignments, name);
+            assert_eq!(value("APPUSR"), Some(user), "{}", metadata);
+            assert_eq!(value("APPGRP"), Some(group), "{}", metadata);
+        }
+    }
+}
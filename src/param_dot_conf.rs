@@ -0,0 +1,100 @@
+use crate::cargo_config::CargoAcapMetadata;
+use crate::error::{Error, IoResultExt};
+use crate::shell_includes;
+use serde::Deserialize;
+use std::fmt;
+
+/// An application parameter, exposed by the Axis product's parameter API.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Parameter {
+    /// The parameter's name, which may contain only ASCII letters and digits.
+    pub name: String,
+
+    /// The parameter's value when the application is first installed.
+    pub default: String,
+
+    /// A hint for how the product's web pages should present the parameter, e.g. `"bool:no,yes"`
+    /// or `"int:min=0,max=100"`.
+    #[serde(rename = "type")]
+    pub param_type: Option<String>,
+}
+
+/// The `param.conf` declaring an application's parameters.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum ParamDotConf {
+    /// Generated from `parameters` in `[package.metadata.acap]`
+    Generated(Vec<Parameter>),
+    /// Copied from `param.conf` in the package root
+    Verbatim(String),
+}
+
+impl ParamDotConf {
+    /// Resolve the `param.conf` for `package`, if it has any parameters.
+    pub fn from_cargo_package(package: &cargo::core::Package) -> Result<Option<Self>, Error> {
+        let parameters = CargoAcapMetadata::for_package(package)?.parameters;
+        let path = package.root().join("param.conf");
+
+        match (parameters, path.is_file()) {
+            (Some(_), true) => Err(Error::Package(format!(
+                "both [package.metadata.acap] parameters and {} are present: use one or the other",
+                path.display()
+            ))),
+            (Some(parameters), false) => {
+                for parameter in &parameters {
+                    if parameter.name.is_empty()
+                        || !parameter.name.chars().all(|c| c.is_ascii_alphanumeric())
+                        || !parameter
+                            .name
+                            .starts_with(|c: char| c.is_ascii_alphabetic())
+                    {
+                        return Err(Error::Package(format!(
+                            "parameter name {:?} must start with a letter and contain only ASCII letters and digits",
+                            parameter.name
+                        )));
+                    }
+                    if parameters
+                        .iter()
+                        .filter(|p| p.name == parameter.name)
+                        .count()
+                        > 1
+                    {
+                        return Err(Error::Package(format!(
+                            "parameter {:?} is declared more than once",
+                            parameter.name
+                        )));
+                    }
+                }
+                Ok(Some(ParamDotConf::Generated(parameters)))
+            }
+            (None, true) => {
+                let contents = std::fs::read_to_string(&path).with_path(&path)?;
+                Ok(Some(ParamDotConf::Verbatim(contents)))
+            }
+            (None, false) => Ok(None),
+        }
+    }
+}
+
+impl fmt::Display for ParamDotConf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamDotConf::Generated(parameters) => {
+                for parameter in parameters {
+                    write!(
+                        f,
+                        "{}={}",
+                        parameter.name,
+                        shell_includes::quote(&parameter.default)
+                    )?;
+                    if let Some(param_type) = parameter.param_type.as_ref() {
+                        write!(f, " type={}", shell_includes::quote(param_type))?;
+                    }
+                    writeln!(f)?;
+                }
+                Ok(())
+            }
+            ParamDotConf::Verbatim(contents) => f.write_str(contents),
+        }
+    }
+}
@@ -4,7 +4,10 @@ struct Serializer {
     output: String,
 }
 
-pub fn to_string<T>(value: &T) -> String
+/// Serialize `value`, a struct, as `KEY='value'` lines to be sourced by a shell.
+///
+/// Fails if a value can't be represented in a shell string, i.e. if it contains a NUL.
+pub fn to_string<T>(value: &T) -> Result<String, SerializeError>
 where
     T: Serialize,
 {
@@ -12,31 +15,137 @@ where
         output: String::new(),
     };
 
-    value.serialize(&mut serializer).unwrap();
+    value.serialize(&mut serializer)?;
 
-    serializer.output
+    Ok(serializer.output)
 }
 
+/// Parse `KEY=value` assignments as written by `to_string()`, or by hand with the usual shell
+/// quoting, e.g. a `package.conf` from another tool.
+///
+/// This understands single quotes, double quotes, and backslashes, but not expansions, which
+/// are kept as they are.
+pub fn parse(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut assignments = Vec::new();
+    let mut chars = contents.chars().peekable();
+    loop {
+        // Skip blank lines and comments between assignments
+        match chars.peek() {
+            None => break,
+            Some(c) if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            Some('#') => {
+                chars.by_ref().find(|&c| c == '\n');
+                continue;
+            }
+            Some(_) => {}
+        }
+
+        let mut key = String::new();
+        for c in chars.by_ref() {
+            match c {
+                '=' => break,
+                c if c.is_ascii_alphanumeric() || c == '_' => key.push(c),
+                c => return Err(format!("unexpected {:?} in the name {:?}", c, key)),
+            }
+        }
+
+        let mut value = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            chars.next();
+            match c {
+                '\'' => loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => value.push(c),
+                        None => return Err(format!("unterminated ' in {}", key)),
+                    }
+                },
+                '"' => loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`' | '!')) => value.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                value.push('\\');
+                                value.push(c);
+                            }
+                            None => return Err(format!("unterminated \" in {}", key)),
+                        },
+                        Some(c) => value.push(c),
+                        None => return Err(format!("unterminated \" in {}", key)),
+                    }
+                },
+                '\\' => match chars.next() {
+                    Some('\n') | None => {}
+                    Some(c) => value.push(c),
+                },
+                c => value.push(c),
+            }
+        }
+        assignments.push((key, value));
+    }
+    Ok(assignments)
+}
+
+/// Quote `value` as a single-quoted shell string, in which nothing is special except `'`.
+///
+/// Returns `None` if `value` contains a NUL, which no shell string can.
+pub fn single_quote(value: &str) -> Option<String> {
+    if value.contains('\0') {
+        return None;
+    }
+    Some(format!("'{}'", value.replace('\'', r"'\''")))
+}
+
+/// Quote `value` as a double-quoted shell string.
+pub fn quote(value: &str) -> String {
+    let mut output = String::with_capacity(value.len() + 2);
+    output.push('"');
+    for ch in value.chars() {
+        match ch {
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\0' => output.push_str("\\0"),
+            '\t' => output.push_str("\\t"),
+            '"' | '!' | '$' | '`' | '\\' => {
+                output.push('\\');
+                output.push(ch);
+            }
+            other => output.push(other),
+        }
+    }
+    output.push('"');
+    output
+}
+
+/// Why a value couldn't be serialized.
 #[derive(Debug)]
-struct Err;
-impl ser::Error for Err {
-    fn custom<T>(_msg: T) -> Self
+pub struct SerializeError(String);
+impl ser::Error for SerializeError {
+    fn custom<T>(msg: T) -> Self
     where
         T: std::fmt::Display,
     {
-        unimplemented!()
+        SerializeError(msg.to_string())
     }
 }
-impl std::fmt::Display for Err {
-    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        unimplemented!()
+impl std::fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
     }
 }
-impl std::error::Error for Err {}
+impl std::error::Error for SerializeError {}
 
-impl<'a> ser::Serializer for &'a mut Serializer {
+impl ser::Serializer for &mut Serializer {
     type Ok = ();
-    type Error = Err;
+    type Error = SerializeError;
     type SerializeSeq = Self;
     type SerializeTuple = Self;
     type SerializeTupleStruct = Self;
@@ -95,22 +204,13 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
-        self.output.reserve(v.len() + 2);
-        self.output.push('"');
-        for ch in v.chars() {
-            match ch {
-                '\n' => self.output.push_str("\\n"),
-                '\r' => self.output.push_str("\\r"),
-                '\0' => self.output.push_str("\\0"),
-                '\t' => self.output.push_str("\\t"),
-                '"' | '!' | '$' => {
-                    self.output.push('\\');
-                    self.output.push(ch);
-                }
-                other => self.output.push(other),
-            }
-        }
-        self.output.push('"');
+        let quoted = single_quote(v).ok_or_else(|| {
+            SerializeError(format!(
+                "{:?} contains a NUL, which can't be represented",
+                v
+            ))
+        })?;
+        self.output.push_str(&quoted);
         Ok(())
     }
 
@@ -122,9 +222,9 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         self.serialize_str("")
     }
 
-    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error>
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(self)
     }
@@ -146,18 +246,18 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         self.serialize_str(variant)
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(
+    fn serialize_newtype_struct<T>(
         self,
         _name: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(self)
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         _variant_index: u32,
@@ -165,7 +265,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         _value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         unimplemented!()
     }
@@ -218,21 +318,21 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         unimplemented!()
     }
 
-    fn collect_str<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error>
+    fn collect_str<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
     where
-        T: std::fmt::Display,
+        T: ?Sized + std::fmt::Display,
     {
         self.serialize_str(&value.to_string())
     }
 }
 
-impl<'a> ser::SerializeSeq for &'a mut Serializer {
+impl ser::SerializeSeq for &mut Serializer {
     type Ok = ();
-    type Error = Err;
+    type Error = SerializeError;
 
-    fn serialize_element<T: ?Sized>(&mut self, _value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, _value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         unimplemented!()
     }
@@ -242,13 +342,13 @@ impl<'a> ser::SerializeSeq for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeTuple for &'a mut Serializer {
+impl ser::SerializeTuple for &mut Serializer {
     type Ok = ();
-    type Error = Err;
+    type Error = SerializeError;
 
-    fn serialize_element<T: ?Sized>(&mut self, _value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, _value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         unimplemented!()
     }
@@ -258,13 +358,13 @@ impl<'a> ser::SerializeTuple for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
+impl ser::SerializeTupleStruct for &mut Serializer {
     type Ok = ();
-    type Error = Err;
+    type Error = SerializeError;
 
-    fn serialize_field<T: ?Sized>(&mut self, _value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, _value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         unimplemented!()
     }
@@ -274,13 +374,13 @@ impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
+impl ser::SerializeTupleVariant for &mut Serializer {
     type Ok = ();
-    type Error = Err;
+    type Error = SerializeError;
 
-    fn serialize_field<T: ?Sized>(&mut self, _value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, _value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         unimplemented!()
     }
@@ -290,20 +390,20 @@ impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeMap for &'a mut Serializer {
+impl ser::SerializeMap for &mut Serializer {
     type Ok = ();
-    type Error = Err;
+    type Error = SerializeError;
 
-    fn serialize_key<T: ?Sized>(&mut self, _key: &T) -> Result<(), Self::Error>
+    fn serialize_key<T>(&mut self, _key: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         unimplemented!()
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, _value: &T) -> Result<(), Self::Error>
+    fn serialize_value<T>(&mut self, _value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         unimplemented!()
     }
@@ -313,21 +413,19 @@ impl<'a> ser::SerializeMap for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeStruct for &'a mut Serializer {
+impl ser::SerializeStruct for &mut Serializer {
     type Ok = ();
-    type Error = Err;
+    type Error = SerializeError;
 
-    fn serialize_field<T: ?Sized>(
-        &mut self,
-        key: &'static str,
-        value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         self.output += key;
         self.output.push('=');
-        value.serialize(&mut **self)?;
+        value
+            .serialize(&mut **self)
+            .map_err(|SerializeError(e)| SerializeError(format!("{}: {}", key, e)))?;
         self.output.push('\n');
         Ok(())
     }
@@ -337,17 +435,13 @@ impl<'a> ser::SerializeStruct for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
+impl ser::SerializeStructVariant for &mut Serializer {
     type Ok = ();
-    type Error = Err;
+    type Error = SerializeError;
 
-    fn serialize_field<T: ?Sized>(
-        &mut self,
-        _key: &'static str,
-        _value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, _key: &'static str, _value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         unimplemented!()
     }
@@ -356,3 +450,90 @@ impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
         unimplemented!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Assignments<'a> {
+        #[serde(rename = "DISPLAYNAME")]
+        display_name: &'a str,
+        #[serde(rename = "MENUNAME")]
+        menu_name: Option<&'a str>,
+    }
+
+    const AWKWARD: &[&str] = &[
+        "it's",
+        "say \"hi\"",
+        "$HOME ${PATH}",
+        "`id` $(id)",
+        "line\nbreak",
+        "back\\slash",
+        "Kamera Åäö 日本",
+        "",
+    ];
+
+    #[test]
+    fn values_round_trip_through_parse() {
+        for value in AWKWARD {
+            let shell = to_string(&Assignments {
+                display_name: value,
+                menu_name: None,
+            })
+            .unwrap();
+            assert_eq!(
+                parse(&shell).unwrap(),
+                [
+                    ("DISPLAYNAME".to_string(), value.to_string()),
+                    ("MENUNAME".to_string(), String::new()),
+                ],
+                "{}",
+                shell
+            );
+        }
+    }
+
+    #[test]
+    fn double_quoted_values_round_trip_through_parse() {
+        for value in AWKWARD.iter().filter(|value| !value.contains('\n')) {
+            let shell = format!("VALUE={}\n", quote(value));
+            assert_eq!(
+                parse(&shell).unwrap(),
+                [("VALUE".to_string(), value.to_string())],
+                "{}",
+                shell
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn values_are_not_expanded_when_sourced() {
+        for value in AWKWARD {
+            let shell = to_string(&Assignments {
+                display_name: value,
+                menu_name: Some("menu"),
+            })
+            .unwrap();
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("{}printf %s \"$DISPLAYNAME\"", shell))
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}", shell);
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), *value);
+        }
+    }
+
+    #[test]
+    fn values_containing_nul_are_rejected() {
+        let e = to_string(&Assignments {
+            display_name: "a\0b",
+            menu_name: None,
+        })
+        .unwrap_err();
+        assert!(e.to_string().contains("NUL"), "{}", e);
+        assert_eq!(single_quote("a\0b"), None);
+    }
+}
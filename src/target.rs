@@ -1,11 +1,12 @@
 use clap::Parser;
 use serde::de::Deserializer;
+use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use std::error::Error;
 use std::str::FromStr;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Parser)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Parser)]
 pub enum Target {
     Aarch64,
     Armv5tej,
@@ -27,6 +28,27 @@ impl Target {
         ]
     }
 
+    /// The names of all targets, in the order of `all()`.
+    pub fn all_names() -> impl Iterator<Item = &'static str> {
+        Target::all().iter().map(Target::name)
+    }
+
+    /// The SoCs whose products use this target, per `SOC::architecture()`.
+    pub fn socs(&self) -> impl Iterator<Item = SOC> + '_ {
+        SOC::all()
+            .iter()
+            .filter(move |soc| soc.architecture() == Ok(*self))
+            .copied()
+    }
+
+    /// The target whose Rust target triple is `triple`, if any.
+    pub fn from_triple(triple: &str) -> Option<Target> {
+        Target::all()
+            .iter()
+            .find(|target| target.rust_target_triple() == triple)
+            .copied()
+    }
+
     pub fn name(&self) -> &'static str {
         match self {
             Target::Aarch64 => "aarch64",
@@ -49,6 +71,36 @@ impl Target {
         }
     }
 
+    /// The `e_machine` of this target's ELF executables.
+    pub fn elf_machine(&self) -> u16 {
+        match self {
+            Target::Aarch64 => 183, // EM_AARCH64
+            Target::Armv5tej | Target::Armv6 | Target::Armv7 | Target::Armv7Hf => 40, // EM_ARM
+            Target::Mips => 8,      // EM_MIPS
+        }
+    }
+
+    /// Whether this target's ELF executables are 64-bit.
+    pub fn elf_is_64_bit(&self) -> bool {
+        matches!(self, Target::Aarch64)
+    }
+
+    /// The runner with which `cargo test` can run this target's executables in the Docker image,
+    /// by QEMU user emulation with the libraries they link against.
+    ///
+    /// `Mips` has none, since the image's MIPS toolchain keeps its libraries in an SDK-specific
+    /// sysroot.
+    pub fn qemu_runner(&self) -> Option<&'static str> {
+        match self {
+            Target::Aarch64 => Some("qemu-aarch64 -L /usr/aarch64-linux-gnu"),
+            Target::Armv5tej | Target::Armv6 | Target::Armv7 => {
+                Some("qemu-arm -L /usr/arm-linux-gnueabi")
+            }
+            Target::Armv7Hf => Some("qemu-arm -L /usr/arm-linux-gnueabihf"),
+            Target::Mips => None,
+        }
+    }
+
     pub fn docker_objcopy_command(&self) -> &'static str {
         match self {
             Target::Aarch64 => "aarch64-linux-gnu-objcopy",
@@ -97,6 +149,23 @@ pub enum Architecture {
     Mips,
 }
 
+impl Architecture {
+    /// The architecture named by a device's `root.Properties.System.Architecture` parameter.
+    pub fn from_vapix(name: &str) -> Option<Architecture> {
+        Some(match name.trim().to_ascii_lowercase().as_str() {
+            "aarch64" | "arm64" => Architecture::Aarch64,
+            "armv5tej" | "armv5te" => Architecture::Armv5tej,
+            "armv6" => Architecture::Armv6,
+            "armv7" => Architecture::Armv7,
+            "armv7hf" => Architecture::Armv7Hf,
+            "cris" | "crisv0" | "crisv10" => Architecture::CrisV0,
+            "crisv32" => Architecture::CrisV32,
+            "mips" | "mipsel" => Architecture::Mips,
+            _ => return None,
+        })
+    }
+}
+
 impl From<Target> for Architecture {
     fn from(t: Target) -> Architecture {
         match t {
@@ -135,12 +204,39 @@ impl std::fmt::Display for Target {
 impl std::str::FromStr for Target {
     type Err = NoSuchTargetError;
 
+    /// Parse a target name or Rust target triple, or the name of an SoC to use its target.
+    ///
+    /// Matching ignores case, and SoC names also ignore punctuation and the vendor, so `artpec7`,
+    /// `ARTPEC-7`, and `Axis ARTPEC-7` are all `armv7hf`.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Target::all()
+        let lowercase = s.to_ascii_lowercase();
+        if let Some(target) = Target::all()
             .iter()
-            .find(|arch| arch.name() == s || arch.rust_target_triple() == s)
+            .find(|target| target.name() == lowercase)
             .copied()
-            .ok_or_else(|| NoSuchTargetError(s.into()))
+            .or_else(|| Target::from_triple(&lowercase))
+        {
+            return Ok(target);
+        }
+
+        let no_such_target = |reason| NoSuchTargetError {
+            name: s.into(),
+            reason,
+        };
+        match SOC::from_alias(s) {
+            Some(soc) => soc.architecture().map_err(|e| no_such_target(Some(e))),
+            None => Err(no_such_target(None)),
+        }
+    }
+}
+
+/// Targets serialize as their `name()`, which deserializes back to the same target.
+impl Serialize for Target {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.name())
     }
 }
 
@@ -155,15 +251,27 @@ impl<'de> Deserialize<'de> for Target {
 }
 
 #[derive(Debug, Clone)]
-pub struct NoSuchTargetError(String);
+pub struct NoSuchTargetError {
+    name: String,
+    /// Why the SoC of this name has no target, if it is one
+    reason: Option<&'static str>,
+}
 
 impl Error for NoSuchTargetError {}
 
 impl std::fmt::Display for NoSuchTargetError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "no such target: {}\nexpected one of:\n", &self.0)?;
-        for arch in Target::all() {
-            write!(f, "  * {}", arch.name())?;
+        write!(f, "no such target: {}", &self.name)?;
+        if let Some(reason) = self.reason {
+            write!(f, " ({})", reason)?;
+        }
+        writeln!(f, "\nexpected one of:")?;
+        for name in Target::all_names() {
+            writeln!(f, "  * {}", name)?;
+        }
+        write!(f, "or an SoC:")?;
+        for soc in SOC::all().iter().filter(|soc| soc.architecture().is_ok()) {
+            write!(f, " {}", soc.alias())?;
         }
         Ok(())
     }
@@ -175,6 +283,7 @@ impl std::fmt::Display for NoSuchTargetError {
 /// AXIS will use additional SOCs in the future.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
 #[non_exhaustive]
+#[allow(clippy::upper_case_acronyms)]
 pub enum SOC {
     Artpec1,
     Artpec2,
@@ -183,7 +292,10 @@ pub enum SOC {
     Artpec5,
     Artpec6,
     Artpec7,
+    Artpec8,
+    Artpec9,
     A5S,
+    CV25,
     Hi3516cV300,
     Hi3719cV100,
     MX8QP,
@@ -193,6 +305,7 @@ pub enum SOC {
     S3L,
     S5,
     S5L,
+    S6LM,
 }
 
 impl SOC {
@@ -206,7 +319,10 @@ impl SOC {
             SOC::Artpec5,
             SOC::Artpec6,
             SOC::Artpec7,
+            SOC::Artpec8,
+            SOC::Artpec9,
             SOC::A5S,
+            SOC::CV25,
             SOC::Hi3516cV300,
             SOC::Hi3719cV100,
             SOC::MX8QP,
@@ -216,6 +332,7 @@ impl SOC {
             SOC::S3L,
             SOC::S5,
             SOC::S5L,
+            SOC::S6LM,
         ]
     }
 
@@ -229,7 +346,10 @@ impl SOC {
             SOC::Artpec5 => "Axis ARTPEC-5",
             SOC::Artpec6 => "Axis ARTPEC-6",
             SOC::Artpec7 => "Axis ARTPEC-7",
+            SOC::Artpec8 => "Axis ARTPEC-8",
+            SOC::Artpec9 => "Axis ARTPEC-9",
             SOC::A5S => "Ambarella A5S",
+            SOC::CV25 => "Ambarella CV25",
             SOC::Hi3516cV300 => "Hi3516C V300",
             SOC::Hi3719cV100 => "Hi3719C V100",
             SOC::MX8QP => "NXP i.MX 8 QP",
@@ -239,9 +359,53 @@ impl SOC {
             SOC::S3L => "Ambarella S3L",
             SOC::S5 => "Ambarella S5",
             SOC::S5L => "Ambarella S5L",
+            SOC::S6LM => "Ambarella S6LM",
         }
     }
 
+    /// The short name by which `--targets` accepts this SoC, e.g. `artpec7`.
+    pub fn alias(&self) -> &'static str {
+        match self {
+            SOC::Artpec1 => "artpec1",
+            SOC::Artpec2 => "artpec2",
+            SOC::Artpec3 => "artpec3",
+            SOC::Artpec4 => "artpec4",
+            SOC::Artpec5 => "artpec5",
+            SOC::Artpec6 => "artpec6",
+            SOC::Artpec7 => "artpec7",
+            SOC::Artpec8 => "artpec8",
+            SOC::Artpec9 => "artpec9",
+            SOC::A5S => "a5s",
+            SOC::CV25 => "cv25",
+            SOC::Hi3516cV300 => "hi3516cv300",
+            SOC::Hi3719cV100 => "hi3719cv100",
+            SOC::MX8QP => "imx8qp",
+            SOC::S2 => "s2",
+            SOC::S2E => "s2e",
+            SOC::S2L => "s2l",
+            SOC::S3L => "s3l",
+            SOC::S5 => "s5",
+            SOC::S5L => "s5l",
+            SOC::S6LM => "s6lm",
+        }
+    }
+
+    /// Find the SoC named `name`, ignoring case, punctuation, and spaces, with or without its
+    /// vendor, e.g. `ARTPEC-7` or `Ambarella S5L`.
+    pub fn from_alias(name: &str) -> Option<SOC> {
+        let simplify = |s: &str| -> String {
+            s.chars()
+                .filter(char::is_ascii_alphanumeric)
+                .map(|c| c.to_ascii_lowercase())
+                .collect()
+        };
+        let name = simplify(name);
+        SOC::all()
+            .iter()
+            .find(|soc| soc.alias() == name || simplify(soc.display_name()) == name)
+            .copied()
+    }
+
     /// The year when this SoC was released.
     pub fn year(&self) -> u32 {
         match self {
@@ -252,13 +416,17 @@ impl SOC {
             SOC::Artpec5 => 2013,
             SOC::Artpec6 => 2017,
             SOC::Artpec7 => 2019,
+            SOC::Artpec8 => 2021,
+            SOC::Artpec9 => 2024,
             SOC::A5S => 2010,
+            SOC::CV25 => 2019,
             SOC::Hi3516cV300 => 2016, //?
             SOC::Hi3719cV100 => 2016, //?
             SOC::MX8QP => 2013,
             SOC::S2 | SOC::S2E | SOC::S2L => 2012,
             SOC::S3L => 2014,
             SOC::S5 | SOC::S5L => 2016,
+            SOC::S6LM => 2019,
         }
     }
 
@@ -275,16 +443,30 @@ impl SOC {
             }
             SOC::Artpec4 | SOC::Artpec5 => Target::Mips,
             SOC::Artpec6 | SOC::Artpec7 => Target::Armv7Hf,
+            SOC::Artpec8 | SOC::Artpec9 => Target::Aarch64,
             SOC::A5S => Target::Armv6,
+            SOC::CV25 => Target::Aarch64,
             SOC::Hi3516cV300 => Target::Armv5tej,
             SOC::Hi3719cV100 => Target::Armv7Hf,
             SOC::MX8QP => Target::Aarch64,
             SOC::S2 => Target::Armv7,
             SOC::S2E | SOC::S2L => Target::Armv7Hf,
             SOC::S3L => Target::Armv7Hf,
-            SOC::S5 | SOC::S5L => Target::Aarch64,
+            SOC::S5 | SOC::S5L | SOC::S6LM => Target::Aarch64,
         })
     }
+
+    /// The architecture of this SoC's processor, whether or not `cargo acap` supports it.
+    pub fn cpu_architecture(&self) -> Architecture {
+        match self {
+            SOC::Artpec1 | SOC::Artpec2 => Architecture::CrisV0,
+            SOC::Artpec3 => Architecture::CrisV32,
+            soc => soc
+                .architecture()
+                .expect("every other SoC has a supported target")
+                .into(),
+        }
+    }
 }
 
 impl std::fmt::Display for SOC {
@@ -292,3 +474,118 @@ impl std::fmt::Display for SOC {
         f.write_str(self.display_name())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_socs_use_their_targets() {
+        for (soc, target) in [
+            (SOC::Artpec6, Target::Armv7Hf),
+            (SOC::Artpec7, Target::Armv7Hf),
+            (SOC::Artpec8, Target::Aarch64),
+            (SOC::Artpec9, Target::Aarch64),
+            (SOC::CV25, Target::Aarch64),
+            (SOC::S5L, Target::Aarch64),
+            (SOC::S6LM, Target::Aarch64),
+        ] {
+            assert_eq!(soc.architecture(), Ok(target), "{}", soc);
+            assert!(target.socs().any(|s| s == soc), "{}", soc);
+        }
+        assert_eq!("ARTPEC-8".parse::<Target>().unwrap(), Target::Aarch64);
+        assert_eq!("Ambarella CV25".parse::<Target>().unwrap(), Target::Aarch64);
+    }
+
+    #[test]
+    fn every_soc_but_cris_ones_maps_to_a_supported_target() {
+        for soc in SOC::all() {
+            match soc.architecture() {
+                Ok(target) => {
+                    assert!(Target::all().contains(&target), "{}", soc);
+                    assert_eq!(
+                        soc.cpu_architecture(),
+                        Architecture::from(target),
+                        "{}",
+                        soc
+                    );
+                }
+                Err(_) => {
+                    assert!(
+                        matches!(soc, SOC::Artpec1 | SOC::Artpec2 | SOC::Artpec3),
+                        "{}",
+                        soc
+                    );
+                    assert!(Target::try_from(soc.cpu_architecture()).is_err(), "{}", soc);
+                    assert!(soc.alias().parse::<Target>().is_err(), "{}", soc);
+                }
+            }
+        }
+
+        // Every SoC with a target is listed by it, once
+        let listed: usize = Target::all().iter().map(|t| t.socs().count()).sum();
+        let supported = SOC::all().iter().filter(|soc| soc.architecture().is_ok());
+        assert_eq!(listed, supported.count());
+    }
+
+    #[test]
+    fn socs_round_trip_through_their_names() {
+        for &soc in SOC::all() {
+            assert_eq!(SOC::from_alias(soc.alias()), Some(soc));
+            assert_eq!(SOC::from_alias(soc.display_name()), Some(soc));
+            assert_eq!(SOC::from_alias(&soc.to_string()), Some(soc));
+            assert_eq!(
+                SOC::from_alias(&soc.display_name().to_ascii_uppercase()),
+                Some(soc)
+            );
+
+            let json = serde_json::to_string(&soc).unwrap();
+            assert_eq!(serde_json::from_str::<SOC>(&json).unwrap(), soc);
+        }
+        assert_eq!(SOC::from_alias("artpec-8"), Some(SOC::Artpec8));
+        assert_eq!(SOC::from_alias("i.MX 8 QP"), Some(SOC::MX8QP));
+        assert_eq!(SOC::from_alias("artpec10"), None);
+    }
+
+    #[test]
+    fn targets_round_trip_through_their_names() {
+        for &target in Target::all() {
+            assert_eq!(target.name().parse::<Target>().unwrap(), target);
+            assert_eq!(target.to_string(), target.name());
+            assert_eq!(
+                target
+                    .name()
+                    .to_ascii_uppercase()
+                    .parse::<Target>()
+                    .unwrap(),
+                target
+            );
+            assert_eq!(
+                target.rust_target_triple().parse::<Target>().unwrap(),
+                target
+            );
+            assert_eq!(
+                Target::from_triple(target.rust_target_triple()),
+                Some(target)
+            );
+
+            let json = serde_json::to_string(&target).unwrap();
+            assert_eq!(json, format!("\"{}\"", target.name()));
+            assert_eq!(serde_json::from_str::<Target>(&json).unwrap(), target);
+        }
+        assert_eq!(
+            Target::all_names().collect::<Vec<_>>(),
+            ["aarch64", "armv5tej", "armv6", "armv7", "armv7hf", "mips"]
+        );
+    }
+
+    #[test]
+    fn unknown_targets_are_errors() {
+        for name in ["", "x86_64", "mips-axis-linux-gnu", "artpec3"] {
+            assert!(name.parse::<Target>().is_err(), "{:?}", name);
+            assert!(serde_json::from_str::<Target>(&format!("{:?}", name)).is_err());
+        }
+        let message = "artpec3".parse::<Target>().unwrap_err().to_string();
+        assert!(message.contains("CrisV32"), "{}", message);
+    }
+}
@@ -0,0 +1,29 @@
+use std::path::Path;
+use tempfile::TempDir;
+
+/// A package whose `Cargo.toml` is `manifest`, with `files` at their paths relative to its root,
+/// loaded as `cargo` would.
+///
+/// The package lives in a temporary directory, which is removed when the returned `TempDir` is
+/// dropped. It gets an empty `src/main.rs` unless `files` gives one.
+pub fn package(manifest: &str, files: &[(&str, &str)]) -> (TempDir, cargo::core::Package) {
+    let dir = tempfile::tempdir().expect("error creating temporary directory");
+    write(dir.path(), "Cargo.toml", manifest);
+    write(dir.path(), "src/main.rs", "fn main() {}\n");
+    for (path, contents) in files {
+        write(dir.path(), path, contents);
+    }
+
+    let config = cargo::Config::default().expect("error configuring cargo");
+    let package = cargo::core::Workspace::new(&dir.path().join("Cargo.toml"), &config)
+        .and_then(|workspace| workspace.current().cloned())
+        .expect("error loading package");
+    (dir, package)
+}
+
+/// Write `contents` to `path` within `root`, creating its directories.
+pub fn write(root: &Path, path: &str, contents: &str) {
+    let path = root.join(path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+}
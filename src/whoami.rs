@@ -15,12 +15,15 @@ pub fn whoami() -> Whoami {
 
 #[cfg(not(windows))]
 mod unix {
-    pub use std::ffi::CStr;
-    pub use std::mem::MaybeUninit;
+    use std::ffi::CStr;
+    use std::mem::MaybeUninit;
     use std::os::raw::c_char;
 
-    // Safety: C getpwuid_r expects buffers to be uninitialized and the appropriate length
-    const STRING_BUF_LEN: usize = libc::_SC_GETPW_R_SIZE_MAX as usize;
+    /// The initial length of the buffer for getpwuid_r's strings, if sysconf doesn't suggest one
+    const DEFAULT_STRING_BUF_LEN: usize = 1024;
+
+    /// The longest buffer to try before giving up on a passwd entry
+    const MAX_STRING_BUF_LEN: usize = 1024 * 1024;
 
     /// A safe and easy to use zero cost wrapper for libc::passwd.
     /// Cannot be created outside this module because the inner field is private, so user can't make an invalid one.
@@ -29,29 +32,34 @@ mod unix {
     impl<'a> SafePasswd<'a> {
         /// calls libc::getpwuid_r but forces params to be correct and enforces the lifetime contract.
         /// user cant access/move/drop the buffers until return value is dropped.
+        ///
+        /// Returns the error number if the lookup failed, e.g. ERANGE if `string_buf` is too short,
+        /// or `Ok(None)` if `uid` has no entry.
         pub fn getpwuid_r(
-            uid: u32,
+            uid: libc::uid_t,
             pwd_buf: &'a mut MaybeUninit<libc::passwd>,
-            string_buf: &'a mut MaybeUninit<[c_char; STRING_BUF_LEN]>,
-        ) -> Option<SafePasswd<'a>> {
+            string_buf: &'a mut [c_char],
+        ) -> Result<Option<SafePasswd<'a>>, i32> {
             let mut pwd_ptr = MaybeUninit::uninit(); // uninitialized pointer to libc::passwd
             unsafe {
                 // Safety: getpwuid_r is reentrant and writes only to our buffers here
                 // Safety: pwd_buf is the libc-defined size and shape
-                // Safety: string_buf is the appropriate length and its length is passed
-                let _errno = libc::getpwuid_r(
+                // Safety: string_buf's length is passed along with it
+                let errno = libc::getpwuid_r(
                     uid,
-                    pwd_buf.as_mut_ptr(), // ptr to uninit struct
-                    string_buf.as_mut_ptr() as *mut c_char, // ptr to first byte of uninit c_char array
-                    STRING_BUF_LEN,
+                    pwd_buf.as_mut_ptr(),    // ptr to uninit struct
+                    string_buf.as_mut_ptr(), // ptr to first byte of c_char array
+                    string_buf.len(),
                     pwd_ptr.as_mut_ptr(), // ptr to uninit ptr to libc::passwd. *result is always written.
                 );
+                if errno != 0 {
+                    return Err(errno);
+                }
                 // Safety: by contract with getpwuid_r, pwd_ptr is now either null or a valid pointer to an initialized pwd_buf
                 // Safety: if pwd_ptr non-null, C string pointers point somewhere valid inside string_buf.
-                pwd_ptr.assume_init().as_ref()
+                Ok(pwd_ptr.assume_init().as_ref().map(SafePasswd))
             }
             // Safety: return value borrows both buffers that back it. User can't make it invalid without using unsafe.
-            .map(|c| SafePasswd(c))
         }
 
         /// safe access to pw_name
@@ -60,22 +68,106 @@ mod unix {
             unsafe { CStr::from_ptr(self.0.pw_name) }
         }
     }
+
+    /// The name of the user with `uid` in the passwd database, if it has an entry.
+    pub fn username(uid: libc::uid_t) -> Option<String> {
+        // _SC_GETPW_R_SIZE_MAX is only a suggestion, and may be -1 if there isn't one
+        let suggested = unsafe { libc::sysconf(libc::_SC_GETPW_R_SIZE_MAX) };
+        let mut len = if suggested > 0 {
+            suggested as usize
+        } else {
+            DEFAULT_STRING_BUF_LEN
+        };
+
+        loop {
+            let mut pwd_buf = MaybeUninit::uninit();
+            let mut string_buf = vec![0; len];
+            match SafePasswd::getpwuid_r(uid, &mut pwd_buf, &mut string_buf) {
+                Ok(pwd) => return pwd.map(|pwd| pwd.pw_name().to_string_lossy().into_owned()),
+                Err(libc::ERANGE) if len < MAX_STRING_BUF_LEN => len *= 2,
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+/// The username from the environment, as read by `var`, for users without a passwd entry, e.g.
+/// in minimal CI containers.
+#[cfg(not(windows))]
+fn username_from_env(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    ["USER", "LOGNAME"]
+        .iter()
+        .filter_map(|name| var(name))
+        .find(|username| !username.is_empty())
+}
+
+/// Who `uid` and `gid` are, named by `username` if it finds an entry for `uid`, or else by the
+/// environment as read by `var`.
+#[cfg(not(windows))]
+fn whoami_with(
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    username: impl FnOnce(libc::uid_t) -> Option<String>,
+    var: impl Fn(&str) -> Option<String>,
+) -> Whoami {
+    Whoami {
+        uid: uid as _,
+        gid: gid as _,
+        username: username(uid).or_else(|| username_from_env(var)),
+    }
 }
 
 #[cfg(not(windows))]
 pub fn whoami() -> Whoami {
     let uid = unsafe { libc::geteuid() };
     let gid = unsafe { libc::getegid() };
+    whoami_with(uid, gid, unix::username, |name| std::env::var(name).ok())
+}
 
-    use unix::*;
-    let mut pwd_buf = MaybeUninit::uninit();
-    let mut string_buf = MaybeUninit::uninit();
-    let opt_safe_pwd = SafePasswd::getpwuid_r(uid, &mut pwd_buf, &mut string_buf);
-    // pwd_buf and string_buf are now borrowed by opt_safe_pwd and we can't violate any contract.
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::*;
 
-    Whoami {
-        uid: uid as _,
-        gid: gid as _,
-        username: opt_safe_pwd.map(|pwd| pwd.pw_name().to_string_lossy().to_string()),
+    /// A uid which no passwd database gives an entry, being just short of `(uid_t)-1`.
+    const UNKNOWN_UID: libc::uid_t = libc::uid_t::MAX - 2;
+
+    fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn a_uid_without_a_passwd_entry_has_no_username() {
+        assert_eq!(unix::username(UNKNOWN_UID), None);
+    }
+
+    #[test]
+    fn a_uid_without_a_passwd_entry_is_named_by_the_environment() {
+        for (vars, expected) in [
+            (&[("USER", "ci"), ("LOGNAME", "login")][..], Some("ci")),
+            (&[("USER", ""), ("LOGNAME", "login")][..], Some("login")),
+            (&[("LOGNAME", "login")][..], Some("login")),
+            (&[("USER", ""), ("LOGNAME", "")][..], None),
+            (&[][..], None),
+        ] {
+            let whoami = whoami_with(UNKNOWN_UID, 1234, unix::username, env(vars));
+            assert_eq!(whoami.uid, UNKNOWN_UID);
+            assert_eq!(whoami.gid, 1234);
+            assert_eq!(whoami.username.as_deref(), expected, "{:?}", vars);
+        }
+    }
+
+    #[test]
+    fn a_passwd_entry_takes_precedence_over_the_environment() {
+        let whoami = whoami_with(
+            1000,
+            1000,
+            |uid| Some(format!("user{}", uid)),
+            env(&[("USER", "ci")]),
+        );
+        assert_eq!(whoami.username.as_deref(), Some("user1000"));
     }
 }